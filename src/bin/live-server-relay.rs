@@ -0,0 +1,49 @@
+//! Relay that workspace servers configured with `tunnel` connect out to, forwarding its visitors to them.
+//!
+//! ```sh
+//! live-server-relay --control 0.0.0.0:7000 --public 0.0.0.0:8000 --secret s3cret --url http://relay.example.com:8000
+//! ```
+
+use live_server_lsp::TunnelRelay;
+use tokio::net::TcpListener;
+
+const USAGE: &str =
+    "usage: live-server-relay [--control ADDR] [--public ADDR] [--secret SECRET] [--url URL]";
+
+#[tokio::main]
+async fn main() {
+    let mut control = "0.0.0.0:7000".to_string();
+    let mut public = "0.0.0.0:8000".to_string();
+    let mut secret = std::env::var("LIVE_SERVER_RELAY_SECRET").ok();
+    let mut url = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "--control" => &mut control,
+            "--public" => &mut public,
+            "--secret" => secret.insert(String::new()),
+            "--url" => url.insert(String::new()),
+            _ => exit(USAGE),
+        };
+        *value = args.next().unwrap_or_else(|| exit(USAGE));
+    }
+    let bind = |address: String| async move {
+        TcpListener::bind(&address)
+            .await
+            .unwrap_or_else(|e| exit(&format!("failed to bind {}: {}", address, e)))
+    };
+    let (control, public) = (bind(control).await, bind(public).await);
+    let url = url.or_else(|| {
+        let port = public.local_addr().ok()?.port();
+        Some(format!("http://localhost:{}", port))
+    });
+    if secret.is_none() {
+        eprintln!("no --secret given, any workspace may register a tunnel");
+    }
+    TunnelRelay::new(secret, url).run(control, public).await;
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(2)
+}
@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use tokio::process::Command;
+
+/// Browser previews are opened in instead of the system default
+pub struct Browser {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Browser {
+    /// Translates the profile and private mode into the flags of firefox or chromium based browsers
+    pub fn new(
+        program: String,
+        mut args: Vec<String>,
+        profile: Option<PathBuf>,
+        incognito: bool,
+    ) -> Self {
+        let firefox = program.to_ascii_lowercase().contains("firefox");
+        if let Some(profile) = profile {
+            match firefox {
+                true => args.extend(["-profile".to_string(), profile.display().to_string()]),
+                false => args.push(format!("--user-data-dir={}", profile.display())),
+            }
+        }
+        if incognito {
+            args.push(match firefox {
+                true => "--private-window".to_string(),
+                false => "--incognito".to_string(),
+            });
+        }
+        Self { program, args }
+    }
+
+    fn open(&self, url: &str) -> std::io::Result<()> {
+        Command::new(&self.program)
+            .args(&self.args)
+            .arg(url)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(|_| ())
+    }
+}
+
+/// Opens `url` in the configured browser, or the system default if there is none
+pub fn open(browser: Option<&Browser>, url: &str) -> std::io::Result<()> {
+    match browser {
+        Some(browser) => browser.open(url),
+        None => webbrowser::open(url),
+    }
+}
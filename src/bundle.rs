@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use dashmap::DashMap;
+
+use crate::hook;
+
+/// Entrypoints bundled by esbuild and served from memory, e.g. `/app.js` built from `src/main.ts`.
+///
+/// Bundles are built on the first request after one of their possible sources changed.
+pub struct Bundler {
+    command: Vec<String>,
+    /// Served path under the root and the entrypoint it is built from
+    entries: Vec<(PathBuf, PathBuf)>,
+    built: DashMap<PathBuf, Result<Vec<u8>, String>>,
+    /// Bumped by every invalidation, so a build racing a change isn't kept
+    generation: AtomicUsize,
+}
+
+impl Bundler {
+    /// `None` without entrypoints or a command, entrypoints are relative to `workspace`
+    pub fn new(
+        command: Vec<String>,
+        root: &Path,
+        workspace: &Path,
+        entries: &HashMap<String, PathBuf>,
+    ) -> Option<Self> {
+        if command.is_empty() || entries.is_empty() {
+            return None;
+        }
+        let entries = entries
+            .iter()
+            .map(|(url, entry)| {
+                (
+                    root.join(url.trim_start_matches('/')),
+                    workspace.join(entry),
+                )
+            })
+            .collect();
+        Some(Self {
+            command,
+            entries,
+            built: Default::default(),
+            generation: AtomicUsize::new(0),
+        })
+    }
+
+    /// Served paths of the bundles
+    pub fn outputs(&self) -> impl Iterator<Item = &Path> {
+        self.entries.iter().map(|(output, _)| output.as_path())
+    }
+
+    /// Drops the bundles if `file` may be one of their sources, returns whether it was
+    pub fn invalidate(&self, file: &Path) -> bool {
+        let source = matches!(
+            file.extension().and_then(|ext| ext.to_str()),
+            Some("js" | "mjs" | "cjs" | "jsx" | "ts" | "mts" | "cts" | "tsx" | "json")
+        );
+        if source {
+            self.generation.fetch_add(1, Ordering::Relaxed);
+            self.built.clear();
+        }
+        source
+    }
+
+    /// Bundle served at `path`, built if needed, `None` if it isn't a bundle
+    pub async fn bundle(&self, workspace: &Path, path: &Path) -> Option<Result<Vec<u8>, String>> {
+        let (output, entry) = self.entries.iter().find(|(output, _)| output == path)?;
+        if let Some(built) = self.built.get(output) {
+            return Some(built.clone());
+        }
+        let generation = self.generation.load(Ordering::Relaxed);
+        let mut command = self.command.clone();
+        command.extend([
+            entry.to_string_lossy().into_owned(),
+            "--bundle".to_string(),
+            "--format=esm".to_string(),
+            "--sourcemap=inline".to_string(),
+        ]);
+        let result = hook::pipe(&command, workspace, entry, &[]).await;
+        if self.generation.load(Ordering::Relaxed) == generation {
+            self.built.insert(output.clone(), result.clone());
+        }
+        Some(result)
+    }
+}
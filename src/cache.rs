@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Entries kept if `cache_entries` isn't set
+pub const DEFAULT_ENTRIES: usize = 256;
+/// Largest asset cached if `cache_max_size` isn't set
+pub const DEFAULT_MAX_SIZE: usize = 256 * 1024;
+
+/// Least recently used cache of small assets read from disk, like icons and fonts.
+///
+/// Entries are dropped when their file changes, see `invalidate`, and remember the modification time
+/// of the file they were read from, so changes that weren't reported are noticed too.
+pub struct AssetCache {
+    max_entries: usize,
+    max_size: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Content, modification time and the tick of the last use of every cached file
+    files: HashMap<PathBuf, (Vec<u8>, SystemTime, u64)>,
+    tick: u64,
+}
+
+impl AssetCache {
+    pub fn new(max_entries: usize, max_size: usize) -> Self {
+        Self {
+            max_entries,
+            max_size,
+            entries: Default::default(),
+        }
+    }
+
+    /// Content of `path` if it was cached from the file last modified at `modified`, stale entries are dropped
+    pub fn get(&self, path: &Path, modified: SystemTime) -> Option<Vec<u8>> {
+        let mut entries = self.entries.lock().ok()?;
+        entries.tick += 1;
+        let tick = entries.tick;
+        let (content, cached, used) = entries.files.get_mut(path)?;
+        if *cached != modified {
+            entries.files.remove(path);
+            return None;
+        }
+        *used = tick;
+        Some(content.clone())
+    }
+
+    /// Stores `content` read from the file last modified at `modified` unless it is too large,
+    /// evicting the least recently used entry if full
+    pub fn insert(&self, path: &Path, content: &[u8], modified: SystemTime) {
+        if self.max_entries == 0 || content.len() > self.max_size {
+            return;
+        }
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.files.len() >= self.max_entries && !entries.files.contains_key(path) {
+            let oldest = entries
+                .files
+                .iter()
+                .min_by_key(|(_, (_, _, used))| *used)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                entries.files.remove(&oldest);
+            }
+        }
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries
+            .files
+            .insert(path.to_path_buf(), (content.to_vec(), modified, tick));
+    }
+
+    /// Drops `path` and everything below it
+    pub fn invalidate(&self, path: &Path) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.files.retain(|file, _| !file.starts_with(path));
+        }
+    }
+
+    /// Drops every entry, for changes that weren't reported
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.files.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn drops_entries_of_modified_files() {
+        let cache = AssetCache::new(2, 16);
+        let read = SystemTime::UNIX_EPOCH;
+        cache.insert(Path::new("/icon.svg"), b"<svg/>", read);
+        assert_eq!(cache.get(Path::new("/icon.svg"), read).unwrap(), b"<svg/>");
+        assert!(cache
+            .get(Path::new("/icon.svg"), read + Duration::from_secs(1))
+            .is_none());
+        // the stale entry is gone, not just skipped
+        assert!(cache.get(Path::new("/icon.svg"), read).is_none());
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let cache = AssetCache::new(2, 16);
+        let read = SystemTime::UNIX_EPOCH;
+        cache.insert(Path::new("/a"), b"a", read);
+        cache.insert(Path::new("/b"), b"b", read);
+        cache.get(Path::new("/a"), read);
+        cache.insert(Path::new("/c"), b"c", read);
+        assert!(cache.get(Path::new("/a"), read).is_some());
+        assert!(cache.get(Path::new("/b"), read).is_none());
+        // too large to cache
+        cache.insert(Path::new("/d"), &[0; 17], read);
+        assert!(cache.get(Path::new("/d"), read).is_none());
+    }
+}
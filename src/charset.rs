@@ -0,0 +1,80 @@
+/// Html is scanned this far for a charset declaration, like browsers do
+const PRESCAN: usize = 1024;
+
+/// Marks html that is utf-8, like open buffers and rendered templates, as utf-8.
+///
+/// Without this a buffer of a Shift-JIS or Latin-1 file, which the editor sends as utf-8,
+/// would be decoded with the charset of the file on disk.
+pub fn declare_utf8(html: Vec<u8>) -> Vec<u8> {
+    match declaration(&html) {
+        Some((start, end)) if !is_utf8(&html[start..end]) => {
+            let mut out = Vec::with_capacity(html.len());
+            out.extend_from_slice(&html[..start]);
+            out.extend_from_slice(b"utf-8");
+            out.extend_from_slice(&html[end..]);
+            out
+        }
+        _ => html,
+    }
+}
+
+/// Declares `charset` for html without a declaration that isn't valid utf-8
+pub fn fallback(html: Vec<u8>, charset: &str) -> Vec<u8> {
+    if declaration(&html).is_some() || std::str::from_utf8(&html).is_ok() {
+        return html;
+    }
+    let meta = format!("<meta charset=\"{}\">", charset);
+    let lower = html[..html.len().min(PRESCAN)].to_ascii_lowercase();
+    let at = lower
+        .windows(5)
+        .position(|w| w == b"<head")
+        .and_then(|head| {
+            lower[head..]
+                .iter()
+                .position(|b| *b == b'>')
+                .map(|i| head + i + 1)
+        })
+        .unwrap_or(0);
+    let mut out = Vec::with_capacity(html.len() + meta.len());
+    out.extend_from_slice(&html[..at]);
+    out.extend_from_slice(meta.as_bytes());
+    out.extend_from_slice(&html[at..]);
+    out
+}
+
+/// Byte range of the charset name in `<meta charset=…>` or `<meta http-equiv content="…; charset=…">`
+fn declaration(html: &[u8]) -> Option<(usize, usize)> {
+    let lower = html[..html.len().min(PRESCAN)].to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(offset) = find(&lower[from..], b"<meta") {
+        let tag_start = from + offset;
+        let tag_end = lower[tag_start..]
+            .iter()
+            .position(|b| *b == b'>')
+            .map_or(lower.len(), |i| tag_start + i);
+        let tag = &lower[tag_start..tag_end];
+        if let Some(at) = find(tag, b"charset=") {
+            let mut start = tag_start + at + b"charset=".len();
+            if matches!(lower.get(start), Some(b'"' | b'\'')) {
+                start += 1;
+            }
+            let end = lower[start..tag_end]
+                .iter()
+                .position(|b| matches!(b, b'"' | b'\'' | b';' | b'/' | b' ' | b'>'))
+                .map_or(tag_end, |i| start + i);
+            if end > start {
+                return Some((start, end));
+            }
+        }
+        from = tag_end;
+    }
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn is_utf8(charset: &[u8]) -> bool {
+    charset.eq_ignore_ascii_case(b"utf-8") || charset.eq_ignore_ascii_case(b"utf8")
+}
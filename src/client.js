@@ -0,0 +1,247 @@
+(() => {
+  const config = window.__liveServer;
+  const relay = `${location.protocol}//${location.hostname}:${config.relay}`;
+  const id = Math.random().toString(36).slice(2);
+  const handlers = {};
+  let applying = false;
+
+  const post = (endpoint) => (type, data = {}) =>
+    fetch(`${relay}/${endpoint}`, {
+      method: "POST",
+      headers: { "X-Live-Server-Token": config.token },
+      body: JSON.stringify({ ...data, type, id, page: location.pathname }),
+    }).catch(() => {});
+  const send = post("emit");
+  const report = post("report");
+  const on = (type, handler) => (handlers[type] ??= []).push(handler);
+
+  const events = new EventSource(`${relay}/events`);
+  events.onmessage = (message) => {
+    const event = JSON.parse(message.data);
+    if (event.id === id) return;
+    applying = true;
+    try {
+      (handlers[event.type] ?? []).forEach((handler) => handler(event));
+    } finally {
+      applying = false;
+    }
+  };
+
+  config.send = send;
+  config.report = report;
+  config.on = on;
+
+  // the editor closes, reconnecting would only fill the console with errors
+  on("closing", () => {
+    events.close();
+    console.info("[live-server] server closing");
+  });
+
+  // sent by the editor with `broadcastToBrowsers`, the relay refuses scripts posted by browsers
+  on("script", (event) => new Function(event.code)());
+
+  if (config.features.console) {
+    const format = (args) =>
+      args
+        .map((arg) => {
+          if (arg instanceof Error) return arg.stack || String(arg);
+          if (typeof arg !== "object" || arg === null) return String(arg);
+          try {
+            return JSON.stringify(arg);
+          } catch {
+            return String(arg);
+          }
+        })
+        .join(" ");
+    for (const level of ["log", "info", "warn", "error", "debug"]) {
+      const original = console[level];
+      console[level] = (...args) => {
+        original.apply(console, args);
+        report("console", { level, message: format(args) });
+      };
+    }
+    addEventListener("error", (event) =>
+      report("console", {
+        level: "error",
+        message: `Uncaught ${event.error?.stack || event.message} (${event.filename}:${event.lineno}:${event.colno})`,
+      }),
+    );
+    addEventListener("unhandledrejection", (event) =>
+      report("console", { level: "error", message: `Unhandled rejection ${format([event.reason])}` }),
+    );
+    addEventListener("securitypolicyviolation", (event) =>
+      report("console", {
+        level: "error",
+        message: `Content-Security-Policy ${event.effectiveDirective} blocked ${event.blockedURI || "inline code"} (${event.sourceFile}:${event.lineNumber})`,
+      }),
+    );
+  }
+
+  if (config.features.hmr) {
+    // modules opt in with `window.__liveServer?.hot?.accept(import.meta.url, (module) => ...)`
+    const accepted = new Map();
+    config.hot = {
+      accept: (url, callback) => accepted.set(new URL(url, location.href).pathname, callback),
+    };
+    on("hmr", (event) => {
+      const callback = accepted.get(event.path);
+      if (!callback) return location.reload();
+      import(`${event.path}?t=${Date.now()}`).then(callback, () => location.reload());
+    });
+  }
+
+  if (config.features.reload) {
+    // the live server reloads over its websocket, this only steps in if that didn't happen shortly after
+    let unloading = false;
+    addEventListener("beforeunload", () => (unloading = true));
+    const page = (path) => (path.endsWith("/") ? `${path}index.html` : path);
+    const other = (path) => /\.html?$/.test(path) && page(path) !== page(location.pathname);
+    on("reload", (event) => {
+      if ((event.paths || [event.path]).every(other)) return;
+      setTimeout(() => unloading || location.reload(), 300);
+    });
+  }
+
+  if (config.features.follow) {
+    // only paths of this server, never another origin or a javascript: url
+    const local = (path) =>
+      typeof path === "string" &&
+      path.startsWith("/") &&
+      !path.startsWith("//") &&
+      new URL(path, location.origin).origin === location.origin;
+    on("navigate", (event) => {
+      if (local(event.path) && event.path !== location.pathname) location.assign(event.path);
+    });
+  }
+
+  if (config.features.documents) {
+    // rendered document blocks carry the source line they start at, the last line survives reloads
+    const key = `__liveServer.line:${location.pathname}`;
+    const scrollToLine = (line, behavior) => {
+      let target;
+      for (const element of document.querySelectorAll("[data-line]")) {
+        if (Number(element.dataset.line) > line) break;
+        target = element;
+      }
+      target?.scrollIntoView({ block: "center", behavior });
+    };
+    on("line", (event) => {
+      if (event.path !== location.pathname) return;
+      sessionStorage.setItem(key, event.line);
+      scrollToLine(event.line, "smooth");
+    });
+    const line = sessionStorage.getItem(key);
+    if (line !== null) scrollToLine(Number(line), "instant");
+  }
+
+  if (config.features.sync) {
+    const selector = (element) => {
+      const parts = [];
+      while (element && element.nodeType === 1 && element !== document.documentElement) {
+        if (element.id) {
+          parts.unshift(`#${CSS.escape(element.id)}`);
+          break;
+        }
+        const index = [...element.parentNode.children].indexOf(element) + 1;
+        parts.unshift(`${element.localName}:nth-child(${index})`);
+        element = element.parentNode;
+      }
+      return parts.join(" > ");
+    };
+    const samePage = (event) => event.page === location.pathname;
+    const scrollable = () => document.scrollingElement || document.documentElement;
+
+    let ignoreScrollUntil = 0;
+    let frame;
+    addEventListener(
+      "scroll",
+      () => {
+        if (Date.now() < ignoreScrollUntil) return;
+        cancelAnimationFrame(frame);
+        frame = requestAnimationFrame(() => {
+          const element = scrollable();
+          send("scroll", {
+            x: scrollX / Math.max(1, element.scrollWidth - innerWidth),
+            y: scrollY / Math.max(1, element.scrollHeight - innerHeight),
+          });
+        });
+      },
+      { passive: true },
+    );
+    addEventListener(
+      "click",
+      (event) => {
+        if (!applying && event.isTrusted) send("click", { target: selector(event.target) });
+      },
+      true,
+    );
+    addEventListener(
+      "input",
+      (event) => {
+        if (applying || !event.isTrusted) return;
+        const target = event.target;
+        const checkable = target.type === "checkbox" || target.type === "radio";
+        send("input", { target: selector(target), value: checkable ? target.checked : target.value });
+      },
+      true,
+    );
+
+    on("scroll", (event) => {
+      if (!samePage(event)) return;
+      const element = scrollable();
+      ignoreScrollUntil = Date.now() + 150;
+      scrollTo(
+        event.x * (element.scrollWidth - innerWidth),
+        event.y * (element.scrollHeight - innerHeight),
+      );
+    });
+    on("click", (event) => {
+      if (samePage(event)) document.querySelector(event.target)?.click();
+    });
+    on("input", (event) => {
+      const target = samePage(event) && document.querySelector(event.target);
+      if (!target) return;
+      if (typeof event.value === "boolean") target.checked = event.value;
+      else target.value = event.value;
+      target.dispatchEvent(new Event("input", { bubbles: true }));
+      target.dispatchEvent(new Event("change", { bubbles: true }));
+    });
+  }
+
+  if (config.features.toolbar) {
+    // a shadow root keeps the styles of the page and of the toolbar apart
+    const host = document.createElement("live-server-toolbar");
+    const root = host.attachShadow({ mode: "open" });
+    root.innerHTML = `<style>
+      div { position: fixed; right: 8px; bottom: 8px; z-index: 2147483647; display: flex; gap: 6px; align-items: center;
+        padding: 4px 8px; border-radius: 4px; font: 12px system-ui, sans-serif; color: #fff; background: #222d; }
+      .status { width: 8px; height: 8px; border-radius: 50%; background: #d33; }
+      .status.connected { background: #3c3; }
+      button { padding: 1px 6px; border: 0; border-radius: 3px; font: inherit; color: inherit; background: #fff3; cursor: pointer; }
+    </style><div><span class="status" title="Disconnected"></span><span class="time"></span>
+      <button class="reload">Reload</button><button class="pause"></button></div>`;
+    const status = root.querySelector(".status");
+    const pause = root.querySelector(".pause");
+    root.querySelector(".time").textContent = `Reloaded ${new Date().toLocaleTimeString()}`;
+    const connected = (state) => {
+      status.classList.toggle("connected", state);
+      status.title = state ? "Connected" : "Disconnected";
+    };
+    events.addEventListener("open", () => connected(true));
+    events.addEventListener("error", () => connected(false));
+    // live reload is paused for the whole workspace, every toolbar shows the state the editor confirmed
+    let paused = config.paused;
+    const showPaused = () => {
+      pause.textContent = paused ? "Resume" : "Pause";
+      pause.title = paused ? "Live reload is paused" : "Pause live reload";
+    };
+    showPaused();
+    root.querySelector(".reload").addEventListener("click", () => location.reload());
+    pause.addEventListener("click", () => report("pause", { paused: !paused }));
+    on("paused", (event) => {
+      paused = event.paused;
+      showPaused();
+    });
+    document.documentElement.append(host);
+  }
+})();
@@ -0,0 +1,135 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use dashmap::DashMap;
+
+use crate::hook;
+
+/// Command rendering `page.adoc` when `asciidoc` is enabled without an `asciidoc_command`
+pub const ASCIIDOC_COMMAND: [&str; 4] = ["asciidoctor", "-o", "-", "-"];
+/// Command rendering `page.rst` when `rst` is enabled without an `rst_command`
+pub const RST_COMMAND: [&str; 1] = ["rst2html"];
+
+/// Language compiled to html by a command, e.g. `npx pug` for `page.pug` or `asciidoctor` for `page.adoc`.
+///
+/// The last result of every source is kept by content, so diagnostics and requests share one run.
+pub struct Compiler {
+    /// Extensions of the sources, the first one is preferred if a page has several
+    extensions: &'static [&'static str],
+    command: Vec<String>,
+    cache: DashMap<PathBuf, (u64, Result<Vec<u8>, String>)>,
+}
+
+impl Compiler {
+    /// `None` if the command is empty
+    pub fn new(extensions: &'static [&'static str], command: Vec<String>) -> Option<Self> {
+        if command.is_empty() {
+            return None;
+        }
+        Some(Self {
+            extensions,
+            command,
+            cache: Default::default(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.command[0]
+    }
+
+    /// Sources that would compile to `page`, e.g. `page.pug` for `page.html`
+    pub fn source_for(&self, page: &Path) -> Vec<PathBuf> {
+        self.extensions
+            .iter()
+            .map(|extension| page.with_extension(extension))
+            .collect()
+    }
+
+    /// Page a source compiles to, `None` for files of other languages
+    pub fn page_for(&self, file: &Path) -> Option<PathBuf> {
+        self.is_source(file).then(|| file.with_extension("html"))
+    }
+
+    pub fn is_source(&self, file: &Path) -> bool {
+        file.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.extensions.contains(&ext))
+    }
+
+    /// Runs the command with the source on stdin and the workspace root as working directory
+    pub async fn compile(
+        &self,
+        root: &Path,
+        source: &Path,
+        text: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        let hash = hasher.finish();
+        if let Some(cached) = self.cache.get(source).filter(|entry| entry.0 == hash) {
+            return cached.1.clone();
+        }
+        let result = hook::pipe(&self.command, root, source, text).await;
+        self.cache
+            .insert(source.to_path_buf(), (hash, result.clone()));
+        result
+    }
+}
+
+/// Zero based line and column a compile error points at, from `file:line:column` as pug reports it,
+/// `line n` as haml and asciidoctor do or `file:line:` as docutils does
+pub fn error_position(message: &str) -> Option<(u32, u32)> {
+    let numbers = |text: &str| -> Option<u32> {
+        let end = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        text[..end].parse().ok()
+    };
+    for (at, _) in message.match_indices(':') {
+        let Some(line) = numbers(&message[at + 1..]) else {
+            continue;
+        };
+        let rest = &message[at + 1 + line.to_string().len()..];
+        if let Some(column) = rest.strip_prefix(':').and_then(numbers) {
+            return Some((line.saturating_sub(1), column.saturating_sub(1)));
+        }
+    }
+    let lower = message.to_ascii_lowercase();
+    if let Some(line) = lower.find("line ").and_then(|at| numbers(&lower[at + 5..])) {
+        return Some((line.saturating_sub(1), 0));
+    }
+    let line = message.match_indices(':').find_map(|(at, _)| {
+        let line = numbers(&message[at + 1..])?;
+        message[at + 1 + line.to_string().len()..]
+            .starts_with(':')
+            .then_some(line)
+    })?;
+    Some((line.saturating_sub(1), 0))
+}
+
+/// Page served instead of one whose source failed to compile, it reloads once the source is fixed
+pub fn error_page(source: &str, message: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Compile error</title>\n\
+         <style>body{{margin:0;padding:32px;background:#1e1e1e;color:#e6e6e6;font:14px ui-monospace,monospace}}\
+         h1{{color:#ff6b6b;font-size:18px}}pre{{white-space:pre-wrap}}</style>\n</head>\n<body>\n\
+         <h1>Failed to compile {}</h1>\n<pre>{}</pre>\n</body>\n</html>\n",
+        escape(source),
+        escape(message)
+    )
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,34 @@
+/// Where a url is written in html and css, attributes need a quoted value
+const OPENERS: [&str; 3] = ["href=", "src=", "url("];
+
+/// Url typed so far if `before`, the text of a line up to the cursor, ends inside an `href` or `src`
+/// attribute or a css `url()`. External urls, fragments and data urls aren't completed.
+pub fn typed_url(before: &str) -> Option<&str> {
+    // lowercasing ascii keeps the byte indices
+    let lower = before.to_ascii_lowercase();
+    let start = OPENERS
+        .iter()
+        .filter_map(|opener| value_start(before, &lower, opener))
+        .max()?;
+    let typed = &before[start..];
+    match typed.starts_with('#') || typed.starts_with("//") || typed.contains(':') {
+        true => None,
+        false => Some(typed),
+    }
+}
+
+/// Start of the value after the last `opener`, `None` if it was closed before the cursor
+fn value_start(before: &str, lower: &str, opener: &str) -> Option<usize> {
+    let at = lower.rfind(opener)? + opener.len();
+    let (quote, start) = match before[at..].chars().next() {
+        Some(quote @ ('"' | '\'')) => (Some(quote), at + 1),
+        _ if opener == "url(" => (None, at),
+        _ => return None,
+    };
+    let value = &before[start..];
+    let closed = match quote {
+        Some(quote) => value.contains(quote),
+        None => value.contains([')', ' ']),
+    };
+    (!closed).then_some(start)
+}
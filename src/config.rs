@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::Config;
+
+/// Configuration files in the workspace root, their options override the global files and `initializationOptions`.
+///
+/// The TOML file is layered over the json one.
+pub const FILE_NAMES: [&str; 2] = [".live-server.json", ".live-server.toml"];
+
+/// `live-server-lsp/config.json` and `config.toml` in the user configuration directory
+pub fn global_paths() -> Vec<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    dir.into_iter()
+        .flat_map(|dir| {
+            let dir = dir.join("live-server-lsp");
+            [dir.join("config.json"), dir.join("config.toml")]
+        })
+        .collect()
+}
+
+pub fn workspace_paths(workspace: &Path) -> [PathBuf; 2] {
+    FILE_NAMES.map(|name| workspace.join(name))
+}
+
+/// Deserializes `options`, the ones of the wrong type are reported and left out instead of all of them
+pub fn parse(options: &Value) -> (Config, Vec<String>) {
+    let Value::Object(options) = options else {
+        return (Config::default(), vec![]);
+    };
+    let mut errors = vec![];
+    let mut valid = Map::new();
+    for (key, value) in options {
+        let option = Map::from_iter([(key.clone(), value.clone())]);
+        match serde_json::from_value::<Config>(Value::Object(option)) {
+            Ok(_) => {
+                valid.insert(key.clone(), value.clone());
+            }
+            Err(e) => errors.push(format!("option `{}` is ignored: {}", key, e)),
+        }
+    }
+    let config = serde_json::from_value(Value::Object(valid)).unwrap_or_default();
+    (config, errors)
+}
+
+/// Layers the global and the workspace configuration file over `options`.
+///
+/// Files that are missing are skipped, files that fail to parse are skipped and reported as errors,
+/// like options of the wrong type.
+pub async fn load(options: &Value, workspace: &Path) -> (Config, Vec<String>) {
+    let mut merged = match options {
+        Value::Object(_) => options.clone(),
+        _ => Value::Object(Default::default()),
+    };
+    let mut errors = vec![];
+    for path in global_paths().into_iter().chain(workspace_paths(workspace)) {
+        let Ok(content) = tokio::fs::read(&path).await else {
+            continue;
+        };
+        let layer = match path.extension().is_some_and(|ext| ext == "toml") {
+            true => toml::from_str::<Value>(&String::from_utf8_lossy(&content))
+                .map_err(|e| e.to_string()),
+            false => serde_json::from_slice::<Value>(&content).map_err(|e| e.to_string()),
+        };
+        match layer {
+            Ok(Value::Object(layer)) => {
+                if let Value::Object(merged) = &mut merged {
+                    merged.extend(layer);
+                }
+            }
+            Ok(_) => errors.push(format!("{}: expected an object", path.display())),
+            Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    let (config, invalid) = parse(&merged);
+    errors.extend(invalid);
+    (config, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn keeps_the_options_of_the_right_type() {
+        let (config, errors) = parse(&json!({ "lazy": "yes", "start_port": 8080 }));
+        assert_eq!(config.start_port, Some(8080));
+        assert_eq!(config.lazy, None);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("option `lazy` is ignored"));
+    }
+}
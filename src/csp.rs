@@ -0,0 +1,45 @@
+use crate::random;
+
+/// Placeholder in a policy replaced with the sources the injected scripts need
+pub const PLACEHOLDER: &str = "{live_server}";
+
+/// The `csp` option of a server and the nonce allowing its injected client script.
+///
+/// The gateway sends the policy apart from the page the server renders, so the nonce is drawn once when the server starts
+pub struct Policy {
+    template: String,
+    nonce: String,
+}
+
+impl Policy {
+    pub fn new(template: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            template: template.to_string(),
+            nonce: nonce()?,
+        })
+    }
+
+    pub fn nonce(&self) -> &str {
+        &self.nonce
+    }
+
+    /// The policy with its placeholder expanded, see [`expand`]
+    pub fn header(&self, port: u16, relay: Option<u16>) -> String {
+        expand(&self.template, &self.nonce, port, relay)
+    }
+}
+
+/// Nonce allowing the injected client script, unguessable for the scripts of the page
+pub fn nonce() -> std::io::Result<String> {
+    random::token()
+}
+
+/// `policy` with the placeholder expanded to the nonce, the reload websocket of the server at `port`
+/// and the relay at `relay`, on any host so LAN and proxied addresses keep reloading
+pub fn expand(policy: &str, nonce: &str, port: u16, relay: Option<u16>) -> String {
+    let mut sources = format!("'nonce-{}' ws://*:{} wss://*:{}", nonce, port, port);
+    if let Some(relay) = relay {
+        sources.push_str(&format!(" http://*:{} https://*:{}", relay, relay));
+    }
+    policy.replace(PLACEHOLDER, &sources)
+}
@@ -0,0 +1,42 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use dashmap::DashMap;
+
+use crate::hook;
+
+/// Pipes served stylesheets through a user configured command, e.g. `npx postcss`.
+///
+/// Results are cached by content, so unchanged stylesheets don't spawn the command again.
+pub struct CssProcessor {
+    command: Vec<String>,
+    cache: DashMap<PathBuf, (u64, Vec<u8>)>,
+}
+
+impl CssProcessor {
+    /// `None` if the command is empty
+    pub fn new(command: Vec<String>) -> Option<Self> {
+        if command.is_empty() {
+            return None;
+        }
+        Some(Self {
+            command,
+            cache: Default::default(),
+        })
+    }
+
+    /// Runs the command with the stylesheet on stdin and the workspace root as working directory
+    pub async fn process(&self, root: &Path, path: &Path, css: &[u8]) -> Result<Vec<u8>, String> {
+        let mut hasher = DefaultHasher::new();
+        css.hash(&mut hasher);
+        let hash = hasher.finish();
+        if let Some(cached) = self.cache.get(path).filter(|entry| entry.0 == hash) {
+            return Ok(cached.1.clone());
+        }
+        let output = hook::pipe(&self.command, root, path, css).await?;
+        self.cache
+            .insert(path.to_path_buf(), (hash, output.clone()));
+        Ok(output)
+    }
+}
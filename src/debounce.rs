@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use dashmap::DashMap;
+
+/// Delays reloads per file extension, a change arriving within the delay replaces the pending one.
+///
+/// Delays are configured like `{"html": 500, "css": 0}`, `*` applies to every other extension.
+#[derive(Default)]
+pub struct Debounce {
+    delays: HashMap<String, Duration>,
+    /// Latest change of every file with a pending reload
+    generations: DashMap<PathBuf, u64>,
+}
+
+impl Debounce {
+    pub fn new(delays: &HashMap<String, u64>) -> Self {
+        Self {
+            delays: delays
+                .iter()
+                .map(|(ext, ms)| {
+                    let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+                    (ext, Duration::from_millis(*ms))
+                })
+                .collect(),
+            generations: Default::default(),
+        }
+    }
+
+    pub fn delay(&self, path: &Path) -> Duration {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        self.delays
+            .get(&ext)
+            .or_else(|| self.delays.get("*"))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Records a change of `path`, returns its generation
+    pub fn touch(&self, path: &Path) -> u64 {
+        let mut generation = self.generations.entry(path.to_path_buf()).or_default();
+        *generation += 1;
+        *generation
+    }
+
+    /// Whether `generation` is still the latest change of `path`, clears it if so
+    pub fn settle(&self, path: &Path, generation: u64) -> bool {
+        self.generations
+            .remove_if(path, |_, latest| *latest == generation)
+            .is_some()
+    }
+}
+
+/// How long reloads are collected unless `reload_batch` is set
+pub const BATCH_WINDOW: Duration = Duration::from_millis(50);
+
+/// Reloads arriving within a window of the first one, sent together once it passed.
+///
+/// Saving many files at once, like formatting a whole project on save, reloads the browsers once.
+pub struct Batch {
+    window: Duration,
+    paths: Mutex<Vec<PathBuf>>,
+}
+
+impl Batch {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            paths: Default::default(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Adds `path` to the pending reload, returns whether it opened the batch
+    pub fn add(&self, path: &Path) -> bool {
+        let Ok(mut paths) = self.paths.lock() else {
+            return false;
+        };
+        let opened = paths.is_empty();
+        if !paths.iter().any(|pending| pending == path) {
+            paths.push(path.to_path_buf());
+        }
+        opened
+    }
+
+    /// Paths of the pending reload in the order they were added, closing the batch
+    pub fn take(&self) -> Vec<PathBuf> {
+        self.paths
+            .lock()
+            .map(|mut paths| std::mem::take(&mut *paths))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_first_add_opens_a_batch() {
+        let batch = Batch::new(BATCH_WINDOW);
+        assert!(batch.add(Path::new("/index.html")));
+        assert!(!batch.add(Path::new("/index.html")));
+        assert!(!batch.add(Path::new("/about.html")));
+        assert!(!batch.add(Path::new("/index.html")));
+        assert_eq!(
+            batch.take(),
+            [PathBuf::from("/index.html"), PathBuf::from("/about.html")]
+        );
+        assert!(batch.add(Path::new("/index.html")));
+    }
+}
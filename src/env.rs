@@ -0,0 +1,42 @@
+use serde_json::{Map, Value};
+
+/// Replaces `{{ env.NAME }}` with the value of the environment variable `NAME`.
+///
+/// Placeholders of unset variables are kept, so a missing variable stays visible in the preview.
+pub fn substitute(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let tag = &rest[start..];
+        let Some(end) = tag.find("}}") else {
+            rest = tag;
+            break;
+        };
+        let value = tag[2..end]
+            .trim()
+            .strip_prefix("env.")
+            .filter(|name| is_name(name))
+            .and_then(|name| std::env::var(name).ok());
+        match value {
+            Some(value) => out.push_str(&value),
+            None => out.push_str(&tag[..end + 2]),
+        }
+        rest = &tag[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Environment as a template context value, exposed as `env`
+pub fn context() -> Value {
+    Value::Object(
+        std::env::vars()
+            .map(|(name, value)| (name, Value::String(value)))
+            .collect::<Map<_, _>>(),
+    )
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
@@ -0,0 +1,27 @@
+use std::net::IpAddr;
+
+use tokio::io::copy_bidirectional;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+
+/// Accepts connections on `address` and pipes them to the server listening on the loopback `port`.
+///
+/// The server can only bind loopback or every interface, forwarding lets it answer on other addresses too.
+/// Connections, including the reload websocket, go to the same server and share its reload signal.
+pub async fn listen(
+    address: IpAddr,
+    port: u16,
+    listeners: &mut JoinSet<()>,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind((address, port)).await?;
+    listeners.spawn(async move {
+        while let Ok((mut inbound, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                if let Ok(mut outbound) = TcpStream::connect(("127.0.0.1", port)).await {
+                    let _ = copy_bidirectional(&mut inbound, &mut outbound).await;
+                }
+            });
+        }
+    });
+    Ok(())
+}
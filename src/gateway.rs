@@ -0,0 +1,914 @@
+use std::future::Future;
+use std::io::{ErrorKind, SeekFrom};
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::io::{
+    copy_bidirectional, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt,
+    AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::limits::{Connections, Limits};
+use crate::network;
+use crate::proxy::{self, Proxy, Response};
+
+/// Request heads larger than this close the connection
+const MAX_HEAD: usize = 16 * 1024;
+/// Connections that didn't send a whole request head by then are closed, so stalled clients don't hold on to a slot
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long an idle connection is kept open for the next request unless `keep_alive_timeout` is set
+pub const KEEP_ALIVE: Duration = Duration::from_secs(5);
+/// Assets from this size on are sent from disk in chunks unless `stream_threshold` is set
+pub const STREAM_THRESHOLD: u64 = 1024 * 1024;
+/// Size of the chunks files are sent in
+const CHUNK: usize = 64 * 1024;
+/// `Content-Encoding` and extension of the precompressed variants, in order of preference
+const ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+/// Headers about the connection to the gateway, which aren't passed on to the server
+const HOP_HEADERS: [&str; 3] = ["connection", "keep-alive", "proxy-connection"];
+/// How long requests wait for a server that is still starting
+const CONNECT_ATTEMPTS: u32 = 20;
+
+/// Content types of the common web extensions, as the live server and the gateway send them
+const MIME_TYPES: [(&str, &str); 27] = [
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("wasm", "application/wasm"),
+    ("avif", "image/avif"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("pdf", "application/pdf"),
+    ("txt", "text/plain"),
+];
+
+/// Workspace answered by a [`Gateway`]
+pub trait Site: Send + Sync + 'static {
+    /// Whether requests fail like without a network, see `setProjectOffline`
+    fn is_offline(&self) -> bool;
+
+    /// Whether `host`, the `Host` header of a request, names the workspace server
+    fn allows(&self, host: &str) -> bool;
+
+    /// Upstream APIs requests and websockets below their prefixes are passed on to
+    fn proxy(&self) -> Option<&Proxy>;
+
+    /// Answer of the mock API to a request and how long it is held back, `None` if no route matches it
+    fn mock(&self, request: &Request) -> impl Future<Output = Option<(Response, Duration)>> + Send;
+
+    /// Called as a websocket to the server opens and closes, like the reload socket of every page
+    fn socket(&self, open: bool);
+
+    /// Answer of the gateway to a `GET` or `HEAD` request, `None` leaves it to the server
+    fn route(&self, request: &Request) -> impl Future<Output = Option<Route>> + Send;
+
+    /// Headers of the response to a request, replacing the same named ones of the server
+    fn headers(&self, request: &Request) -> impl Future<Output = Vec<(String, String)>> + Send;
+
+    /// Records a response the gateway sent itself, like the server records its own
+    fn record(
+        &self,
+        url: &str,
+        status: u16,
+        size: Option<u64>,
+        started: SystemTime,
+        wait: Duration,
+        receive: Duration,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+pub enum Route {
+    /// `301 Moved Permanently` to the location, like the redirects of the trailing slash policy
+    Redirect(String),
+    File(StaticFile),
+}
+
+/// File sent from disk as it is
+pub struct StaticFile {
+    /// Url the request is recorded under
+    pub url: String,
+    pub path: PathBuf,
+    pub content_type: &'static str,
+    /// `Content-Encoding` of a precompressed variant
+    pub encoding: Option<&'static str>,
+    /// Simulated bandwidth in kilobits per second, chunks are held back for their transfer time
+    pub throttle: Option<u64>,
+}
+
+pub struct Request {
+    pub method: String,
+    /// Path with query
+    pub target: String,
+    version: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Path without the query
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    /// Query with its `?`, empty without one
+    pub fn query(&self) -> &str {
+        &self.target[self.path().len()..]
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether the client closes the connection after the response
+    fn closes(&self) -> bool {
+        self.version != "HTTP/1.1"
+            || self
+                .header("connection")
+                .is_some_and(|connection| lists(connection, "close"))
+    }
+
+    fn is_upgrade(&self) -> bool {
+        self.header("upgrade").is_some()
+            && self
+                .header("connection")
+                .is_some_and(|connection| lists(connection, "upgrade"))
+    }
+
+    fn has_body(&self) -> bool {
+        self.header("transfer-encoding").is_some() || self.content_length() > 0
+    }
+
+    fn content_length(&self) -> u64 {
+        self.header("content-length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Request line and headers without the ones in `skip`, followed by the lines of `extra`
+    fn head(&self, skip: &[&str], extra: &str) -> String {
+        let mut head = format!("{} {} {}\r\n", self.method, self.target, self.version);
+        for (name, value) in &self.headers {
+            if !skip.iter().any(|skip| name.eq_ignore_ascii_case(skip)) {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str(extra);
+        head.push_str("\r\n");
+        head
+    }
+}
+
+/// Answers on the port of a workspace in front of `rusty-live-server`, which listens on a free loopback port behind it.
+///
+/// The gateway sends what the server can't: redirects of the trailing slash policy, precompressed variants,
+/// large assets streamed from disk with support for ranges,
+/// `503`s of the offline simulation and `403`s for hosts the workspace isn't served under, which keeps pages of rebound domains out.
+/// Routes of the mock API are answered by the gateway, requests below a prefix of `proxy` go to its upstream.
+/// Upgrades bypass the offline simulation, so the reload websocket keeps working,
+/// except websockets below a prefix of `proxy`, which go to its upstream as well.
+/// Responses get the headers of the site, like those of `header_rules`, in place of the server's.
+/// Everything else, like the reload websocket, goes to the server.
+/// Connections over the limits get a `503`, the others are kept open for the next request until they idle for `keep_alive`.
+pub struct Gateway<S> {
+    site: S,
+    /// Loopback port of the server
+    server: u16,
+    connections: Arc<Connections>,
+    /// Zero closes every connection after its response
+    keep_alive: Duration,
+}
+
+impl<S: Site> Gateway<S> {
+    pub fn new(site: S, server: u16, limits: Limits, keep_alive: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            site,
+            server,
+            connections: Connections::new(limits),
+            keep_alive,
+        })
+    }
+
+    /// Accepts connections until the listener fails
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        while let Ok((stream, address)) = listener.accept().await {
+            self.clone().accept(stream, Some(address.ip()));
+        }
+    }
+
+    /// Accepts connections of a local reverse proxy until the listener fails,
+    /// they have no address and only count towards `max_connections`
+    #[cfg(unix)]
+    pub async fn serve_unix(self: Arc<Self>, listener: tokio::net::UnixListener) {
+        while let Ok((stream, _)) = listener.accept().await {
+            self.clone().accept(stream, None);
+        }
+    }
+
+    fn accept(
+        self: Arc<Self>,
+        mut stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        address: Option<IpAddr>,
+    ) {
+        tokio::spawn(async move {
+            let Some(connection) = self.connections.admit(address) else {
+                let _ = respond(&mut stream, "503 Service Unavailable", "", true).await;
+                return;
+            };
+            let _ = self.handle(stream).await;
+            drop(connection);
+        });
+    }
+
+    async fn handle(
+        &self,
+        stream: impl AsyncRead + AsyncWrite + Unpin + Send,
+    ) -> std::io::Result<()> {
+        let mut client = BufReader::new(stream);
+        let mut timeout = READ_TIMEOUT;
+        loop {
+            let request = match tokio::time::timeout(timeout, read_request(&mut client)).await {
+                Ok(Ok(Some(request))) => request,
+                // closed or idle between requests
+                Ok(Ok(None)) | Err(_) => return Ok(()),
+                Ok(Err(e)) => return Err(e),
+            };
+            let started = SystemTime::now();
+            let start = Instant::now();
+            if !request
+                .header("host")
+                .is_some_and(|host| self.site.allows(host))
+            {
+                return respond(&mut client, "403 Forbidden", "", true).await;
+            }
+            if request.is_upgrade() {
+                return self.upgrade(client, &request).await;
+            }
+            let close = request.closes() || self.keep_alive.is_zero();
+            let open = match self.site.is_offline() {
+                true => {
+                    // a body that isn't read would be taken for the next request
+                    let close = close || request.has_body();
+                    respond(&mut client, "503 Service Unavailable", "", close).await?;
+                    !close
+                }
+                false => {
+                    let timing = (started, start);
+                    self.answer(&mut client, &request, close, timing).await?
+                }
+            };
+            if !open {
+                return Ok(());
+            }
+            timeout = self.keep_alive;
+        }
+    }
+
+    /// Answers a request itself or through the server, returns whether the connection stays open
+    async fn answer(
+        &self,
+        client: &mut BufReader<impl AsyncRead + AsyncWrite + Unpin + Send>,
+        request: &Request,
+        close: bool,
+        timing: (SystemTime, Instant),
+    ) -> std::io::Result<bool> {
+        if let Some((response, delay)) = self.site.mock(request).await {
+            // the body isn't used, but would be taken for the next request
+            if read_body(client, request, proxy::MAX_BODY).await?.is_none() {
+                return too_large(client).await;
+            }
+            tokio::time::sleep(delay).await;
+            return self
+                .send_response(client, request, response, close, timing)
+                .await;
+        }
+        if let Some(proxy) = self
+            .site
+            .proxy()
+            .filter(|proxy| proxy.routes(&request.target))
+        {
+            let Some(body) = read_body(client, request, proxy::MAX_BODY).await? else {
+                return too_large(client).await;
+            };
+            let response = proxy
+                .handle(&request.method, &request.target, &request.headers, &body)
+                .await;
+            return self
+                .send_response(client, request, response, close, timing)
+                .await;
+        }
+        let route = match matches!(request.method.as_str(), "GET" | "HEAD") && !request.has_body() {
+            true => self.site.route(request).await,
+            false => None,
+        };
+        match route {
+            Some(Route::Redirect(location)) => {
+                let location = format!("Location: {}\r\n", location);
+                respond(client, "301 Moved Permanently", &location, close).await?;
+                Ok(!close)
+            }
+            Some(Route::File(file)) => self.send_file(client, request, file, close, timing).await,
+            None => self.forward(client, request, close).await,
+        }
+    }
+
+    /// Connects to the server, which may still be starting while the gateway accepts
+    async fn connect(&self) -> std::io::Result<TcpStream> {
+        let mut attempts = 1;
+        loop {
+            let connected = TcpStream::connect((Ipv4Addr::LOCALHOST, self.server)).await;
+            let refused = matches!(&connected, Err(e) if e.kind() == ErrorKind::ConnectionRefused);
+            if !refused || attempts == CONNECT_ATTEMPTS {
+                return connected;
+            }
+            attempts += 1;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Passes a request on to the server and its response back, returns whether the connection stays open
+    async fn forward(
+        &self,
+        client: &mut BufReader<impl AsyncRead + AsyncWrite + Unpin + Send>,
+        request: &Request,
+        close: bool,
+    ) -> std::io::Result<bool> {
+        let Ok(mut server) = self.connect().await else {
+            respond(client, "502 Bad Gateway", "", close).await?;
+            return Ok(!close);
+        };
+        if request.header("transfer-encoding").is_some() {
+            // chunked bodies aren't parsed, the server gets the rest of the connection as it is
+            server.write_all(request.head(&[], "").as_bytes()).await?;
+            copy_bidirectional(client, &mut server).await?;
+            return Ok(false);
+        }
+        let head = request.head(&HOP_HEADERS, "Connection: close\r\n");
+        server.write_all(head.as_bytes()).await?;
+        let length = request.content_length();
+        if tokio::io::copy(&mut (&mut *client).take(length), &mut server).await? < length {
+            return Ok(false);
+        }
+
+        let headers = self.site.headers(request).await;
+        let mut server = BufReader::new(server);
+        let mut head = String::new();
+        server.read_line(&mut head).await?;
+        let status: u16 = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or(ErrorKind::InvalidData)?;
+        let mut length = None;
+        let mut chunked = false;
+        loop {
+            let mut line = String::new();
+            if server.read_line(&mut line).await? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "connection" | "keep-alive" => continue,
+                "content-length" => length = value.parse::<u64>().ok(),
+                "transfer-encoding" => chunked = lists(value, "chunked"),
+                _ => {}
+            }
+            if !replaces(&headers, name.trim()) {
+                head.push_str(line);
+                head.push_str("\r\n");
+            }
+        }
+        push_headers(&mut head, &headers);
+        let bodyless = request.method == "HEAD" || status < 200 || matches!(status, 204 | 304);
+        // a body without a length ends when the server closes, and so does the connection
+        let open = !close && (bodyless || chunked || length.is_some());
+        if !open {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        client.write_all(head.as_bytes()).await?;
+        match length {
+            _ if bodyless => {}
+            _ if chunked => copy_chunked(&mut server, client).await?,
+            Some(length) => {
+                if tokio::io::copy(&mut (&mut server).take(length), client).await? < length {
+                    return Ok(false);
+                }
+            }
+            None => {
+                tokio::io::copy(&mut server, client).await?;
+            }
+        }
+        client.flush().await?;
+        Ok(open)
+    }
+
+    /// Sends a response of the gateway itself, like those of the mock API and the proxy,
+    /// returns whether the connection stays open
+    async fn send_response(
+        &self,
+        client: &mut BufReader<impl AsyncRead + AsyncWrite + Unpin + Send>,
+        request: &Request,
+        response: Response,
+        close: bool,
+        (started, start): (SystemTime, Instant),
+    ) -> std::io::Result<bool> {
+        let wait = start.elapsed();
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\n",
+            response.status,
+            response.reason,
+            response.body.len()
+        );
+        for (name, value) in &response.headers {
+            // the gateway frames the body itself
+            let framing = ["content-length", "transfer-encoding"]
+                .iter()
+                .chain(&HOP_HEADERS)
+                .any(|framing| name.eq_ignore_ascii_case(framing));
+            if !framing {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        if close {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        client.write_all(head.as_bytes()).await?;
+        if request.method != "HEAD" {
+            client.write_all(&response.body).await?;
+        }
+        client.flush().await?;
+        let size = Some(response.body.len() as u64);
+        let receive = start.elapsed() - wait;
+        self.site
+            .record(
+                request.path(),
+                response.status,
+                size,
+                started,
+                wait,
+                receive,
+            )
+            .await;
+        Ok(!close)
+    }
+
+    /// Pipes an upgraded connection, like the reload websocket, to the server
+    async fn upgrade(
+        &self,
+        mut client: BufReader<impl AsyncRead + AsyncWrite + Unpin + Send>,
+        request: &Request,
+    ) -> std::io::Result<()> {
+        if let Some(proxy) = self
+            .site
+            .proxy()
+            .filter(|proxy| proxy.routes(&request.target))
+        {
+            return self.upgrade_upstream(client, request, proxy).await;
+        }
+        let mut server = self.connect().await?;
+        server.write_all(request.head(&[], "").as_bytes()).await?;
+        self.site.socket(true);
+        let piped = copy_bidirectional(&mut client, &mut server).await;
+        self.site.socket(false);
+        piped.map(|_| ())
+    }
+
+    /// Passes a websocket below a prefix of the proxy on to its upstream.
+    /// Browsers send an `Origin` with every websocket, which has to be the page itself,
+    /// so pages of other sites can't reach the upstream through the browsers of the network
+    async fn upgrade_upstream(
+        &self,
+        mut client: BufReader<impl AsyncRead + AsyncWrite + Unpin + Send>,
+        request: &Request,
+        proxy: &Proxy,
+    ) -> std::io::Result<()> {
+        let same_origin = request
+            .header("origin")
+            .zip(request.header("host"))
+            .is_some_and(|(origin, host)| is_same_origin(origin, host));
+        if !same_origin {
+            return respond(&mut client, "403 Forbidden", "", true).await;
+        }
+        if self.site.is_offline() {
+            return respond(&mut client, "503 Service Unavailable", "", true).await;
+        }
+        let upgraded = proxy
+            .upgrade(&request.method, &request.target, &request.headers)
+            .await;
+        let Ok(mut upstream) = upgraded else {
+            return respond(&mut client, "502 Bad Gateway", "", true).await;
+        };
+        copy_bidirectional(&mut client, &mut upstream).await?;
+        Ok(())
+    }
+
+    /// Sends a file from disk in chunks, or the range of it a `Range` header asks for.
+    /// Returns whether the connection stays open
+    async fn send_file(
+        &self,
+        client: &mut BufReader<impl AsyncRead + AsyncWrite + Unpin + Send>,
+        request: &Request,
+        file: StaticFile,
+        close: bool,
+        (started, start): (SystemTime, Instant),
+    ) -> std::io::Result<bool> {
+        let Ok(mut opened) = tokio::fs::File::open(&file.path).await else {
+            // gone since it was routed
+            return self.forward(client, request, close).await;
+        };
+        let size = opened.metadata().await?.len();
+        // ranges of a precompressed variant would be ranges of its encoding
+        let range = match file.encoding {
+            None => request
+                .header("range")
+                .and_then(|range| byte_range(range, size)),
+            Some(_) => None,
+        };
+        let (status, body) = match range {
+            None => (200, 0..size),
+            Some(Some(range)) => (206, range),
+            Some(None) => {
+                let unsatisfiable = format!("Content-Range: bytes */{}\r\n", size);
+                respond(client, "416 Range Not Satisfiable", &unsatisfiable, close).await?;
+                return Ok(!close);
+            }
+        };
+        let length = body.end - body.start;
+        let headers = self.site.headers(request).await;
+        let mut head = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\n",
+            match status {
+                206 => "206 Partial Content",
+                _ => "200 OK",
+            },
+            length
+        );
+        if !replaces(&headers, "content-type") {
+            head.push_str(&format!("Content-Type: {}\r\n", file.content_type));
+        }
+        match file.encoding {
+            Some(encoding) => head.push_str(&format!(
+                "Content-Encoding: {}\r\nVary: Accept-Encoding\r\n",
+                encoding
+            )),
+            None => head.push_str("Accept-Ranges: bytes\r\n"),
+        }
+        if status == 206 {
+            let range = format!("bytes {}-{}/{}", body.start, body.end - 1, size);
+            head.push_str(&format!("Content-Range: {}\r\n", range));
+        }
+        push_headers(&mut head, &headers);
+        if close {
+            head.push_str("Connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        client.write_all(head.as_bytes()).await?;
+        let wait = start.elapsed();
+        if request.method != "HEAD" {
+            opened.seek(SeekFrom::Start(body.start)).await?;
+            let mut remaining = length;
+            let mut chunk = vec![0; CHUNK];
+            while remaining > 0 {
+                let len = remaining.min(CHUNK as u64) as usize;
+                let read = opened.read(&mut chunk[..len]).await?;
+                if read == 0 {
+                    // truncated while it was sent, the length can't be kept
+                    return Ok(false);
+                }
+                client.write_all(&chunk[..read]).await?;
+                remaining -= read as u64;
+                if let Some(kbps) = file.throttle {
+                    tokio::time::sleep(network::transfer_time(read as u64, kbps)).await;
+                }
+            }
+        }
+        client.flush().await?;
+        let receive = start.elapsed() - wait;
+        self.site
+            .record(&file.url, status, Some(length), started, wait, receive)
+            .await;
+        Ok(!close)
+    }
+}
+
+/// Next request head of a connection, `None` if it closed before sending one
+async fn read_request(
+    client: &mut (impl AsyncBufRead + Unpin),
+) -> std::io::Result<Option<Request>> {
+    let mut head = client.take(MAX_HEAD as u64);
+    let mut line = String::new();
+    // clients may send a line break after the body of a request
+    while line.trim().is_empty() {
+        line.clear();
+        if head.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ErrorKind::InvalidData.into());
+    };
+    let mut request = Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        version: version.to_string(),
+        headers: vec![],
+    };
+    loop {
+        let mut header = String::new();
+        // the end of a head larger than the limit reads like a closed connection
+        if head.read_line(&mut header).await? == 0 {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            return Ok(Some(request));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let header = (name.trim().to_string(), value.trim().to_string());
+            request.headers.push(header);
+        }
+    }
+}
+
+/// Refuses a request with a body over the limit, whose rest would be taken for the next request
+async fn too_large(client: &mut (impl AsyncWrite + Unpin)) -> std::io::Result<bool> {
+    respond(client, "413 Content Too Large", "", true).await?;
+    Ok(false)
+}
+
+/// Body of a request, decoded if it is chunked, `None` if it is larger than `limit`
+async fn read_body(
+    client: &mut (impl AsyncBufRead + Unpin),
+    request: &Request,
+    limit: usize,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let mut body = vec![];
+    if request.header("transfer-encoding").is_none() {
+        let length = request.content_length();
+        if length > limit as u64 {
+            return Ok(None);
+        }
+        body.resize(length as usize, 0);
+        client.read_exact(&mut body).await?;
+        return Ok(Some(body));
+    }
+    loop {
+        let line = read_line(client).await?;
+        let size = line.trim().split(';').next().unwrap_or_default();
+        let size = usize::from_str_radix(size, 16).map_err(|_| ErrorKind::InvalidData)?;
+        if size == 0 {
+            break;
+        }
+        if size > limit - body.len() {
+            return Ok(None);
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        client.read_exact(&mut body[start..]).await?;
+        // the line break after the chunk
+        read_line(client).await?;
+    }
+    // trailers aren't passed on
+    while !read_line(client).await?.trim().is_empty() {}
+    Ok(Some(body))
+}
+
+/// Line of a chunked body no longer than a request head, failing if the connection closes before it ends
+async fn read_line(client: &mut (impl AsyncBufRead + Unpin)) -> std::io::Result<String> {
+    let mut line = String::new();
+    if client.take(MAX_HEAD as u64).read_line(&mut line).await? == 0 || !line.ends_with('\n') {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(line)
+}
+
+/// Copies a chunked body up to its last chunk and trailers
+async fn copy_chunked(
+    from: &mut (impl AsyncBufRead + Unpin),
+    to: &mut (impl AsyncWrite + Unpin),
+) -> std::io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if from.read_line(&mut line).await? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        to.write_all(line.as_bytes()).await?;
+        let size = line.trim().split(';').next().unwrap_or_default();
+        let size = u64::from_str_radix(size, 16).map_err(|_| ErrorKind::InvalidData)?;
+        if size == 0 {
+            break;
+        }
+        // the chunk and the line break after it
+        if tokio::io::copy(&mut (&mut *from).take(size + 2), to).await? < size + 2 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+    }
+    loop {
+        let mut line = String::new();
+        if from.read_line(&mut line).await? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        to.write_all(line.as_bytes()).await?;
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// Writes a response without a body, every one of `headers` ends with a line break
+async fn respond(
+    client: &mut (impl AsyncWrite + Unpin),
+    status: &str,
+    headers: &str,
+    close: bool,
+) -> std::io::Result<()> {
+    let connection = match close {
+        true => "Connection: close\r\n",
+        false => "",
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\n{}Content-Length: 0\r\n{}\r\n",
+        status, headers, connection
+    );
+    client.write_all(response.as_bytes()).await?;
+    client.flush().await
+}
+
+/// Whether one of `headers` replaces the header `name`
+fn replaces(headers: &[(String, String)], name: &str) -> bool {
+    headers
+        .iter()
+        .any(|(header, _)| header.eq_ignore_ascii_case(name))
+}
+
+/// Appends `headers` to a response head
+fn push_headers(head: &mut String, headers: &[(String, String)]) {
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+}
+
+/// Whether `origin`, like `https://site.localhost`, is the page `host` names
+fn is_same_origin(origin: &str, host: &str) -> bool {
+    origin
+        .strip_prefix("http://")
+        .or_else(|| origin.strip_prefix("https://"))
+        .is_some_and(|origin| origin.eq_ignore_ascii_case(host))
+}
+
+/// Whether the comma separated `header` lists `token`
+fn lists(header: &str, token: &str) -> bool {
+    header
+        .split(',')
+        .any(|item| item.trim().eq_ignore_ascii_case(token))
+}
+
+/// Whether an `Accept-Encoding` header accepts `encoding`, by name or `*`, with a quality above 0
+fn accepts(header: &str, encoding: &str) -> bool {
+    let quality = |name: &str| {
+        header.split(',').find_map(|item| {
+            let mut params = item.split(';');
+            let coding = params.next()?.trim();
+            coding.eq_ignore_ascii_case(name).then(|| {
+                params
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(1.0, |q| q.trim().parse::<f32>().unwrap_or(0.0))
+            })
+        })
+    };
+    quality(encoding)
+        .or_else(|| quality("*"))
+        .is_some_and(|quality| quality > 0.0)
+}
+
+/// Content type of a file by its extension, `application/octet-stream` for unknown ones
+pub fn mime_type(path: &Path) -> &'static str {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| {
+            MIME_TYPES
+                .iter()
+                .find(|(known, _)| ext.eq_ignore_ascii_case(known))
+        })
+        .map_or("application/octet-stream", |(_, mime_type)| *mime_type)
+}
+
+/// Precompressed variant of `file` the client accepts with `accepted`, like `app.js.br` next to `app.js`,
+/// and its `Content-Encoding`. Variants older than the file are left over from a previous build and ignored
+pub async fn precompressed(file: &Path, accepted: &str) -> Option<(PathBuf, &'static str)> {
+    let modified = tokio::fs::metadata(file).await.ok()?.modified().ok()?;
+    for (encoding, extension) in ENCODINGS {
+        if !accepts(accepted, encoding) {
+            continue;
+        }
+        let mut name = file.file_name()?.to_os_string();
+        name.push(".");
+        name.push(extension);
+        let variant = file.with_file_name(name);
+        let fresh = tokio::fs::metadata(&variant).await.is_ok_and(|metadata| {
+            metadata.is_file() && metadata.modified().is_ok_and(|variant| variant >= modified)
+        });
+        if fresh {
+            return Some((variant, encoding));
+        }
+    }
+    None
+}
+
+/// Bytes a `Range` header asks for of a body of `size` bytes, `Some(None)` if they lie past its end.
+/// `None` for headers asking for several ranges or other units, which are answered with the whole body
+fn byte_range(header: &str, size: u64) -> Option<Option<Range<u64>>> {
+    let (start, end) = header.trim().strip_prefix("bytes=")?.split_once('-')?;
+    if end.contains(',') {
+        return None;
+    }
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => (size.saturating_sub(suffix.parse().ok()?), size),
+        (start, "") => (start.parse().ok()?, size),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            (start, end.saturating_add(1).min(size))
+        }
+    };
+    Some((start < end).then_some(start..end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_encodings_by_name_and_quality() {
+        assert!(accepts("gzip, deflate, br", "br"));
+        assert!(accepts("GZIP", "gzip"));
+        assert!(accepts("*", "br"));
+        assert!(!accepts("gzip, br;q=0", "br"));
+        assert!(!accepts("br;q=0, *", "br"));
+        assert!(accepts("br;q=0.5", "br"));
+        assert!(!accepts("identity", "gzip"));
+        assert!(!accepts("", "gzip"));
+    }
+
+    #[test]
+    fn matches_origins_to_the_host() {
+        assert!(is_same_origin("http://localhost:5500", "localhost:5500"));
+        assert!(is_same_origin("https://Site.localhost", "site.localhost"));
+        assert!(!is_same_origin("http://localhost:5501", "localhost:5500"));
+        assert!(!is_same_origin("http://evil.example", "localhost:5500"));
+        assert!(!is_same_origin("null", "localhost:5500"));
+    }
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        assert_eq!(byte_range("bytes=0-99", 1000), Some(Some(0..100)));
+        assert_eq!(byte_range("bytes=900-", 1000), Some(Some(900..1000)));
+        assert_eq!(byte_range("bytes=-100", 1000), Some(Some(900..1000)));
+        // the end is capped at the size
+        assert_eq!(byte_range("bytes=500-5000", 1000), Some(Some(500..1000)));
+        assert_eq!(byte_range("bytes=1000-", 1000), Some(None));
+        assert_eq!(byte_range("bytes=-0", 1000), Some(None));
+    }
+
+    #[test]
+    fn ignores_other_ranges() {
+        assert_eq!(byte_range("bytes=0-1, 5-6", 1000), None);
+        assert_eq!(byte_range("bytes=5-1", 1000), None);
+        assert_eq!(byte_range("items=0-1", 1000), None);
+        assert_eq!(byte_range("bytes=a-b", 1000), None);
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+use dashmap::DashMap;
+
+/// Which served pages reference which assets, built from every html response.
+#[derive(Default)]
+pub struct DependencyGraph {
+    /// asset -> pages including it
+    dependents: DashMap<PathBuf, HashSet<PathBuf>>,
+    /// page -> assets it includes, used to drop stale edges when a page is served again
+    dependencies: DashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl DependencyGraph {
+    /// Replaces the recorded dependencies of `page` with the references found in `html`
+    /// and the files it was generated from
+    pub fn record(&self, root: &Path, page: &Path, html: &str, sources: &[PathBuf]) {
+        let assets: Vec<PathBuf> = references(html)
+            .filter_map(|reference| resolve(root, page, reference))
+            .chain(sources.iter().cloned())
+            .collect();
+        if let Some((_, old)) = self.dependencies.remove(page) {
+            for asset in old {
+                if let Some(mut pages) = self.dependents.get_mut(&asset) {
+                    pages.remove(page);
+                }
+            }
+        }
+        for asset in &assets {
+            self.dependents
+                .entry(asset.clone())
+                .or_default()
+                .insert(page.to_path_buf());
+        }
+        self.dependencies.insert(page.to_path_buf(), assets);
+    }
+
+    /// Every page served so far
+    pub fn pages(&self) -> Vec<PathBuf> {
+        self.dependencies
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Pages that have to reload when `asset` changed. Empty if no served page is known to include it.
+    pub fn dependents(&self, asset: &Path) -> Vec<PathBuf> {
+        self.dependents
+            .get(asset)
+            .map(|pages| pages.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Values of `src` and `href` attributes
+fn references(html: &str) -> impl Iterator<Item = &str> {
+    let lower = html.to_ascii_lowercase();
+    let mut found = vec![];
+    for attribute in ["src", "href"] {
+        let mut from = 0;
+        while let Some(i) = lower[from..].find(attribute) {
+            let start = from + i;
+            from = start + attribute.len();
+            let before = lower[..start].chars().next_back();
+            if !matches!(before, Some(c) if c.is_whitespace()) {
+                continue;
+            }
+            let rest = html[from..].trim_start();
+            let Some(rest) = rest.strip_prefix('=') else {
+                continue;
+            };
+            let rest = rest.trim_start();
+            let value = match rest.chars().next() {
+                Some(quote @ ('"' | '\'')) => rest[1..].split(quote).next(),
+                _ => rest.split(|c: char| c.is_whitespace() || c == '>').next(),
+            };
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                found.push(value);
+            }
+        }
+    }
+    found.into_iter()
+}
+
+/// Resolves a reference against the page, `None` for external urls
+fn resolve(root: &Path, page: &Path, reference: &str) -> Option<PathBuf> {
+    let reference = reference.split(['?', '#']).next()?;
+    if reference.is_empty() || reference.starts_with("//") || reference.contains(':') {
+        return None;
+    }
+    let joined = match reference.strip_prefix('/') {
+        Some(absolute) => root.join(absolute),
+        None => page.parent()?.join(reference),
+    };
+    let mut resolved = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            c => resolved.push(c),
+        }
+    }
+    resolved.starts_with(root).then_some(resolved)
+}
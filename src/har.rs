@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+
+use crate::gateway::mime_type;
+use crate::log::iso_date;
+
+/// Requests kept for the export unless `har_entries` is set
+pub const DEFAULT_ENTRIES: usize = 1000;
+
+struct Entry {
+    started: SystemTime,
+    url: String,
+    status: u16,
+    size: Option<u64>,
+    /// Until the body was ready
+    wait: Duration,
+    /// Simulated transfer of the body
+    receive: Duration,
+}
+
+/// The latest requests a workspace server answered, exported as a HAR file
+pub struct Har {
+    entries: Mutex<VecDeque<Entry>>,
+    capacity: usize,
+}
+
+impl Har {
+    /// Keeps up to `capacity` requests, none if it is 0
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(capacity.min(DEFAULT_ENTRIES))),
+            capacity,
+        }
+    }
+
+    /// Keeps a request, returns its summary
+    pub fn record(
+        &self,
+        started: SystemTime,
+        url: &str,
+        status: u16,
+        size: Option<u64>,
+        wait: Duration,
+        receive: Duration,
+    ) -> Value {
+        let entry = Entry {
+            started,
+            url: url.to_string(),
+            status,
+            size,
+            wait,
+            receive,
+        };
+        let summary = entry.summary();
+        if let (true, Ok(mut entries)) = (self.capacity > 0, self.entries.lock()) {
+            if entries.len() == self.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+        summary
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Path, status, size and duration of the kept requests, oldest first
+    pub fn summaries(&self) -> Vec<Value> {
+        self.entries
+            .lock()
+            .map(|entries| entries.iter().map(Entry::summary).collect())
+            .unwrap_or_default()
+    }
+
+    /// HAR 1.2 log of the recorded requests, their urls below `origin`
+    pub fn export(&self, origin: &str) -> Value {
+        let entries: Vec<Value> = self
+            .entries
+            .lock()
+            .map(|entries| entries.iter().map(|entry| entry.to_json(origin)).collect())
+            .unwrap_or_default();
+        json!({
+            "log": {
+                "version": "1.2",
+                "creator": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "pages": [],
+                "entries": entries,
+            }
+        })
+    }
+}
+
+impl Entry {
+    fn summary(&self) -> Value {
+        json!({
+            "started": iso_date(self.started),
+            "path": self.url,
+            "status": self.status,
+            "size": self.size,
+            "time": (self.wait + self.receive).as_secs_f64() * 1000.0,
+        })
+    }
+
+    fn to_json(&self, origin: &str) -> Value {
+        let wait = self.wait.as_secs_f64() * 1000.0;
+        let receive = self.receive.as_secs_f64() * 1000.0;
+        let size = self.size.map_or(-1, |size| size as i64);
+        let mime_type = mime_type(Path::new(&self.url));
+        json!({
+            "startedDateTime": iso_date(self.started),
+            "time": wait + receive,
+            "request": {
+                "method": "GET",
+                "url": format!("{}{}", origin, self.url),
+                "httpVersion": "HTTP/1.1",
+                "cookies": [],
+                "headers": [],
+                "queryString": [],
+                "headersSize": -1,
+                "bodySize": 0,
+            },
+            "response": {
+                "status": self.status,
+                "statusText": match self.status {
+                    200 => "OK",
+                    206 => "Partial Content",
+                    _ => "Not Found",
+                },
+                "httpVersion": "HTTP/1.1",
+                "cookies": [],
+                "headers": [],
+                "content": { "size": size.max(0), "mimeType": mime_type },
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": size,
+            },
+            "cache": {},
+            "timings": { "send": 0, "wait": wait, "receive": receive },
+        })
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::OnceLock;
+
+use syntect::highlighting::{Color, ThemeSet};
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+use crate::HighlightTheme;
+
+/// Scopes become classes with this prefix, so they don't collide with the classes of the page
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// Colors of the scope classes, `pre` included so dark themes get a dark background
+pub fn style(theme: HighlightTheme) -> String {
+    let name = match theme {
+        HighlightTheme::Light => "InspiredGitHub",
+        HighlightTheme::Dark => "base16-ocean.dark",
+        HighlightTheme::None => return String::new(),
+    };
+    let Some(theme) = themes().themes.get(name) else {
+        return String::new();
+    };
+    let hex = |color: Option<Color>| {
+        color.map_or("inherit".to_string(), |c| {
+            format!("#{:02x}{:02x}{:02x}", c.r, c.g, c.b)
+        })
+    };
+    format!(
+        "pre{{background:{};color:{}}}{}",
+        hex(theme.settings.background),
+        hex(theme.settings.foreground),
+        css_for_theme_with_class_style(theme, CLASS_STYLE).unwrap_or_default()
+    )
+}
+
+/// Escaped `code` with its tokens wrapped in `hl-*` classes of their scopes,
+/// `None` for languages syntect has no syntax for
+pub fn highlight(language: &str, code: &str) -> Option<String> {
+    let language = match language.to_ascii_lowercase().as_str() {
+        "" => return None,
+        // no syntax of their own, but close enough
+        "ts" | "typescript" | "tsx" | "jsx" | "mjs" => "js".to_string(),
+        language => language.to_string(),
+    };
+    let syntaxes = syntaxes();
+    let syntax = syntaxes.find_syntax_by_token(&language)?;
+    let mut html = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        html.parse_html_for_line_which_includes_newline(line).ok()?;
+    }
+    Some(html.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_tokens_by_scope() {
+        let html = highlight("rust", "fn main() { let x = 1; } // hi\n").unwrap();
+        assert!(html.contains("<span class=\"hl-"));
+        assert!(html.contains("hl-comment"));
+        assert!(highlight("TypeScript", "const s = 1;").is_some());
+    }
+
+    #[test]
+    fn escapes_code() {
+        let html = highlight("sql", "SELECT 'x<y'").unwrap();
+        assert!(html.contains("x&lt;y"));
+        assert!(!html.contains("x<y"));
+    }
+
+    #[test]
+    fn leaves_unknown_languages_alone() {
+        assert_eq!(highlight("brainfuck", "+"), None);
+        assert_eq!(highlight("", "+"), None);
+        assert_eq!(style(HighlightTheme::None), "");
+        assert!(style(HighlightTheme::Dark).starts_with("pre{"));
+        assert!(style(HighlightTheme::Light).contains(".hl-comment"));
+    }
+}
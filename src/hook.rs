@@ -0,0 +1,92 @@
+use std::path::Path;
+use std::process::Stdio;
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+/// Keeps this many trailing bytes of a failed command's output for the error message
+const MAX_ERROR_OUTPUT: usize = 2000;
+
+/// Shell command run before a reload, e.g. `["npm", "run", "build"]`.
+///
+/// Runs are serialized, so saving several files in a row never starts overlapping builds.
+pub struct Hook {
+    command: Vec<String>,
+    running: Mutex<()>,
+}
+
+impl Hook {
+    /// `None` if the command is empty
+    pub fn new(command: Vec<String>) -> Option<Self> {
+        if command.is_empty() {
+            return None;
+        }
+        Some(Self {
+            command,
+            running: Mutex::new(()),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.command.join(" ")
+    }
+
+    /// Runs the command in `root`, the error holds the tail of its output
+    pub async fn run(&self, root: &Path, file: &Path) -> Result<(), String> {
+        let _running = self.running.lock().await;
+        let output = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .current_dir(root)
+            .env("LIVE_SERVER_FILE", file)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| format!("failed to run {}: {}", self.command[0], e))?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let mut out = String::from_utf8_lossy(&output.stdout).into_owned();
+        out.push_str(&String::from_utf8_lossy(&output.stderr));
+        let out = out.trim();
+        let mut start = out.len().saturating_sub(MAX_ERROR_OUTPUT);
+        while !out.is_char_boundary(start) {
+            start += 1;
+        }
+        Err(format!("{} ({})", &out[start..], output.status))
+    }
+}
+
+/// Runs `command` in `root` with `input` on stdin, returns its stdout or on failure its stderr
+pub async fn pipe(
+    command: &[String],
+    root: &Path,
+    file: &Path,
+    input: &[u8],
+) -> Result<Vec<u8>, String> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .current_dir(root)
+        .env("LIVE_SERVER_FILE", file)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to run {}: {}", command[0], e))?;
+    // written from another task, a command streaming its output would block otherwise
+    let stdin = child.stdin.take();
+    let input = input.to_vec();
+    let writer = tokio::spawn(async move {
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(&input).await;
+        }
+    });
+    let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+    let _ = writer.await;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,260 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Directories never served or watched, unless re-included
+const DEFAULT_DIRS: [&str; 4] = ["node_modules", ".git", "target", ".svn"];
+/// Files operating systems and editors leave behind
+const DEFAULT_FILES: [&str; 4] = [".DS_Store", "Thumbs.db", "desktop.ini", ".directory"];
+
+/// Ignore file in the workspace root, gitignore syntax
+pub const FILE_NAME: &str = ".liveserverignore";
+
+/// Paths of a workspace that are neither served nor trigger reloads
+pub struct Ignore {
+    root: PathBuf,
+    defaults: bool,
+    /// Re-included paths, they win over every exclusion
+    include: Vec<PathBuf>,
+    /// Patterns of the ignore files with the directory they are relative to, later ones win
+    patterns: Vec<(PathBuf, Pattern)>,
+}
+
+struct Pattern {
+    glob: String,
+    negated: bool,
+    dir_only: bool,
+    /// Contains a slash, so it matches the whole path instead of any name in it
+    anchored: bool,
+}
+
+impl Ignore {
+    /// `include` is relative to `root`
+    pub fn new(root: &Path, defaults: bool, include: &[PathBuf]) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            defaults,
+            include: include.iter().map(|path| root.join(path)).collect(),
+            patterns: vec![],
+        }
+    }
+
+    /// Adds the patterns of a gitignore style file lying in `base`
+    pub fn add_patterns(&mut self, base: &Path, content: &str) {
+        self.patterns.extend(
+            content
+                .lines()
+                .filter_map(Pattern::parse)
+                .map(|pattern| (base.to_path_buf(), pattern)),
+        );
+    }
+
+    /// Whether a path under the root is excluded, paths outside of it never are
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let Ok(rel) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if self.include.iter().any(|include| path.starts_with(include)) {
+            return false;
+        }
+        match self.matched(path) {
+            Some(ignored) => ignored,
+            None => self.defaults && is_default_excluded(rel),
+        }
+    }
+
+    /// Decision of the ignore files, `None` if no pattern matches.
+    /// Patterns don't apply to the root and the directories above it,
+    /// so a `.gitignore` listing the build output doesn't hide all of it when it is served
+    fn matched(&self, path: &Path) -> Option<bool> {
+        let mut state = None;
+        for (base, pattern) in &self.patterns {
+            let Ok(rel) = path.strip_prefix(base) else {
+                continue;
+            };
+            let above_root = self
+                .root
+                .strip_prefix(base)
+                .map_or(0, |root| root.components().count());
+            let names: Vec<_> = rel
+                .components()
+                .filter_map(|c| match c {
+                    Component::Normal(name) => Some(name.to_string_lossy()),
+                    _ => None,
+                })
+                .collect();
+            // a pattern matching a parent directory applies to everything inside it
+            let matches = (above_root + 1..=names.len()).any(|len| {
+                let is_parent = len < names.len();
+                if pattern.dir_only && !is_parent {
+                    return false;
+                }
+                let text = match pattern.anchored {
+                    true => names[..len].join("/"),
+                    false => names[len - 1].to_string(),
+                };
+                glob(pattern.glob.as_bytes(), text.as_bytes())
+            });
+            if matches {
+                state = Some(!pattern.negated);
+            }
+        }
+        state
+    }
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let glob = line.trim_start_matches('/').to_string();
+        (!glob.is_empty()).then_some(Self {
+            glob,
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+}
+
+/// Gitignore globs: `*` and `?` stay within a path segment, `**` spans segments
+pub fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*'] => true,
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len())
+                .filter(|&i| i == 0 || text[i - 1] == b'/')
+                .any(|i| glob(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => {
+            for i in 0..=text.len() {
+                if glob(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        [b'?', rest @ ..] => matches!(text, [c, ..] if *c != b'/') && glob(rest, &text[1..]),
+        [b'[', class @ ..] => {
+            let Some(end) = class.iter().skip(1).position(|c| *c == b']').map(|i| i + 1) else {
+                return text.first() == Some(&b'[') && glob(class, &text[1..]);
+            };
+            let Some(&c) = text.first() else {
+                return false;
+            };
+            let (negated, set) = match class[0] {
+                b'!' | b'^' => (true, &class[1..end]),
+                _ => (false, &class[..end]),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == b'-' {
+                    found |= (set[i]..=set[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated && c != b'/' && glob(&class[end + 1..], &text[1..])
+        }
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => text.first() == Some(c) && glob(rest, &text[1..]),
+    }
+}
+
+fn is_default_excluded(rel: &Path) -> bool {
+    let mut names = rel.components().filter_map(|c| match c {
+        Component::Normal(name) => name.to_str(),
+        _ => None,
+    });
+    let last = rel.file_name().and_then(|name| name.to_str());
+    names.any(|name| DEFAULT_DIRS.contains(&name))
+        || last.is_some_and(|name| DEFAULT_FILES.contains(&name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore(patterns: &str) -> Ignore {
+        let mut ignore = Ignore::new(Path::new("/site"), true, &[]);
+        ignore.add_patterns(Path::new("/site"), patterns);
+        ignore
+    }
+
+    #[test]
+    fn matches_globs_within_and_across_segments() {
+        assert!(glob(b"*.log", b"debug.log"));
+        assert!(!glob(b"*.log", b"logs/debug.log"));
+        assert!(glob(b"docs/**/*.md", b"docs/a/b/notes.md"));
+        assert!(glob(b"docs/**/*.md", b"docs/notes.md"));
+        assert!(glob(b"**/cache", b"a/b/cache"));
+        assert!(glob(b"file?.txt", b"file1.txt"));
+        assert!(!glob(b"file?.txt", b"file/.txt"));
+        assert!(glob(b"[a-c]at", b"bat"));
+        assert!(!glob(b"[!a-c]at", b"bat"));
+        assert!(glob(b"\\*literal", b"*literal"));
+        assert!(!glob(b"\\*literal", b"xliteral"));
+    }
+
+    #[test]
+    fn applies_patterns_like_gitignore() {
+        let ignore = ignore("# build output\n*.log\n!keep.log\n/dist\nbuild/\ndocs/*.tmp\n");
+        assert!(ignore.is_ignored(Path::new("/site/logs/debug.log")));
+        assert!(!ignore.is_ignored(Path::new("/site/keep.log")));
+        // anchored patterns match from the base only
+        assert!(ignore.is_ignored(Path::new("/site/dist/app.js")));
+        assert!(!ignore.is_ignored(Path::new("/site/src/dist/app.js")));
+        // directory patterns match the files inside, not a file of that name
+        assert!(ignore.is_ignored(Path::new("/site/src/build/out.js")));
+        assert!(!ignore.is_ignored(Path::new("/site/build")));
+        assert!(ignore.is_ignored(Path::new("/site/docs/a.tmp")));
+        assert!(!ignore.is_ignored(Path::new("/site/docs/sub/a.tmp")));
+        assert!(!ignore.is_ignored(Path::new("/elsewhere/debug.log")));
+    }
+
+    #[test]
+    fn excludes_junk_by_default_unless_re_included() {
+        let defaults = ignore("");
+        assert!(defaults.is_ignored(Path::new("/site/node_modules/pkg/index.js")));
+        assert!(defaults.is_ignored(Path::new("/site/assets/.DS_Store")));
+        assert!(!defaults.is_ignored(Path::new("/site/index.html")));
+
+        let negated = ignore("!node_modules/");
+        assert!(!negated.is_ignored(Path::new("/site/node_modules/pkg/index.js")));
+
+        let include = [PathBuf::from("node_modules/pkg")];
+        let mut included = Ignore::new(Path::new("/site"), true, &include);
+        included.add_patterns(Path::new("/site"), "*.js");
+        assert!(!included.is_ignored(Path::new("/site/node_modules/pkg/index.js")));
+        assert!(included.is_ignored(Path::new("/site/node_modules/other/index.js")));
+
+        let plain = Ignore::new(Path::new("/site"), false, &[]);
+        assert!(!plain.is_ignored(Path::new("/site/node_modules/pkg/index.js")));
+    }
+
+    #[test]
+    fn serves_a_root_the_patterns_ignore() {
+        let mut ignore = Ignore::new(Path::new("/site/dist"), true, &[]);
+        ignore.add_patterns(Path::new("/site"), "dist/\n/dist\n*.map\n");
+        assert!(!ignore.is_ignored(Path::new("/site/dist/index.html")));
+        // the patterns still match what is inside
+        assert!(ignore.is_ignored(Path::new("/site/dist/app.js.map")));
+        assert!(ignore.is_ignored(Path::new("/site/dist/dist/app.js")));
+    }
+}
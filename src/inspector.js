@@ -0,0 +1,39 @@
+// Live table of the requests answered by the workspace server, `config` is prepended by the server
+// with the relay port and the requests so far. New requests arrive as `request` events of the relay.
+const rows = document.querySelector("tbody");
+const filter = document.querySelector("input");
+
+const size = (bytes) => {
+  if (bytes === null) return "-";
+  if (bytes < 1024) return `${bytes} B`;
+  return `${(bytes / 1024).toFixed(1)} kB`;
+};
+
+const show = (row) => {
+  row.hidden = !row.cells[1].textContent.includes(filter.value);
+};
+
+const add = (request) => {
+  const row = rows.insertRow(0);
+  const cells = [
+    new Date(request.started).toLocaleTimeString(),
+    request.path,
+    request.status,
+    size(request.size),
+    `${request.time.toFixed(1)} ms`,
+  ];
+  for (const text of cells) row.insertCell().textContent = text;
+  if (request.status >= 400) row.className = "failed";
+  show(row);
+  while (rows.rows.length > config.limit) rows.deleteRow(-1);
+};
+
+config.requests.forEach(add);
+filter.addEventListener("input", () => Array.from(rows.rows).forEach(show));
+document.querySelector("button").addEventListener("click", () => rows.replaceChildren());
+
+const events = new EventSource(`${location.protocol}//${location.hostname}:${config.relay}/events`);
+events.onmessage = (message) => {
+  const event = JSON.parse(message.data);
+  if (event.type === "request") add(event.request);
+};
@@ -0,0 +1,29 @@
+use serde_json::{json, Value};
+
+/// Url of the inspector page of a workspace
+pub const PATH: &str = "__inspector";
+const SCRIPT: &str = include_str!("inspector.js");
+
+const STYLE: &str = "body { font: 13px system-ui, sans-serif; margin: 0; }
+header { display: flex; gap: 8px; padding: 8px; border-bottom: 1px solid #ddd; }
+input { flex: 1; }
+table { width: 100%; border-collapse: collapse; }
+th, td { text-align: left; padding: 2px 8px; border-bottom: 1px solid #eee; white-space: nowrap; }
+td:nth-child(2) { width: 100%; white-space: normal; word-break: break-all; }
+td:nth-child(n + 3) { text-align: right; }
+.failed { color: #c00; }";
+
+/// Page listing the latest `requests` and the ones the relay at `relay` announces, up to `limit`
+pub fn page(relay: u16, requests: Vec<Value>, limit: usize) -> String {
+    let config = json!({ "relay": relay, "requests": requests, "limit": limit });
+    format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\"><title>Requests</title><style>{}</style></head>\
+<body><header><input placeholder=\"Filter paths\"><button>Clear</button></header>\
+<table><thead><tr><th>Time</th><th>Path</th><th>Status</th><th>Size</th><th>Duration</th></tr></thead><tbody></tbody></table>\
+<script>const config = {};\n{}</script></body></html>",
+        STYLE,
+        // a path can't close the script
+        config.to_string().replace('<', "\\u003c"),
+        SCRIPT
+    )
+}
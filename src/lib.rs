@@ -0,0 +1,443 @@
+//! Language server that previews workspaces in the browser and reloads them as they are edited.
+//!
+//! [`lsp`] runs it over stdin and stdout like the binary does, [`serve_on`] over another transport.
+//! Every option of [`Config`] is passed as `initializationOptions`, or set up front with [`Config::builder`]:
+//!
+//! ```no_run
+//! use live_server_lsp::{serve_on, Config, OpenOnStart};
+//!
+//! # async fn run() -> serde_json::Result<()> {
+//! let config = Config::builder()
+//!     .start_port(8080)
+//!     .open_on_start(OpenOnStart::First)
+//!     .option("serve_dir", "dist")
+//!     .build()?;
+//! serve_on(config, tokio::io::stdin(), tokio::io::stdout()).await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Backend::service`] keeps a handle on the language server, e.g. to find the servers of the workspaces.
+//! Each [`LspFileService`] implements the file system interface of `rusty_live_server`,
+//! which reads files the way browsers get them:
+//!
+//! ```no_run
+//! use live_server_lsp::{Backend, Config};
+//! use tower_lsp::Server;
+//!
+//! # async fn run() {
+//! let (service, socket) = Backend::service(Config::default());
+//! let backend = service.inner().clone();
+//! tokio::spawn(Server::new(tokio::io::stdin(), tokio::io::stdout(), socket).serve(service));
+//! // once the editor initialized the workspace folders
+//! for workspace in backend.services().await {
+//!     println!("{} at {}", workspace.root().display(), workspace.url().await);
+//! }
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub use lsp::{lsp, serve_on, Backend, LspFileService};
+pub use middleware::Middleware;
+use network::{Latency, Throttle};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+pub use tunnel::TunnelRelay;
+
+pub(crate) mod browser;
+pub(crate) mod bundle;
+pub(crate) mod cache;
+pub(crate) mod charset;
+pub(crate) mod compile;
+pub(crate) mod completion;
+pub(crate) mod config;
+pub(crate) mod csp;
+pub(crate) mod css;
+pub(crate) mod debounce;
+pub(crate) mod env;
+pub(crate) mod forward;
+pub(crate) mod gateway;
+pub(crate) mod graph;
+pub(crate) mod har;
+pub(crate) mod headers;
+pub(crate) mod highlight;
+pub(crate) mod hook;
+pub(crate) mod ignore;
+pub(crate) mod inspector;
+pub(crate) mod limits;
+pub(crate) mod log;
+pub(crate) mod lsp;
+pub(crate) mod markdown;
+pub(crate) mod middleware;
+pub(crate) mod mock;
+pub(crate) mod mount;
+pub(crate) mod network;
+pub(crate) mod overlay;
+pub(crate) mod position;
+pub(crate) mod proxy;
+pub(crate) mod random;
+pub(crate) mod relay;
+pub(crate) mod responsive;
+pub(crate) mod rope;
+pub(crate) mod rules;
+pub(crate) mod screenshot;
+pub(crate) mod scripts;
+pub(crate) mod share;
+pub(crate) mod sourcemap;
+pub(crate) mod supervise;
+pub(crate) mod tailwind;
+pub(crate) mod templates;
+pub(crate) mod tunnel;
+pub(crate) mod uri;
+pub(crate) mod wasm;
+pub(crate) mod watch;
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenOnStart {
+    #[default]
+    None,
+    /// Every workspace
+    All,
+    /// The first workspace folder only
+    First,
+}
+
+/// Whether directory urls end with a slash
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    /// Redirect `/docs` to `/docs/`
+    Add,
+    /// Redirect `/docs/` to `/docs`
+    Remove,
+    /// Serve both without redirecting
+    #[default]
+    Both,
+}
+
+/// How the editor sends the changes of open documents in eager mode
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TextSync {
+    /// Only the edited ranges
+    #[default]
+    Incremental,
+    /// The whole document with every change
+    Full,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PortStrategy {
+    /// Report the conflict and don't start the server
+    Fail,
+    /// Try the following ports, up to `port_attempts` of them
+    #[default]
+    Increment,
+    /// Let the system pick a free port
+    Random,
+}
+
+/// Colors of code blocks in rendered markdown
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightTheme {
+    #[default]
+    Light,
+    Dark,
+    /// Leave code blocks uncolored
+    None,
+}
+
+/// What the proxy does with requests to its upstreams
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    /// Forward every request
+    #[default]
+    Forward,
+    /// Forward and write every response to the recordings
+    Record,
+    /// Answer from the recordings without contacting the upstreams
+    Replay,
+}
+
+/// Additional server of a workspace, see `servers`
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ServerConfig {
+    /// Served directory, relative to the workspace root
+    dir: PathBuf,
+    /// Port of this server [Default: next free port]
+    port: Option<u16>,
+}
+
+/// Options of the workspace servers, from `initializationOptions` and the configuration files
+#[derive(Deserialize, Serialize, Default)]
+pub struct Config {
+    /// Set if update on save or keypress [Default: false]
+    lazy: Option<bool>,
+    /// `"incremental"` or `"full"`, which resends the whole document with every change for clients whose incremental sync is unreliable [Default: incremental]
+    text_sync: Option<TextSync>,
+    /// Bytes of open documents kept in memory, beyond it the least recently used ones are served as saved [Default: 67108864]
+    overlay_max_size: Option<usize>,
+    /// Start the server of every workspace folder right away. Otherwise it starts with `startProjectServer`, `openProjectWeb` or once an html file in it is opened [Default: true]
+    auto_start: Option<bool>,
+    /// Minutes without requests or connected browsers after which a server stops, starting again like one not started automatically [Default: never]
+    idle_stop: Option<u64>,
+    /// 0.0.0.0 or 127.0.0.1 [Default: false]
+    public: Option<bool>,
+    /// Address the servers are reachable at, e.g. the ip of one network interface, `0.0.0.0` or `::` [Default: 127.0.0.1, 0.0.0.0 if `public`]
+    bind: Option<String>,
+    /// Set the port number
+    start_port: Option<u16>,
+    /// Directory to write one rotating log file per workspace into [Default: disabled]
+    log_dir: Option<PathBuf>,
+    /// Size in bytes after which a log file is rotated [Default: 1048576]
+    log_max_size: Option<u64>,
+    /// Also write `<workspace>.access.log` in the combined log format to `log_dir` [Default: false]
+    access_log: Option<bool>,
+    /// Render `.hbs`/`.tera` templates when the matching `.html` is requested [Default: false]
+    templates: Option<bool>,
+    /// Directory of partials, relative to the workspace root [Default: partials]
+    template_partials: Option<PathBuf>,
+    /// Json file used as template context, relative to the workspace root [Default: data.json]
+    template_data: Option<PathBuf>,
+    /// Render `page.md` when `page.html` is requested, scrolled along with the editor cursor [Default: false]
+    markdown: Option<bool>,
+    /// Render `page.adoc` with `asciidoc_command` when `page.html` is requested [Default: false]
+    asciidoc: Option<bool>,
+    /// Render `page.rst` with `rst_command` when `page.html` is requested, without a sphinx build [Default: false]
+    rst: Option<bool>,
+    /// Directory of the mermaid bundle markdown diagrams load, relative to the workspace [Default: node_modules/mermaid/dist]
+    mermaid_dir: Option<PathBuf>,
+    /// Directory of the KaTeX bundle markdown formulas load, relative to the workspace [Default: node_modules/katex/dist]
+    katex_dir: Option<PathBuf>,
+    /// Colors markdown code blocks are highlighted with [Default: light]
+    highlight_theme: Option<HighlightTheme>,
+    /// Mirror scrolls, clicks and form input between all browsers of a workspace [Default: false]
+    sync: Option<bool>,
+    /// Forward browser console output and uncaught errors to the editor log [Default: false]
+    console: Option<bool>,
+    /// Hot swap changed javascript modules that accept updates, reload the page otherwise [Default: false]
+    hmr: Option<bool>,
+    /// Notify the editor with `liveServer/clientConnected` and `liveServer/clientDisconnected` when browsers come and go [Default: false]
+    presence: Option<bool>,
+    /// Navigate browsers to the page of the document focused in the editor [Default: false]
+    follow: Option<bool>,
+    /// Also send reloads over server sent events, for networks blocking websockets [Default: false]
+    sse_reload: Option<bool>,
+    /// Command served stylesheets are piped through, e.g. `["npx", "postcss"]` [Default: disabled]
+    css_command: Option<Vec<String>>,
+    /// Command compiling `page.pug` on stdin to `page.html`, e.g. `["npx", "pug"]` [Default: disabled]
+    pug_command: Option<Vec<String>>,
+    /// Command compiling `page.haml` on stdin to `page.html`, e.g. `["haml"]` [Default: disabled]
+    haml_command: Option<Vec<String>>,
+    /// Command rendering `page.adoc` on stdin to `page.html` [Default: ["asciidoctor", "-o", "-", "-"]]
+    asciidoc_command: Option<Vec<String>>,
+    /// Command rendering `page.rst` on stdin to `page.html` [Default: ["rst2html"]]
+    rst_command: Option<Vec<String>>,
+    /// Entrypoints bundled with esbuild and served from memory by url, e.g. `{"/app.js": "src/main.ts"}` [Default: none]
+    bundle: Option<HashMap<String, PathBuf>>,
+    /// Command bundling an entrypoint to stdout, the entrypoint and esbuild flags are appended [Default: ["npx", "esbuild"]]
+    esbuild_command: Option<Vec<String>>,
+    /// Run the tailwind CLI in watch mode [Default: if a `tailwind.config.*` exists in the workspace]
+    tailwind: Option<bool>,
+    /// Stylesheet with the tailwind directives, relative to the workspace [Default: tailwind's base styles]
+    tailwind_input: Option<PathBuf>,
+    /// Stylesheet tailwind writes, relative to the served directory [Default: tailwind.css]
+    tailwind_output: Option<PathBuf>,
+    /// Command of the tailwind CLI, without its arguments [Default: ["npx", "tailwindcss"]]
+    tailwind_command: Option<Vec<String>>,
+    /// Url rewrites like `{"/blog/*": "/blog.html"}`, a trailing `*` matches every url with that prefix
+    rewrites: Option<HashMap<String, String>>,
+    /// Only changes of files with these extensions reload, html, css and js always do [Default: all]
+    reload_include: Option<Vec<String>>,
+    /// Changes of files with these extensions never reload, e.g. `["md", "log"]` [Default: none]
+    reload_exclude: Option<Vec<String>>,
+    /// Neither serve nor reload for `node_modules`, `.git`, `target` and files like `.DS_Store` [Default: true]
+    default_exclusions: Option<bool>,
+    /// Also exclude what `.gitignore` of the workspace root ignores, `.liveserverignore` can re-include it and `false` serves it all [Default: true]
+    gitignore: Option<bool>,
+    /// Paths relative to the root served and reloaded despite the exclusions, e.g. `["node_modules/my-lib"]` [Default: none]
+    include: Option<Vec<PathBuf>>,
+    /// Milliseconds to wait before a change reloads per extension, e.g. `{"html": 500, "*": 0}` [Default: none]
+    reload_delay: Option<HashMap<String, u64>>,
+    /// Milliseconds reloads are collected for after the first, so saving many files at once reloads once, 0 reloads right away [Default: 50]
+    reload_batch: Option<u64>,
+    /// Connections a server of a workspace and its relay each accept at once, more get a `503` [Default: unlimited]
+    max_connections: Option<usize>,
+    /// Connections a server and its relay each accept at once from one address [Default: unlimited]
+    max_connections_per_address: Option<usize>,
+    /// Seconds an idle connection to a server is kept open for the next request, `0` closes it after every response [Default: 5]
+    keep_alive_timeout: Option<u64>,
+    /// Host names browsers may reach the servers and the relay under besides localhost and ip addresses, the servers also accept the hosts of `public_url` and the tunnel [Default: none]
+    allowed_hosts: Option<Vec<String>>,
+    /// Response headers per glob of the url path, like `{"*.woff2": {"Cache-Control": "max-age=31536000"}}` [Default: none]
+    header_rules: Option<HashMap<String, HashMap<String, String>>>,
+    /// Simulated bandwidth, `"2G"`, `"3G"`, `"slow-4G"`, `"4G"` or kilobits per second [Default: unthrottled]
+    throttle: Option<Throttle>,
+    /// Milliseconds every response is delayed by, fixed like `300` or jittered like `{"min": 100, "max": 800}` [Default: none]
+    latency: Option<Latency>,
+    /// Start with the offline simulation on, see `setProjectOffline` [Default: false]
+    offline: Option<bool>,
+    /// Files served for a directory url in order of preference, e.g. `["index.html", "default.html"]` [Default: `["index.html"]`]
+    index_files: Option<Vec<String>>,
+    /// Serve `about.html` for `/about` like most static hosts [Default: false]
+    clean_urls: Option<bool>,
+    /// Number of small assets like icons and fonts kept in memory, `0` disables the cache [Default: 256]
+    cache_entries: Option<usize>,
+    /// Size in bytes up to which an asset is cached [Default: 262144]
+    cache_max_size: Option<usize>,
+    /// Send `app.js.br` or `app.js.gz` in place of `app.js` to browsers accepting them, unless the variant is older [Default: true]
+    precompressed: Option<bool>,
+    /// `"add"` redirects directory urls like `/docs` to `/docs/`, `"remove"` the other way round, `"both"` serves both like the server alone [Default: both]
+    trailing_slash: Option<TrailingSlash>,
+    /// Size in bytes from which assets are sent from disk in chunks instead of read into memory, which also answers `Range` requests [Default: 1048576]
+    stream_threshold: Option<u64>,
+    /// Latest requests kept per workspace for `exportProjectHar`, 0 keeps none [Default: 1000]
+    har_entries: Option<usize>,
+    /// Serve a live table of the requests at `/__inspector` [Default: false]
+    inspector: Option<bool>,
+    /// Show a toolbar in served pages with the connection, the last reload, a reload button and a live reload toggle [Default: false]
+    toolbar: Option<bool>,
+    /// Serve `/__responsive`, the site in a frame per viewport width [Default: false]
+    responsive: Option<bool>,
+    /// Viewport widths of `/__responsive` in css pixels [Default: [375, 768, 1280]]
+    responsive_widths: Option<Vec<u32>>,
+    /// Minutes `shareProjectTemporarily` keeps a workspace reachable [Default: 60]
+    share_ttl: Option<u64>,
+    /// Control address `host:port` of a `live-server-relay` to keep a tunnel open to, for previews across networks [Default: disabled]
+    tunnel: Option<String>,
+    /// Path segment of the workspace on the relay [Default: the workspace name]
+    tunnel_name: Option<String>,
+    /// Secret the relay was started with [Default: none]
+    tunnel_secret: Option<String>,
+    /// Command run on save before reloading, e.g. `["npm", "run", "build"]`. Reloads only if it succeeds [Default: disabled]
+    on_save: Option<Vec<String>>,
+    /// Rebuild the wasm module when a Rust source is saved, reloading only on success [Default: if `Cargo.toml` builds a `cdylib` or targets wasm]
+    wasm: Option<bool>,
+    /// Command building the wasm module [Default: ["wasm-pack", "build", "--target", "web"]]
+    wasm_command: Option<Vec<String>>,
+    /// Isolate pages with `Cross-Origin-Opener-Policy` and `Cross-Origin-Embedder-Policy` headers, for `SharedArrayBuffer` and wasm threads [Default: false]
+    cross_origin_isolation: Option<bool>,
+    /// Send the headers of a production host, like `X-Content-Type-Options` and `Referrer-Policy` [Default: false]
+    security_headers: Option<bool>,
+    /// Mock API definition answered by the server, relative to the workspace [Default: `mock-api.yaml`, `mock-api.yml` or `mock-api.json` if present]
+    mock_api: Option<PathBuf>,
+    /// Url prefix of the mock API routes [Default: /api]
+    mock_api_prefix: Option<String>,
+    /// Url prefixes forwarded to upstream APIs by the gateway, like `{"/api": "http://localhost:8080"}` [Default: none]
+    proxy: Option<HashMap<String, String>>,
+    /// `forward`, `record` responses to `proxy_recordings`, or `replay` them without the upstream [Default: forward]
+    proxy_mode: Option<ProxyMode>,
+    /// Directory of the recorded responses, relative to the workspace [Default: recordings]
+    proxy_recordings: Option<PathBuf>,
+    /// Directories outside the workspace served at `/<dir name>/` and reloaded on change, relative to the root [Default: none]
+    shared_dirs: Option<Vec<PathBuf>>,
+    /// Replace `{{ env.NAME }}` in served html with environment variables [Default: false]
+    env: Option<bool>,
+    /// Further addresses the server answers on, e.g. the LAN address `["192.168.1.20"]`, sharing its port and reloads [Default: none]
+    listen: Option<Vec<String>>,
+    /// Base url of a reverse proxy serving the workspace, e.g. `https://site.localhost`, opened instead of the local port [Default: none]
+    public_url: Option<String>,
+    /// Unix socket the workspace server answers on besides its port, for a reverse proxy like Caddy or nginx, relative to the workspace. Not supported on Windows [Default: none]
+    unix_socket: Option<PathBuf>,
+    /// Charset declared for html files that aren't utf-8 and declare none, e.g. `shift_jis` [Default: none]
+    charset: Option<String>,
+    /// Content-Security-Policy header of served pages, `{live_server}` expands to the sources live reload needs [Default: none]
+    csp: Option<String>,
+    /// Url prefixes served from other directories like `{"/assets": "../design/exports"}`, relative to the root [Default: none]
+    mounts: Option<HashMap<String, PathBuf>>,
+    /// Open the browser when the servers start, `"all"`, `"first"` or `"none"` [Default: none]
+    open_on_start: Option<OpenOnStart>,
+    /// Let the editor open pages with `window/showDocument` instead of launching a browser, e.g. over ssh [Default: false]
+    open_in_editor: Option<bool>,
+    /// Browser executable pages are opened in, e.g. `firefox` [Default: system default]
+    browser: Option<String>,
+    /// Extra arguments passed to `browser` before the url [Default: none]
+    browser_args: Option<Vec<String>>,
+    /// Profile directory `browser` is started with [Default: its default profile]
+    browser_profile: Option<PathBuf>,
+    /// Open pages in a private window of `browser` [Default: false]
+    browser_incognito: Option<bool>,
+    /// Chromium based browser used by `captureProjectScreenshot` [Default: first one found]
+    headless_browser: Option<String>,
+    /// Directory served over http instead of the workspace root, e.g. `dist` [Default: workspace root]
+    serve_dir: Option<PathBuf>,
+    /// Sources of `serve_dir`, e.g. `src`. Saving one reloads every page, after `on_save` succeeded [Default: none]
+    watch_dir: Option<PathBuf>,
+    /// File an external build writes when it is done, e.g. `dist/.build-done`. Changes only reload once it changes [Default: none]
+    build_marker: Option<PathBuf>,
+    /// What to do if the port is taken, `"fail"`, `"increment"` or `"random"` [Default: increment]
+    port_strategy: Option<PortStrategy>,
+    /// Ports tried by the `increment` strategy [Default: 10]
+    port_attempts: Option<u16>,
+    /// Additional servers of the workspace like `[{"dir": "docs", "port": 8100}]`, each with its own port and reloads [Default: none]
+    servers: Option<Vec<ServerConfig>>,
+    /// Hooks of the embedding, applied after the ones of `rewrites` and the reload filters
+    #[serde(skip)]
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Config {
+    /// Options set from code, `initializationOptions` and the configuration files override them
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+}
+
+/// Builds a [`Config`] option by option, see [`Config::builder`]
+#[derive(Default)]
+pub struct ConfigBuilder {
+    options: Map<String, Value>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl ConfigBuilder {
+    pub fn lazy(self, lazy: bool) -> Self {
+        self.option("lazy", lazy)
+    }
+
+    pub fn public(self, public: bool) -> Self {
+        self.option("public", public)
+    }
+
+    pub fn bind(self, address: IpAddr) -> Self {
+        self.option("bind", address.to_string())
+    }
+
+    pub fn start_port(self, port: u16) -> Self {
+        self.option("start_port", port)
+    }
+
+    pub fn open_on_start(self, open: OpenOnStart) -> Self {
+        self.option("open_on_start", open)
+    }
+
+    /// Sets any other option by its name in `initializationOptions`
+    pub fn option(mut self, name: &str, value: impl Serialize) -> Self {
+        let value = serde_json::to_value(value).unwrap_or_default();
+        self.options.insert(name.to_string(), value);
+        self
+    }
+
+    /// Registers hooks into serving and reloading of the workspaces [`Middleware::applies_to`]
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Fails if an option has the wrong type
+    pub fn build(self) -> serde_json::Result<Config> {
+        let config: Config = serde_json::from_value(Value::Object(self.options))?;
+        Ok(Config {
+            middleware: self.middleware,
+            ..config
+        })
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Caps on concurrent connections to a workspace server or its relay, `None` is unlimited
+#[derive(Default, Clone, Copy)]
+pub struct Limits {
+    pub total: Option<usize>,
+    pub per_address: Option<usize>,
+}
+
+/// Open connections of a listener per client address
+pub struct Connections {
+    limits: Limits,
+    /// `None` counts the connections without an address, like those through a unix socket
+    open: Mutex<HashMap<Option<IpAddr>, usize>>,
+}
+
+/// Counts as open until dropped
+pub struct Connection {
+    connections: Arc<Connections>,
+    address: Option<IpAddr>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Ok(mut open) = self.connections.open.lock() {
+            if let Some(count) = open.get_mut(&self.address) {
+                *count -= 1;
+                if *count == 0 {
+                    open.remove(&self.address);
+                }
+            }
+        }
+    }
+}
+
+impl Connections {
+    pub fn new(limits: Limits) -> Arc<Self> {
+        Arc::new(Self {
+            limits,
+            open: Default::default(),
+        })
+    }
+
+    /// Registers a connection from `address`, `None` if that exceeds the limits.
+    /// Connections without an address only count towards the total
+    pub fn admit(self: &Arc<Self>, address: Option<IpAddr>) -> Option<Connection> {
+        let mut open = self.open.lock().ok()?;
+        let total: usize = open.values().sum();
+        let from_address = open.get(&address).copied().unwrap_or_default();
+        if self.limits.total.is_some_and(|max| total >= max)
+            || address.is_some()
+                && self
+                    .limits
+                    .per_address
+                    .is_some_and(|max| from_address >= max)
+        {
+            return None;
+        }
+        *open.entry(address).or_default() += 1;
+        Some(Connection {
+            connections: self.clone(),
+            address,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn admits_up_to_the_limits_until_connections_close() {
+        let connections = Connections::new(Limits {
+            total: Some(3),
+            per_address: Some(2),
+        });
+        let phone = Some(IpAddr::from(Ipv4Addr::new(192, 168, 1, 20)));
+        let laptop = Some(IpAddr::from(Ipv4Addr::new(192, 168, 1, 21)));
+        let first = connections.admit(phone).unwrap();
+        let _second = connections.admit(phone).unwrap();
+        assert!(connections.admit(phone).is_none());
+        let _third = connections.admit(laptop).unwrap();
+        assert!(connections.admit(laptop).is_none());
+        drop(first);
+        assert!(connections.admit(phone).is_some());
+    }
+
+    #[test]
+    fn limits_connections_without_an_address_by_the_total_only() {
+        let connections = Connections::new(Limits {
+            total: Some(2),
+            per_address: Some(1),
+        });
+        let _first = connections.admit(None).unwrap();
+        let _second = connections.admit(None).unwrap();
+        assert!(connections.admit(None).is_none());
+    }
+}
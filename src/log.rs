@@ -0,0 +1,208 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::fs::{create_dir_all, rename, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tower_lsp::lsp_types::MessageType;
+
+/// Number of rotated files kept next to the active log (`name.log.1` .. `name.log.3`)
+const KEEP_ROTATED: usize = 3;
+
+/// Default size in bytes after which a log file gets rotated
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+
+/// Per workspace log sink. A default `Logger` is disabled and drops every message.
+#[derive(Clone, Default)]
+pub struct Logger {
+    file: Option<Arc<Mutex<RotatingFile>>>,
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    size: u64,
+    file: File,
+}
+
+impl Logger {
+    pub async fn open(dir: &Path, name: &str, max_size: u64) -> std::io::Result<Self> {
+        create_dir_all(dir).await?;
+        let path = dir.join(format!("{}.log", sanitize(name)));
+        let file = open_append(&path).await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            file: Some(Arc::new(Mutex::new(RotatingFile {
+                path,
+                max_size,
+                size,
+                file,
+            }))),
+        })
+    }
+
+    pub async fn log(&self, ty: MessageType, message: &str) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let line = format!("{} [{}] {}\n", timestamp(), level(ty), message);
+        file.lock().await.write(&line).await;
+    }
+}
+
+/// Per workspace access log in the combined log format, a default `AccessLog` is disabled.
+///
+/// Fields the file system interface doesn't see, like the client address or the referer, are written as `-`.
+#[derive(Clone, Default)]
+pub struct AccessLog {
+    file: Option<Arc<Mutex<RotatingFile>>>,
+}
+
+impl AccessLog {
+    pub async fn open(dir: &Path, name: &str, max_size: u64) -> std::io::Result<Self> {
+        create_dir_all(dir).await?;
+        let path = dir.join(format!("{}.access.log", sanitize(name)));
+        let file = open_append(&path).await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            file: Some(Arc::new(Mutex::new(RotatingFile {
+                path,
+                max_size,
+                size,
+                file,
+            }))),
+        })
+    }
+
+    pub async fn record(&self, url: &str, status: u16, bytes: Option<u64>) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let bytes = bytes.map_or("-".to_string(), |bytes| bytes.to_string());
+        let line = format!(
+            "- - - [{}] \"GET {} HTTP/1.1\" {} {} \"-\" \"-\"\n",
+            clf_date(),
+            url,
+            status,
+            bytes
+        );
+        file.lock().await.write(&line).await;
+    }
+}
+
+impl RotatingFile {
+    async fn write(&mut self, line: &str) {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            // keep writing into the current file if rotation fails
+            let _ = self.rotate().await;
+        }
+        if self.file.write_all(line.as_bytes()).await.is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+
+    async fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush().await?;
+        for index in (1..KEEP_ROTATED).rev() {
+            let _ = rename(self.rotated(index), self.rotated(index + 1)).await;
+        }
+        rename(&self.path, self.rotated(1)).await?;
+        self.file = open_append(&self.path).await?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+}
+
+async fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}.{:03}", now.as_secs(), now.subsec_millis())
+}
+
+/// Current time like `10/Oct/2000:13:55:36 +0000`
+fn clf_date() -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_date(secs / 86400);
+    let time = secs % 86400;
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// `time` like `2000-10-10T13:55:36.123Z`
+pub fn iso_date(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (year, month, day) = civil_date(secs / 86400);
+    let time = secs % 86400;
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        since.subsec_millis()
+    )
+}
+
+/// Year, month and day of a day count since 1970-01-01, from Howard Hinnant's date algorithms
+fn civil_date(days: u64) -> (i64, i64, i64) {
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn level(ty: MessageType) -> &'static str {
+    match ty {
+        MessageType::ERROR => "error",
+        MessageType::WARNING => "warn",
+        MessageType::INFO => "info",
+        _ => "log",
+    }
+}
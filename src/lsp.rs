@@ -1,249 +1,468 @@
-use rusty_live_server::{Dir, Error, File, FileSystemInterface, Signal};
-use serde_json::Value;
+use dashmap::{DashMap, DashSet};
+use rusty_live_server::{Dir, FileSystemInterface, Signal};
+use serde_json::{json, Value};
 use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::fs::{read_dir, File as TokioFile, ReadDir};
-use tokio::io::AsyncReadExt;
-use tokio::sync::{Mutex, RwLock};
-use tokio::task::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{watch, Mutex, RwLock};
+use tokio::task::{AbortHandle, JoinHandle};
 use tower_lsp::lsp_types::{
-    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, CodeActionResponse, Command,
-    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-    DidSaveTextDocumentParams, ExecuteCommandParams, InitializeParams, InitializeResult,
-    InitializedParams, MessageType, Position, SaveOptions, ServerCapabilities,
-    TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
-    TextDocumentSyncSaveOptions,
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, CodeActionResponse,
+    CodeLens, CodeLensOptions, CodeLensParams, Command, CompletionItem, CompletionItemKind,
+    CompletionOptions, CompletionParams, CompletionResponse, CreateFilesParams, DeleteFilesParams,
+    DidChangeTextDocumentParams, DidChangeWatchedFilesParams, DidChangeWorkspaceFoldersParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DidSaveTextDocumentParams,
+    ExecuteCommandParams, FileChangeType, FileOperationFilter, FileOperationPattern,
+    FileOperationRegistrationOptions, InitializeParams, InitializeResult, InitializedParams,
+    MessageActionItem, MessageType, OneOf, Range, RenameFilesParams, SaveOptions,
+    ServerCapabilities, TextDocumentContentChangeEvent, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions, Url,
+    WorkspaceFileOperationsServerCapabilities, WorkspaceFolder, WorkspaceFoldersServerCapabilities,
+    WorkspaceServerCapabilities,
 };
 
-use tower_lsp::{Client, LanguageServer, LspService, Server};
-
-use crate::Config;
-
-struct Backend {
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService, Server};
+
+use crate::cache::AssetCache;
+use crate::completion;
+use crate::graph::DependencyGraph;
+use crate::har::Har;
+use crate::ignore::Ignore;
+use crate::log::{AccessLog, Logger};
+use crate::middleware::{Middleware, Middlewares};
+use crate::mount::Mounts;
+use crate::overlay::Overlay;
+use crate::position::{get_byte_index_from_position, PositionEncoding};
+use crate::proxy::Proxy;
+use crate::relay::{ClientFeatures, Relay};
+use crate::rope::Rope;
+use crate::scripts;
+use crate::sourcemap::SourceMaps;
+use crate::uri::{uri_to_path, url_path, virtual_route};
+use crate::{config, Config, OpenOnStart, TextSync};
+
+mod addresses;
+mod browsers;
+mod builds;
+mod clients;
+mod commands;
+mod documents;
+mod follow;
+mod http;
+mod reload;
+mod server;
+mod service;
+mod sharing;
+mod simulation;
+mod workspace;
+
+use addresses::Addresses;
+use browsers::Browsers;
+use builds::Builds;
+use documents::Documents;
+use http::Http;
+use reload::Reloads;
+use sharing::Sharing;
+use simulation::Simulation;
+
+/// Longest shutdown waits for requests being answered
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Failures of a server in a row after which the user is told, instead of only the log
+const REPORTED_FAILURES: u32 = 3;
+
+/// Language server, runs one or more live servers per workspace folder
+#[derive(Clone)]
+pub struct Backend {
     port: Arc<RwLock<u16>>,
-    public: Arc<RwLock<bool>>,
+    /// Address from `bind`, or `public`. Servers bind it if it is loopback or unspecified, other addresses are forwarded
+    bind: Arc<RwLock<IpAddr>>,
     eager: Arc<RwLock<bool>>,
+    encoding: Arc<RwLock<PositionEncoding>>,
+    /// Whether the client registers file watchers on request, and supports relative patterns for them
+    watch_files: Arc<RwLock<(bool, bool)>>,
+    /// Whether the client opens urls with `window/showDocument`
+    show_document: Arc<RwLock<bool>>,
     client: Client,
     threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
-    workspace_folders: Arc<Mutex<HashMap<PathBuf, (String, LspFileService)>>>,
+    /// Server, relay and watcher tasks of each workspace, restarted when its configuration changes
+    tasks: Arc<Mutex<HashMap<PathBuf, Vec<JoinHandle<()>>>>>,
+    /// Workspaces opened in the browser once their server is up
+    open_on_start: Arc<RwLock<Vec<PathBuf>>>,
+    /// `initializationOptions` over the options of the embedding [`Config`], the configuration files are layered over them
+    options: Arc<RwLock<Value>>,
+    workspace_folders: Arc<RwLock<HashMap<PathBuf, (String, LspFileService)>>>,
+    /// Routes of open documents without a file on disk
+    virtual_documents: Arc<RwLock<HashMap<Url, PathBuf>>>,
+    /// Latest version of every open document. Versions only have to increase, editors like Neovim skip some,
+    /// one that doesn't means changes were lost
+    versions: Arc<DashMap<Url, i32>>,
+    /// Started without a workspace and no page opened yet, the first one is served from its directory
+    single_file: Arc<RwLock<bool>>,
+    /// Hooks registered by the embedding
+    middleware: Arc<[Arc<dyn Middleware>]>,
 }
 
+/// Files of one live server as a browser gets them: overlay, templates, transformations and mounts applied
 #[derive(Clone)]
-struct LspFileService {
-    eager: bool,
-    port: Arc<Mutex<u16>>,
+pub struct LspFileService {
+    port: Arc<RwLock<u16>>,
+    /// Key in `workspace_folders`, the workspace folder or the root of an extra server
+    id: Arc<PathBuf>,
+    /// Workspace folder, commands run in it
+    workspace: Arc<PathBuf>,
+    /// Directory served over http, the workspace folder unless `serve_dir` is set
     root: Arc<PathBuf>,
-    files: Arc<Mutex<HashMap<String, String>>>,
+    /// Unsaved buffers and pending deletes, sharded so serving never waits on edits to another document
+    overlay: Arc<Overlay>,
+    graph: Arc<DependencyGraph>,
+    source_maps: Arc<SourceMaps>,
+    middleware: Middlewares,
+    /// Directories outside the root served and watched along with it
+    mounts: Arc<Mounts>,
+    documents: Documents,
+    builds: Builds,
+    addresses: Addresses,
+    http: Http,
+    simulation: Simulation,
+    reloads: Reloads,
+    sharing: Sharing,
+    browsers: Browsers,
+    /// Requests per url since the server started
+    requests: Arc<DashMap<String, u64>>,
+    /// Requests being answered, shutdown waits for them
+    in_flight: Arc<AtomicUsize>,
+    /// Mock API definition answered by the gateway
+    mock_api: Option<Arc<PathBuf>>,
+    /// Url prefix of the mock API routes
+    mock_api_prefix: Arc<str>,
+    /// Upstream APIs the gateway passes the requests below their prefixes on to
+    proxy: Option<Arc<Proxy>>,
+    /// Event channel to the injected client script, `None` if it failed to start
+    relay: Option<Arc<Relay>>,
+    client_features: ClientFeatures,
     sig: Signal,
-}
-
-struct LspDir {
-    dir: ReadDir,
-}
-
-enum LspFile {
-    Content(String),
-    File(TokioFile),
-}
-
-impl LspFile {
-    async fn new(
-        files: Arc<Mutex<HashMap<String, String>>>,
-        path: &Path,
-        eager: bool,
-    ) -> Result<Self, Error> {
-        if !eager {
-            return Ok(LspFile::File(TokioFile::open(path).await?));
-        }
-        let content = files
-            .lock()
-            .await
-            .get(&format!("file://{}", path.to_str().unwrap_or_default()))
-            .cloned();
-        Ok(match content {
-            Some(v) => LspFile::Content(v.to_string()),
-            None => LspFile::File(TokioFile::open(path).await?),
-        })
-    }
-}
-
-impl File for LspFile {
-    async fn read_to_end(&mut self) -> Vec<u8> {
-        match self {
-            LspFile::Content(c) => c.as_bytes().to_vec(),
-            LspFile::File(file) => {
-                let mut buffer = vec![];
-                let _ = file.read_to_end(&mut buffer).await;
-                buffer
-            }
-        }
-    }
-}
-
-impl LspDir {
-    async fn new(path: &Path) -> Result<Self, Error> {
-        let dir = read_dir(path).await?;
-        Ok(Self { dir })
-    }
-}
-
-impl Dir for LspDir {
-    async fn get_next(&mut self) -> Result<Option<PathBuf>, Error> {
-        Ok(self.dir.next_entry().await?.map(|v| v.path()))
-    }
-}
-
-impl FileSystemInterface for LspFileService {
-    async fn get_dir(&self, path: &Path) -> Result<impl Dir, rusty_live_server::Error> {
-        LspDir::new(path).await
-    }
-
-    async fn get_file(&self, path: &Path) -> Result<impl File, rusty_live_server::Error> {
-        LspFile::new(self.files.clone(), path, self.eager).await
-    }
+    logger: Logger,
+    access_log: AccessLog,
+    /// Latest requests for `exportProjectHar`
+    har: Arc<Har>,
+    /// Viewport widths of the responsive preview, `None` if it isn't served
+    responsive: Option<Arc<[u32]>>,
+    /// Paths that are neither served nor reload
+    ignore: Arc<Ignore>,
+    /// Small assets served from disk, dropped when their file changes
+    cache: Arc<AssetCache>,
+    /// When the server was (re)started
+    started: Instant,
+    /// Serve right away instead of waiting for the first page to be opened
+    auto_start: bool,
+    /// Whether the http server was started
+    serving: Arc<AtomicBool>,
+    /// Websockets open to the server through the gateway, one reload socket per open page
+    sockets: Arc<watch::Sender<usize>>,
+    /// Tasks of the http server, stopped when it is idle
+    server: Arc<std::sync::Mutex<Vec<AbortHandle>>>,
+    /// Milliseconds after `started` of the latest request
+    active: Arc<AtomicU64>,
+    /// `package.json` scripts started with `runNpmScript` that are still running
+    scripts: Arc<DashSet<String>>,
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let uri = params.text_document.uri.to_string();
+        let uri = params.text_document.uri;
         let content = params.text_document.text;
+        self.versions
+            .insert(uri.clone(), params.text_document.version);
 
-        if let Some((_, service)) = self.get_workspace_for_file(&uri).await {
-            let mut files = service.files.lock().await;
+        if uri.scheme() == "file" {
+            self.serve_single_file(&uri).await;
+        } else {
+            let route = virtual_route(&uri, &params.text_document.language_id);
+            self.virtual_documents
+                .write()
+                .await
+                .insert(uri.clone(), route);
+        }
+        for (path, service) in self.get_documents(&uri).await {
+            if is_html(&path) {
+                self.activate(&service).await;
+            }
             if *self.eager.read().await {
-                files.insert(uri.clone(), content.clone());
+                service.overlay.open(path.clone(), &content);
+                self.trim_overlay(&service, &path).await;
             }
-            self.update_file(&uri, &service, false).await;
+            self.update_file(&path, &service, false).await;
         }
+        self.follow(&uri).await;
     }
 
     async fn did_save(&self, params: DidSaveTextDocumentParams) {
-        let uri = params.text_document.uri.to_string();
-        if let Some((_, service)) = self.get_workspace_for_file(&uri).await {
-            let message = format!("File saved: {}", uri);
-            self.client.log_message(MessageType::INFO, message).await;
-            self.update_file(&uri, &service, true).await;
+        for (path, service) in self.get_documents(&params.text_document.uri).await {
+            service.overlay.create(&path);
+            let message = format!("File saved: {}", path.display());
+            self.log(&service, MessageType::INFO, message).await;
+            if self.run_save_hook(&path, &service).await {
+                self.update_file(&path, &service, true).await;
+            }
         }
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        let uri = params.text_document.uri.to_string();
-
-        if let Some((_, service)) = self.get_workspace_for_file(&uri).await {
-            let mut files = service.files.lock().await;
-            if let Some(file) = files.get_mut(&uri) {
-                if *self.eager.read().await {
-                    for change in params.content_changes {
-                        if let Some(range) = change.range {
-                            let start = get_byte_index_from_position(file, range.start);
-                            let end = get_byte_index_from_position(file, range.end);
-
-                            file.replace_range(start..end, &change.text);
-                        } else {
-                            *file = change.text.clone();
-                        }
+        let uri = &params.text_document.uri;
+        let version = params.text_document.version;
+        let in_order = self
+            .versions
+            .insert(uri.clone(), version)
+            .is_none_or(|last| version > last);
+        let changes = &params.content_changes;
+        for (path, service) in self.get_documents(uri).await {
+            if *self.eager.read().await {
+                let encoding = *self.encoding.read().await;
+                let applied = in_order
+                    && service
+                        .overlay
+                        .edit(&path, |file| apply_changes(file, changes, encoding));
+                // a change of the whole document makes up for lost ones
+                let full = changes.iter().rposition(|change| change.range.is_none());
+                match full {
+                    Some(full) if !applied && service.overlay.is_open(&path) => {
+                        let mut rope = Rope::from(changes[full].text.as_str());
+                        apply_changes(&mut rope, &changes[full + 1..], encoding);
+                        service.overlay.replace(path.clone(), rope);
                     }
+                    _ if !applied && service.overlay.evict(&path) => {
+                        let message = format!(
+                            "Changes of {} were lost, serving it as saved until it is reopened",
+                            path.display()
+                        );
+                        self.log(&service, MessageType::WARNING, message).await;
+                    }
+                    _ => {}
                 }
+                self.trim_overlay(&service, &path).await;
             }
-            self.update_file(&uri, &service, false).await;
+            self.update_file(&path, &service, false).await;
+        }
+        if let Some(range) = params
+            .content_changes
+            .last()
+            .and_then(|change| change.range)
+        {
+            self.scroll_preview(&params.text_document.uri, range.start.line)
+                .await;
         }
     }
 
+    /// Files and directories of the served root inside `href` and `src` attributes of html and `url()` of css
+    async fn completion(
+        &self,
+        params: CompletionParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<CompletionResponse>> {
+        let position = params.text_document_position;
+        let Some((path, service)) = self
+            .get_documents(&position.text_document.uri)
+            .await
+            .into_iter()
+            .next()
+        else {
+            return Ok(None);
+        };
+        let css = path.extension().is_some_and(|ext| ext == "css");
+        if !is_html(&path) && !css {
+            return Ok(None);
+        }
+        let Ok(text) = service.overlay.read(&path).await else {
+            return Ok(None);
+        };
+        let text = String::from_utf8_lossy(&text);
+        let encoding = *self.encoding.read().await;
+        let cursor = get_byte_index_from_position(&Rope::from(&*text), position.position, encoding);
+        let line_start = text[..cursor].rfind(['\n', '\r']).map_or(0, |i| i + 1);
+        let Some(typed) = completion::typed_url(&text[line_start..cursor]) else {
+            return Ok(None);
+        };
+        let dir = typed.rsplit_once('/').map_or("", |(dir, _)| dir);
+        let dir = match dir.strip_prefix('/') {
+            Some(dir) => service.root.join(dir),
+            _ if typed.starts_with('/') => service.root.to_path_buf(),
+            _ => {
+                let page = service.served_path(&path);
+                page.parent().unwrap_or(&service.root).join(dir)
+            }
+        };
+        // listed like the server lists directories, with mounts, ignores and unsaved files
+        let Ok(mut entries) = service.get_dir(&dir).await else {
+            return Ok(None);
+        };
+        let mut items = vec![];
+        while let Ok(Some(entry)) = entries.get_next().await {
+            let Some(name) = entry
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+            else {
+                continue;
+            };
+            let is_dir = tokio::fs::metadata(&entry)
+                .await
+                .is_ok_and(|metadata| metadata.is_dir());
+            items.push(CompletionItem {
+                label: match is_dir {
+                    true => format!("{}/", name),
+                    false => name,
+                },
+                kind: Some(match is_dir {
+                    true => CompletionItemKind::FOLDER,
+                    false => CompletionItemKind::FILE,
+                }),
+                ..Default::default()
+            });
+        }
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
     async fn execute_command(
         &self,
         params: ExecuteCommandParams,
     ) -> tower_lsp::jsonrpc::Result<Option<Value>> {
-        if params.command == "openProjectWeb" {
-            let mut args = params.arguments.iter();
-            if let (Some(project), Some(file)) = (
-                args.next().and_then(|arg| arg.as_str()),
-                args.next().and_then(|arg| arg.as_str()),
-            ) {
-                if let Some((_, v)) = self.workspace_folders.lock().await.get(Path::new(project)) {
-                    if let Err(e) = webbrowser::open(&format!(
-                        "http://127.0.0.1:{}/{file}",
-                        v.port.lock().await
-                    )) {
-                        self.client
-                            .show_message(
-                                MessageType::WARNING,
-                                format!("failed to open browser {}", e.to_string()),
-                            )
-                            .await;
-                        return Err(tower_lsp::jsonrpc::Error::invalid_params(
-                            "failed to open browser",
-                        ));
-                    }
-                } else {
-                    return Err(tower_lsp::jsonrpc::Error::invalid_params(
-                        "URL argument invalid",
-                    ));
-                }
-            } else {
-                return Err(tower_lsp::jsonrpc::Error::invalid_params(
-                    "URL argument missing",
-                ));
-            }
-        } else {
-            return Err(tower_lsp::jsonrpc::Error::method_not_found());
+        match commands::Command::parse(&params.command) {
+            Some(command) => self.execute(command, &params.arguments).await,
+            None => Err(tower_lsp::jsonrpc::Error::method_not_found()),
         }
-        Ok(None)
     }
 
     async fn initialize(
         &self,
         params: InitializeParams,
     ) -> tower_lsp::jsonrpc::Result<InitializeResult> {
-        let config: Config = params
-            .initialization_options
-            .map(serde_json::from_value)
-            .and_then(|v| v.ok())
-            .unwrap_or_default();
+        let mut options = self.options.read().await.clone();
+        if let (Value::Object(options), Some(Value::Object(layer))) =
+            (&mut options, params.initialization_options)
+        {
+            options.extend(layer);
+        }
+        let (config, errors) = config::parse(&options);
+        for error in errors {
+            self.client.show_message(MessageType::ERROR, error).await;
+        }
+        *self.options.write().await = options.clone();
         {
             *self.eager.write().await = !config.lazy.unwrap_or_default();
             *self.port.write().await = config.start_port.unwrap_or(57391);
-            *self.public.write().await = config.public.unwrap_or_default();
+            *self.bind.write().await = match config.bind.as_deref().map(str::parse::<IpAddr>) {
+                Some(Ok(address)) => address,
+                Some(Err(e)) => {
+                    let message = format!("invalid bind address, serving on loopback: {}", e);
+                    self.client.show_message(MessageType::ERROR, message).await;
+                    Ipv4Addr::LOCALHOST.into()
+                }
+                None if config.public.unwrap_or_default() => Ipv4Addr::UNSPECIFIED.into(),
+                None => Ipv4Addr::LOCALHOST.into(),
+            };
+            *self.encoding.write().await = params
+                .capabilities
+                .general
+                .as_ref()
+                .and_then(|general| general.position_encodings.as_deref())
+                .map(PositionEncoding::negotiate)
+                .unwrap_or_default();
+            let watched = params
+                .capabilities
+                .workspace
+                .as_ref()
+                .and_then(|workspace| workspace.did_change_watched_files.as_ref());
+            *self.watch_files.write().await = (
+                watched.and_then(|w| w.dynamic_registration) == Some(true),
+                watched.and_then(|w| w.relative_pattern_support) == Some(true),
+            );
+            *self.show_document.write().await = params
+                .capabilities
+                .window
+                .as_ref()
+                .and_then(|window| window.show_document.as_ref())
+                .is_some_and(|show_document| show_document.support);
         }
 
-        if let Some(workspace_folders) = params.workspace_folders {
-            let mut folders = self.workspace_folders.lock().await;
+        // older clients only send the root of a single workspace
+        #[allow(deprecated)]
+        let root = params.root_uri.or_else(|| {
+            params
+                .root_path
+                .as_deref()
+                .and_then(|path| Url::from_directory_path(path).ok())
+        });
+        let workspace_folders = params
+            .workspace_folders
+            .filter(|folders| !folders.is_empty())
+            .or_else(|| {
+                root.map(|uri| {
+                    vec![WorkspaceFolder {
+                        uri,
+                        name: String::new(),
+                    }]
+                })
+            });
+        *self.single_file.write().await = workspace_folders.is_none();
+        if let Some(workspace_folders) = workspace_folders {
+            let mut folders = self.workspace_folders.write().await;
             for folder in workspace_folders {
-                let name = if folder.name.is_empty() {
-                    folder
-                        .uri
-                        .to_file_path()
-                        .ok()
-                        .and_then(|path| {
-                            path.file_name()
-                                .map(|name| name.to_string_lossy().into_owned())
-                        })
-                        .unwrap_or_else(|| "Unnamed Workspace".to_string())
-                } else {
-                    folder.name.clone()
-                };
-                let path = folder
-                    .uri
-                    .to_file_path()
-                    .unwrap_or_else(|_| PathBuf::from(&folder.uri.to_string()));
-                let fs = LspFileService {
-                    port: Arc::new(Mutex::new(*self.port.read().await)),
-                    sig: Signal::default(),
-                    eager: *self.eager.read().await,
-                    files: Default::default(),
-                    root: Arc::new(path.clone()),
+                let (name, path) = folder_root(&folder);
+                let (config, errors) = config::load(&options, &path).await;
+                for error in errors {
+                    self.client.log_message(MessageType::WARNING, error).await;
+                }
+                let open = match config.open_on_start.unwrap_or_default() {
+                    OpenOnStart::All => true,
+                    OpenOnStart::First => folders.is_empty(),
+                    OpenOnStart::None => false,
                 };
-                folders.insert(path, (name, fs));
+                if open {
+                    self.open_on_start.write().await.push(path.clone());
+                }
+                let servers = self
+                    .build_workspace(&name, &path, &config, &HashMap::new())
+                    .await;
+                for (id, name, fs, tasks) in servers {
+                    self.tasks.lock().await.insert(id.clone(), tasks);
+                    folders.insert(id, (name, fs));
+                }
             }
         }
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
+                position_encoding: Some(self.encoding.read().await.kind()),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                        did_create: Some(file_operation_filters()),
+                        did_rename: Some(file_operation_filters()),
+                        did_delete: Some(file_operation_filters()),
+                        ..Default::default()
+                    }),
+                }),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(["/", "\"", "'", "("].map(String::from).into()),
+                    ..Default::default()
+                }),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(true),
+                }),
                 code_action_provider: Some(
                     tower_lsp::lsp_types::CodeActionProviderCapability::Simple(true),
                 ),
                 execute_command_provider: Some(tower_lsp::lsp_types::ExecuteCommandOptions {
-                    commands: vec!["openProjectWeb".to_string()],
+                    commands: commands::Command::ALL
+                        .iter()
+                        .map(|command| command.name().to_string())
+                        .collect(),
                     ..Default::default()
                 }),
 
@@ -251,11 +470,15 @@ impl LanguageServer for Backend {
                     match *self.eager.read().await {
                         true => TextDocumentSyncOptions {
                             open_close: Some(true),
-                            change: Some(TextDocumentSyncKind::INCREMENTAL),
+                            change: Some(match config.text_sync.unwrap_or_default() {
+                                TextSync::Incremental => TextDocumentSyncKind::INCREMENTAL,
+                                TextSync::Full => TextDocumentSyncKind::FULL,
+                            }),
                             ..Default::default()
                         },
                         false => TextDocumentSyncOptions {
-                            open_close: Some(false),
+                            // opening an html file activates its workspace, edits are still read from disk
+                            open_close: Some(true),
                             change: Some(TextDocumentSyncKind::NONE),
                             save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
                                 include_text: Some(false),
@@ -270,29 +493,71 @@ impl LanguageServer for Backend {
         })
     }
 
+    async fn code_lens(
+        &self,
+        params: CodeLensParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<Vec<CodeLens>>> {
+        let mut lenses = vec![];
+        for (path, service) in self.get_documents(&params.text_document.uri).await {
+            if !service.serving.load(Ordering::Relaxed) || !is_html(&path) {
+                continue;
+            }
+            lenses.push(CodeLens {
+                range: Range::default(),
+                command: None,
+                data: Some(json!({ "workspace": service.id, "path": path })),
+            });
+        }
+        Ok(Some(lenses))
+    }
+
+    async fn code_lens_resolve(&self, mut lens: CodeLens) -> tower_lsp::jsonrpc::Result<CodeLens> {
+        let data = lens.data.clone().unwrap_or_default();
+        let field = |key: &str| data.get(key).and_then(Value::as_str).unwrap_or_default();
+        let folders = self.workspace_folders.read().await;
+        let Some((_, service)) = folders.get(Path::new(field("workspace"))) else {
+            return Ok(lens);
+        };
+        let clients = service.clients();
+        let served = service.served_path(Path::new(field("path")));
+        let file = url_path(
+            served
+                .strip_prefix(service.root.as_ref())
+                .unwrap_or(&served),
+        );
+        lens.command = Some(Command {
+            title: match clients {
+                1 => "1 browser connected".to_string(),
+                n => format!("{} browsers connected", n),
+            },
+            command: commands::Command::OpenProjectWeb.name().to_string(),
+            arguments: Some(vec![Value::from(field("workspace")), Value::from(file)]),
+        });
+        Ok(lens)
+    }
+
     async fn code_action(
         &self,
         params: CodeActionParams,
     ) -> tower_lsp::jsonrpc::Result<Option<CodeActionResponse>> {
         let mut actions = vec![];
 
-        let uri = params.text_document.uri.to_string();
-
-        if let Some((_, service)) = self.get_workspace_for_file(&uri).await {
-            let port = *service.port.lock().await;
-            let file = uri.strip_prefix("file://").unwrap_or(&uri);
-            let file = file
-                .strip_prefix(service.root.to_str().unwrap_or_default())
-                .unwrap_or(file);
-            let file = file.strip_prefix("/").unwrap_or(file);
+        for (path, service) in self.get_documents(&params.text_document.uri).await {
+            let port = *service.port.read().await;
+            let served = service.served_path(&path);
+            let file = url_path(
+                served
+                    .strip_prefix(service.root.as_ref())
+                    .unwrap_or(&served),
+            );
             let action = CodeActionOrCommand::CodeAction(CodeAction {
                 title: format!("Open in Browser({})", port),
                 kind: Some(CodeActionKind::EMPTY),
                 command: Some(Command {
                     title: format!("Open in Browser({})", port),
-                    command: "openProjectWeb".to_string(),
+                    command: commands::Command::OpenProjectWeb.name().to_string(),
                     arguments: Some(vec![
-                        Value::from(service.root.to_str().unwrap_or_default().to_string()),
+                        Value::from(service.id.to_str().unwrap_or_default().to_string()),
                         Value::from(file),
                     ]),
                 }),
@@ -303,6 +568,37 @@ impl LanguageServer for Backend {
                 data: None,
             });
             actions.push(action);
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: "Reload Browsers".to_string(),
+                kind: Some(CodeActionKind::EMPTY),
+                command: Some(Command {
+                    title: "Reload Browsers".to_string(),
+                    command: commands::Command::ForceProjectReload.name().to_string(),
+                    arguments: Some(vec![Value::from(
+                        service.id.to_str().unwrap_or_default().to_string(),
+                    )]),
+                }),
+                ..Default::default()
+            }));
+            if is_html(&path) || path.file_name() == Some("package.json".as_ref()) {
+                let manager = scripts::package_manager(&service.workspace);
+                for script in scripts::list(&service.workspace).await {
+                    let title = format!("Run {} script: {}", manager, script);
+                    actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                        title: title.clone(),
+                        kind: Some(CodeActionKind::EMPTY),
+                        command: Some(Command {
+                            title,
+                            command: commands::Command::RunNpmScript.name().to_string(),
+                            arguments: Some(vec![
+                                Value::from(service.id.to_str().unwrap_or_default().to_string()),
+                                Value::from(script),
+                            ]),
+                        }),
+                        ..Default::default()
+                    }));
+                }
+            }
         }
 
         Ok(Some(actions))
@@ -312,141 +608,367 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "LiveServer Initialized!")
             .await;
-        let folders = self.workspace_folders.lock().await;
-        let mut threads = vec![];
+        let folders = self.workspace_folders.read().await;
+        let opened = self.open_on_start.read().await;
         for (path, (name, fs)) in folders.iter() {
-            let path = path.clone();
-            let f = fs.clone();
-            let public = *self.public.read().await;
-            threads.push(tokio::spawn(async move {
-                loop {
-                    let port = *f.port.clone().lock().await;
-                    let _ = rusty_live_server::serve(
-                        path.clone(),
-                        port,
-                        public,
-                        Some(f.sig.clone()),
-                        f.clone(),
-                    )
-                    .await;
-                    *f.port.lock().await += 1;
-                }
-            }));
-            self.client
-                .log_message(
+            if !fs.auto_start && !opened.contains(path) {
+                self.log(
+                    fs,
                     MessageType::INFO,
-                    format!("Opend Workspace: {} at port {}", name, fs.port.lock().await),
+                    format!("Opend Workspace: {}, serving once a page is opened", name),
                 )
                 .await;
+                continue;
+            }
+            let tasks = self.serve_once(fs);
+            self.tasks
+                .lock()
+                .await
+                .entry(path.clone())
+                .or_default()
+                .extend(tasks);
+            let port = *fs.port.read().await;
+            self.log(
+                fs,
+                MessageType::INFO,
+                format!("Opend Workspace: {} at port {}", name, port),
+            )
+            .await;
+        }
+        for path in opened.iter() {
+            if let Some((_, fs)) = folders.get(path) {
+                let task = self.open_when_listening(fs.clone());
+                self.threads.lock().await.push(task);
+            }
+        }
+        drop(opened);
+        self.threads.lock().await.push(self.watch_config());
+        drop(folders);
+        self.register_watchers(false).await;
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        for folder in params.event.removed {
+            let (name, path) = folder_root(&folder);
+            if !self.stop_workspace(&path).await.is_empty() {
+                self.client
+                    .log_message(MessageType::INFO, format!("Closed Workspace: {}", name))
+                    .await;
+            }
+        }
+        if !params.event.added.is_empty() {
+            *self.single_file.write().await = false;
+        }
+        for folder in params.event.added {
+            let (name, path) = folder_root(&folder);
+            if self.workspace_folders.read().await.contains_key(&path) {
+                continue;
+            }
+            self.start_workspace(&name, &path).await;
+        }
+        self.register_watchers(true).await;
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        for change in params.changes {
+            let Some((path, service)) = self.get_workspace_for_file(&change.uri).await else {
+                continue;
+            };
+            match change.typ {
+                FileChangeType::CREATED => service.overlay.create(&path),
+                FileChangeType::DELETED => service.overlay.delete(&path),
+                // open documents are reloaded on save and edit already
+                _ if service.overlay.is_open(&path) => continue,
+                _ => {}
+            }
+            self.update_file(&path, &service, true).await;
         }
-        *self.threads.lock().await = threads;
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
-        let uri = params.text_document.uri.to_string();
+        let uri = params.text_document.uri;
+        self.versions.remove(&uri);
+        for (path, service) in self.get_documents(&uri).await {
+            service.overlay.close(&path);
+        }
+        self.virtual_documents.write().await.remove(&uri);
+    }
+
+    async fn did_create_files(&self, params: CreateFilesParams) {
+        for file in params.files {
+            let Ok(uri) = Url::parse(&file.uri) else {
+                continue;
+            };
+            if let Some((path, service)) = self.get_workspace_for_file(&uri).await {
+                service.overlay.create(&path);
+                self.update_file(&path, &service, true).await;
+            }
+        }
+    }
+
+    async fn did_rename_files(&self, params: RenameFilesParams) {
+        for file in params.files {
+            let (Ok(old_uri), Ok(new_uri)) = (Url::parse(&file.old_uri), Url::parse(&file.new_uri))
+            else {
+                continue;
+            };
+            let old = self.get_workspace_for_file(&old_uri).await;
+            let new = self.get_workspace_for_file(&new_uri).await;
+            match (old, new) {
+                (Some((from, old)), Some((to, new))) if Arc::ptr_eq(&old.overlay, &new.overlay) => {
+                    old.overlay.rename(&from, &to);
+                    self.update_file(&from, &old, true).await;
+                    self.update_file(&to, &new, true).await;
+                }
+                (old, new) => {
+                    if let Some((from, service)) = old {
+                        service.overlay.delete(&from);
+                        self.update_file(&from, &service, true).await;
+                    }
+                    if let Some((to, service)) = new {
+                        service.overlay.create(&to);
+                        self.update_file(&to, &service, true).await;
+                    }
+                }
+            }
+        }
+    }
 
-        if let Some((_, service)) = self.get_workspace_for_file(&uri).await {
-            let mut files = service.files.lock().await;
-            files.remove(&uri);
+    async fn did_delete_files(&self, params: DeleteFilesParams) {
+        for file in params.files {
+            let Ok(uri) = Url::parse(&file.uri) else {
+                continue;
+            };
+            if let Some((path, service)) = self.get_workspace_for_file(&uri).await {
+                service.overlay.delete(&path);
+                self.update_file(&path, &service, true).await;
+            }
         }
     }
 
     async fn shutdown(&self) -> tower_lsp::jsonrpc::Result<()> {
+        let services: Vec<LspFileService> = self
+            .workspace_folders
+            .read()
+            .await
+            .values()
+            .map(|(_, fs)| fs.clone())
+            .collect();
+        for fs in &services {
+            fs.logger.log(MessageType::INFO, "Shutting down").await;
+            // every served page runs the client script, which the relay of its workspace reaches
+            match &fs.relay {
+                Some(relay) => relay.broadcast(&json!({ "type": "closing" })),
+                None => {
+                    let message =
+                        "no relay to tell the browsers, they notice the closed reload socket";
+                    fs.logger.log(MessageType::WARNING, message).await;
+                }
+            }
+        }
+        // requests being answered finish, and the closing event reaches the browsers, before the ports close
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        loop {
+            let busy = services
+                .iter()
+                .any(|fs| fs.in_flight.load(Ordering::Relaxed) > 0);
+            if !busy || Instant::now() >= deadline {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
         self.threads.lock().await.iter().for_each(|v| v.abort());
+        for tasks in self.tasks.lock().await.values() {
+            tasks.iter().for_each(|v| v.abort());
+        }
         Ok(())
     }
 }
 
 impl Backend {
-    async fn get_workspace_for_file(&self, uri: &str) -> Option<(PathBuf, LspFileService)> {
-        let folders = self.workspace_folders.lock().await;
-        for (path, (_, service)) in folders.iter() {
-            let file_path = Path::new(uri.strip_prefix("file://").unwrap_or(uri));
-            if file_path.starts_with(&service.root.as_ref()) {
-                return Some((path.clone(), service.clone()));
-            }
+    /// `liveServer/status`, the state of every workspace server for editor extensions
+    async fn status(&self) -> tower_lsp::jsonrpc::Result<Value> {
+        let bind = *self.bind.read().await;
+        let eager = *self.eager.read().await;
+        let mut workspaces = vec![];
+        for (path, (name, fs)) in self.workspace_folders.read().await.iter() {
+            workspaces.push(json!({
+                "name": name,
+                "root": path,
+                "port": *fs.port.read().await,
+                "address": bind,
+                "clients": fs.clients(),
+                "uptime": fs.started.elapsed().as_secs(),
+                "serving": fs.serving.load(Ordering::Relaxed),
+            }));
         }
-        None
+        Ok(json!({ "eager": eager, "workspaces": workspaces }))
     }
 
-    async fn update_file(&self, uri: &str, service: &LspFileService, saved: bool) {
-        self.client
-            .log_message(MessageType::INFO, format!("File updated: {}", uri))
-            .await;
-        let abs = uri.strip_prefix("file://").unwrap_or(uri);
-        let rel = abs
-            .strip_prefix(service.root.to_str().unwrap_or_default())
-            .unwrap_or(abs);
-        self.call_custom_function(&service.root, Path::new(rel), saved)
-            .await;
+    /// Drops the buffers beyond `overlay_max_size`, their files are served as saved from now on.
+    /// `current`, the document just edited, keeps its buffer
+    async fn trim_overlay(&self, service: &LspFileService, current: &Path) {
+        // incremental changes of a dropped buffer can't be applied, it needs a full change to be served unsaved again
+        for path in service.overlay.trim(current) {
+            let message = format!(
+                "Open documents exceed overlay_max_size, serving {} as saved until it is reopened or sent whole",
+                path.display()
+            );
+            self.log(service, MessageType::WARNING, message).await;
+        }
     }
 
-    async fn call_custom_function(&self, workspace: &PathBuf, file_path: &Path, saved: bool) {
-        if !*self.eager.read().await && !saved {
-            return;
-        }
+    async fn log(&self, service: &LspFileService, ty: MessageType, message: String) {
+        service.logger.log(ty, &message).await;
+        self.client.log_message(ty, message).await;
+    }
+
+    /// `window/showMessageRequest`, the title of the action the user picked
+    async fn ask(&self, ty: MessageType, message: String, actions: &[&str]) -> Option<String> {
+        let actions = actions
+            .iter()
+            .map(|title| MessageActionItem {
+                title: title.to_string(),
+                properties: HashMap::new(),
+            })
+            .collect();
         self.client
-            .log_message(MessageType::INFO, format!("reload"))
-            .await;
-        let mutex = self.workspace_folders.lock().await;
-        if let Some((_, fs)) = mutex.get(workspace) {
-            fs.sig.send_signal(file_path.to_path_buf());
+            .show_message_request(ty, message, Some(actions))
+            .await
+            .ok()
+            .flatten()
+            .map(|action| action.title)
+    }
+}
+
+/// Applies the changes of a `didChange` notification in order
+fn apply_changes(
+    rope: &mut Rope,
+    changes: &[TextDocumentContentChangeEvent],
+    encoding: PositionEncoding,
+) {
+    for change in changes {
+        if let Some(range) = change.range {
+            let start = get_byte_index_from_position(rope, range.start, encoding);
+            let end = get_byte_index_from_position(rope, range.end, encoding);
+            rope.replace(start..end, &change.text);
+        } else {
+            *rope = Rope::from(change.text.as_str());
         }
     }
 }
 
-pub async fn lsp() {
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
-
-    let (client, server) = LspService::build(|client| Backend {
-        client,
-        workspace_folders: Default::default(),
-        threads: Default::default(),
-        port: Default::default(),
-        public: Default::default(),
-        eager: Arc::new(RwLock::new(true)),
-    })
-    .finish();
-
-    Server::new(stdin, stdout, server).serve(client).await;
+/// Display name and root of a workspace folder
+fn folder_root(folder: &WorkspaceFolder) -> (String, PathBuf) {
+    let path = uri_to_path(&folder.uri).unwrap_or_else(|| PathBuf::from(&folder.uri.to_string()));
+    let name = if folder.name.is_empty() {
+        folder
+            .uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "Unnamed Workspace".to_string())
+    } else {
+        folder.name.clone()
+    };
+    (name, path)
 }
 
-pub fn get_byte_index_from_position(s: &str, position: Position) -> usize {
-    if s.len() == 0 {
-        return 0;
-    }
-    let line_start = index_of_first_char_in_line(s, position.line).unwrap_or(s.len());
+fn is_module(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("js" | "mjs")
+    )
+}
 
-    let char_index = line_start + position.character as usize;
+fn is_stylesheet(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some("css")
+}
 
-    if char_index >= s.len() {
-        s.char_indices().nth(s.len() - 1).unwrap().0
-    } else {
-        s.char_indices().nth(char_index).unwrap().0
-    }
+fn is_html(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("html" | "htm")
+    )
 }
 
-fn index_of_first_char_in_line(s: &str, line: u32) -> Option<usize> {
-    if line == 0 {
-        return Some(0);
+fn file_operation_filters() -> FileOperationRegistrationOptions {
+    FileOperationRegistrationOptions {
+        filters: vec![FileOperationFilter {
+            scheme: Some("file".to_string()),
+            pattern: FileOperationPattern {
+                glob: "**/*".to_string(),
+                matches: None,
+                options: None,
+            },
+        }],
     }
-    let mut current_line = 0;
-    let mut index = 0;
-
-    for (i, c) in s.char_indices() {
-        if c == '\n' {
-            current_line += 1;
-            if current_line == line {
-                return Some(i + 1);
-            }
+}
+
+impl Backend {
+    /// Backend starting out with the options of `config`, `initializationOptions` and the configuration files are layered over them.
+    ///
+    /// It answers the standard LSP requests in an [`LspService`] of the embedding, [`Backend::service`]
+    /// also registers the custom methods like `liveServer/status`.
+    pub fn new(client: Client, mut config: Config) -> Self {
+        let middleware = std::mem::take(&mut config.middleware).into();
+        let mut defaults = serde_json::to_value(config).unwrap_or_default();
+        if let Value::Object(options) = &mut defaults {
+            options.retain(|_, value| !value.is_null());
         }
-        index = i;
+        Self {
+            client,
+            workspace_folders: Default::default(),
+            threads: Default::default(),
+            tasks: Default::default(),
+            open_on_start: Default::default(),
+            options: Arc::new(RwLock::new(defaults)),
+            port: Default::default(),
+            bind: Arc::new(RwLock::new(Ipv4Addr::LOCALHOST.into())),
+            eager: Arc::new(RwLock::new(true)),
+            encoding: Default::default(),
+            watch_files: Default::default(),
+            show_document: Default::default(),
+            virtual_documents: Default::default(),
+            versions: Default::default(),
+            single_file: Default::default(),
+            middleware,
+        }
+    }
+}
+
+impl Backend {
+    /// The language server with its custom methods, starting out with the options of `config`.
+    /// [`LspService::inner`] is the backend, and [`Server`] serves it over any transport.
+    pub fn service(config: Config) -> (LspService<Backend>, ClientSocket) {
+        LspService::build(|client| Backend::new(client, config))
+            .custom_method("liveServer/status", Backend::status)
+            .custom_method("liveServer/activeDocument", Backend::active_document)
+            .finish()
     }
 
-    if current_line == line - 1 {
-        return Some(index + 1);
+    /// File services of the workspace folders and their additional servers, once initialized
+    pub async fn services(&self) -> Vec<LspFileService> {
+        self.workspace_folders
+            .read()
+            .await
+            .values()
+            .map(|(_, service)| service.clone())
+            .collect()
     }
+}
 
-    None
+/// Runs the language server over `input` and `output`, `initializationOptions` are layered over `config`
+pub async fn serve_on(config: Config, input: impl AsyncRead + Unpin, output: impl AsyncWrite) {
+    let (service, socket) = Backend::service(config);
+    Server::new(input, output, socket).serve(service).await;
+}
+
+/// Runs the language server over stdin and stdout
+pub async fn lsp() {
+    serve_on(Config::default(), tokio::io::stdin(), tokio::io::stdout()).await;
 }
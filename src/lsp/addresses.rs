@@ -0,0 +1,197 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::{JoinHandle, JoinSet};
+use tower_lsp::lsp_types::{MessageType, Url};
+
+use crate::forward;
+use crate::{Config, PortStrategy, ServerConfig};
+
+use super::{Backend, LspFileService};
+
+/// Where the server of a workspace binds, is reached and opened
+#[derive(Clone)]
+pub(super) struct Addresses {
+    /// Address pages are opened at
+    pub(super) host: IpAddr,
+    /// Address the server binds itself, `0.0.0.0` or `127.0.0.1`
+    pub(super) server: IpAddr,
+    /// Addresses forwarded to the server besides the one it binds
+    pub(super) listen: Arc<[IpAddr]>,
+    /// Base url of a reverse proxy in front of the server
+    pub(super) public_url: Option<Arc<str>>,
+    /// Socket a reverse proxy connects to besides the port, only of the main server of a workspace
+    pub(super) unix_socket: Option<Arc<PathBuf>>,
+    /// Host names pages are requested under besides localhost and ip addresses, lowercase.
+    /// `allowed_hosts`, the host of `public_url` and the one of the tunnel once it is registered
+    pub(super) hosts: Arc<std::sync::RwLock<Vec<String>>>,
+    /// What to do if the port is taken
+    pub(super) port_strategy: PortStrategy,
+    /// Ports tried by the increment strategy
+    pub(super) port_attempts: u16,
+}
+
+impl Addresses {
+    /// Addresses of the server for `bind`, invalid `listen` addresses are skipped with a warning
+    pub(super) fn new(
+        config: &Config,
+        bind: IpAddr,
+        path: &Path,
+        server: Option<&ServerConfig>,
+        warnings: &mut Vec<String>,
+    ) -> Self {
+        let mut listen = vec![];
+        match bind {
+            // ipv6 sockets on `::` take ipv4 as well on most systems, clashing with the server on 0.0.0.0
+            IpAddr::V6(address) if address.is_unspecified() => {
+                listen.push(Ipv6Addr::LOCALHOST.into())
+            }
+            bind if is_forwarded(bind) => listen.push(bind),
+            _ => {}
+        }
+        for address in config.listen.iter().flatten() {
+            match address.parse::<IpAddr>() {
+                Ok(address) => listen.push(address),
+                Err(e) => warnings.push(format!("invalid listen address {}: {}", address, e)),
+            }
+        }
+        Self {
+            host: match is_forwarded(bind) && !bind.is_unspecified() {
+                true => bind,
+                false => Ipv4Addr::LOCALHOST.into(),
+            },
+            server: server_address(bind),
+            listen: listen.into(),
+            public_url: config.public_url.as_deref().map(Arc::from),
+            unix_socket: match server {
+                Some(_) => None,
+                None => config
+                    .unix_socket
+                    .as_ref()
+                    .map(|socket| Arc::new(path.join(socket))),
+            },
+            hosts: Arc::new(std::sync::RwLock::new(
+                config
+                    .allowed_hosts
+                    .iter()
+                    .flatten()
+                    .map(|host| host.to_ascii_lowercase())
+                    .chain(config.public_url.as_deref().and_then(url_host))
+                    .collect(),
+            )),
+            port_strategy: config.port_strategy.unwrap_or_default(),
+            port_attempts: config.port_attempts.unwrap_or(10).max(1),
+        }
+    }
+}
+
+impl LspFileService {
+    /// Port of the server once it accepts connections, `None` if it didn't come up within 5 seconds
+    pub(super) async fn listening(&self) -> Option<u16> {
+        let address = match self.addresses.server.is_unspecified() {
+            true => Ipv4Addr::LOCALHOST.into(),
+            false => self.addresses.server,
+        };
+        for _ in 0..50 {
+            let port = *self.port.read().await;
+            if tokio::net::TcpStream::connect((address, port))
+                .await
+                .is_ok()
+            {
+                return Some(port);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        None
+    }
+
+    /// Url other devices on the local network open the pages at, `None` if the server only answers on loopback
+    pub(super) async fn lan_url(&self, public: bool) -> Option<String> {
+        let forwarded = Some(&self.addresses.host)
+            .filter(|host| !host.is_loopback())
+            .or_else(|| {
+                self.addresses
+                    .listen
+                    .iter()
+                    .find(|address| !address.is_loopback() && !address.is_unspecified())
+            });
+        let address = match forwarded {
+            Some(address) => *address,
+            None if public => lan_address().await?,
+            None => return None,
+        };
+        let address = SocketAddr::new(address, *self.port.read().await);
+        Some(format!("http://{}/", address))
+    }
+
+    /// Url pages are opened at, behind `public_url` if a proxy serves the workspace
+    pub(super) async fn page_url(&self, file: &str) -> String {
+        let file = file.trim_start_matches('/');
+        match &self.addresses.public_url {
+            Some(base) => format!("{}/{}", base.trim_end_matches('/'), file),
+            None => {
+                let address = SocketAddr::new(self.addresses.host, *self.port.read().await);
+                format!("http://{}/{}", address, file)
+            }
+        }
+    }
+}
+
+impl Backend {
+    /// Forwards the `listen` addresses of a workspace to its server once it is up
+    pub(super) fn forward_addresses(&self, fs: LspFileService) -> JoinHandle<()> {
+        let backend = self.clone();
+        tokio::spawn(async move {
+            let Some(port) = fs.listening().await else {
+                return;
+            };
+            // aborting this task drops the set, which stops the listeners
+            let mut listeners = JoinSet::new();
+            for address in fs.addresses.listen.iter() {
+                if let Err(e) = forward::listen(*address, port, &mut listeners).await {
+                    let message = format!("failed to listen on {}:{}: {}", address, port, e);
+                    backend.log(&fs, MessageType::WARNING, message).await;
+                }
+            }
+            while listeners.join_next().await.is_some() {}
+        })
+    }
+}
+
+/// Lowercase host name of an url, `None` if it can't be parsed
+pub(super) fn url_host(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    url.host_str().map(|host| host.to_ascii_lowercase())
+}
+
+/// Address the server binds for `bind`, it only takes loopback or every ipv4 interface
+fn server_address(bind: IpAddr) -> IpAddr {
+    match bind.is_unspecified() {
+        true => Ipv4Addr::UNSPECIFIED.into(),
+        false => Ipv4Addr::LOCALHOST.into(),
+    }
+}
+
+/// Whether the server can't bind `address` itself and it is forwarded to its loopback port,
+/// like the ip of one interface or an ipv6 address
+fn is_forwarded(address: IpAddr) -> bool {
+    address != server_address(address)
+}
+
+/// Address of the interface with the default route, which devices on the local network reach
+pub(super) async fn lan_address() -> Option<IpAddr> {
+    // connecting a udp socket only picks the route, nothing is sent
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.ok()?;
+    socket.connect((Ipv4Addr::new(8, 8, 8, 8), 80)).await.ok()?;
+    let address = socket.local_addr().ok()?.ip();
+    (!address.is_loopback() && !address.is_unspecified()).then_some(address)
+}
+
+/// A port of `host` nothing listens on
+pub(super) async fn free_port(host: IpAddr) -> Option<u16> {
+    let listener = TcpListener::bind((host, 0)).await.ok()?;
+    listener.local_addr().ok().map(|address| address.port())
+}
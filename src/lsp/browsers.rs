@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use tokio::task::JoinHandle;
+use tower_lsp::lsp_types::{MessageType, ShowDocumentParams, Url};
+
+use crate::browser::{self, Browser};
+use crate::Config;
+
+use super::{Backend, LspFileService};
+
+/// Browsers the pages of a workspace are opened and captured in
+#[derive(Clone)]
+pub(super) struct Browsers {
+    /// Open pages with `window/showDocument` instead of launching a browser
+    pub(super) open_in_editor: bool,
+    /// Browser pages are opened in, the system default if unset
+    pub(super) browser: Option<Arc<Browser>>,
+    /// Chromium executable for screenshots
+    pub(super) headless: Option<Arc<str>>,
+}
+
+impl Browsers {
+    /// Browsers of the configuration, a missing browser falls back to the system default
+    pub(super) fn new(config: &Config) -> Self {
+        Self {
+            open_in_editor: config.open_in_editor.unwrap_or_default(),
+            browser: config.browser.clone().map(|program| {
+                Arc::new(Browser::new(
+                    program,
+                    config.browser_args.clone().unwrap_or_default(),
+                    config.browser_profile.clone(),
+                    config.browser_incognito.unwrap_or_default(),
+                ))
+            }),
+            headless: config.headless_browser.as_deref().map(Arc::from),
+        }
+    }
+}
+
+impl Backend {
+    /// Opens the index of a workspace once its server accepts connections
+    pub(super) fn open_when_listening(&self, fs: LspFileService) -> JoinHandle<()> {
+        let backend = self.clone();
+        tokio::spawn(async move {
+            if fs.listening().await.is_none() {
+                return;
+            }
+            backend.open_browser(&fs, &fs.page_url("").await).await;
+        })
+    }
+
+    /// Opens `url` in the browser of a workspace, offering to retry or to show the url if that fails
+    pub(super) async fn open_browser(&self, fs: &LspFileService, url: &str) -> bool {
+        loop {
+            let error = match fs.browsers.open_in_editor {
+                true => "the editor refused".to_string(),
+                false => match browser::open(fs.browsers.browser.as_deref(), url) {
+                    Ok(()) => return true,
+                    Err(e) => e.to_string(),
+                },
+            };
+            // remote sessions have no browser to launch, the editor may still open it on its side
+            if self.show_document(url).await {
+                return true;
+            }
+            let message = format!("failed to open browser {}", error);
+            self.log(fs, MessageType::WARNING, message.clone()).await;
+            match self
+                .ask(MessageType::WARNING, message, &["Retry", "Copy URL"])
+                .await
+                .as_deref()
+            {
+                Some("Retry") => continue,
+                // there is no clipboard access, the url is shown to copy it from
+                Some(_) => {
+                    self.client
+                        .show_message(MessageType::INFO, url.to_string())
+                        .await
+                }
+                None => {}
+            }
+            return false;
+        }
+    }
+
+    /// Asks the editor to open `url` externally, true if it did
+    async fn show_document(&self, url: &str) -> bool {
+        let (true, Ok(uri)) = (*self.show_document.read().await, Url::parse(url)) else {
+            return false;
+        };
+        let params = ShowDocumentParams {
+            uri,
+            external: Some(true),
+            take_focus: Some(true),
+            selection: None,
+        };
+        self.client.show_document(params).await.unwrap_or(false)
+    }
+}
@@ -0,0 +1,202 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use dashmap::DashSet;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::task::JoinHandle;
+use tower_lsp::lsp_types::MessageType;
+
+use crate::bundle::Bundler;
+use crate::css::CssProcessor;
+use crate::hook::Hook;
+use crate::supervise::Backoff;
+use crate::tailwind::Tailwind;
+use crate::wasm;
+use crate::Config;
+
+use super::{Backend, LspFileService, REPORTED_FAILURES};
+
+/// Builds the server of a workspace runs, or waits for
+#[derive(Clone)]
+pub(super) struct Builds {
+    pub(super) css: Option<Arc<CssProcessor>>,
+    pub(super) bundler: Option<Arc<Bundler>>,
+    pub(super) tailwind: Option<Arc<Tailwind>>,
+    /// Command that has to succeed before a save reloads the browsers
+    pub(super) on_save: Option<Arc<Hook>>,
+    /// Build of a Rust wasm project, run when one of its sources is saved
+    pub(super) wasm_build: Option<Arc<Hook>>,
+    /// Sources of the served directory, their changes reload every page
+    pub(super) sources: Option<Arc<PathBuf>>,
+    /// Written by an external build once it is done, changes wait for it before reloading
+    pub(super) build_marker: Option<Arc<PathBuf>>,
+    /// Changes since the build marker last changed
+    pub(super) built: Arc<DashSet<PathBuf>>,
+}
+
+impl Builds {
+    /// Builds configured for the workspace at `path` serving `root`
+    pub(super) fn new(config: &Config, path: &Path, root: &Path) -> Self {
+        let wasm_project = config.wasm.unwrap_or_else(|| wasm::detect(path));
+        Self {
+            css: config
+                .css_command
+                .clone()
+                .and_then(CssProcessor::new)
+                .map(Arc::new),
+            bundler: config.bundle.as_ref().and_then(|entries| {
+                let command = config
+                    .esbuild_command
+                    .clone()
+                    .unwrap_or_else(|| vec!["npx".to_string(), "esbuild".to_string()]);
+                Bundler::new(command, root, path, entries).map(Arc::new)
+            }),
+            tailwind: config
+                .tailwind
+                .unwrap_or_else(|| Tailwind::detect(path))
+                .then(|| {
+                    let command = config
+                        .tailwind_command
+                        .clone()
+                        .unwrap_or_else(|| vec!["npx".to_string(), "tailwindcss".to_string()]);
+                    let output = root.join(
+                        config
+                            .tailwind_output
+                            .as_deref()
+                            .unwrap_or(Path::new("tailwind.css")),
+                    );
+                    Tailwind::new(command, config.tailwind_input.clone(), output)
+                })
+                .flatten()
+                .map(Arc::new),
+            on_save: config.on_save.clone().and_then(Hook::new).map(Arc::new),
+            wasm_build: wasm_project
+                .then(|| {
+                    config.wasm_command.clone().unwrap_or_else(|| {
+                        wasm::BUILD_COMMAND
+                            .iter()
+                            .map(|arg| arg.to_string())
+                            .collect()
+                    })
+                })
+                .and_then(Hook::new)
+                .map(Arc::new),
+            sources: config
+                .watch_dir
+                .as_ref()
+                .map(|dir| Arc::new(path.join(dir))),
+            build_marker: config
+                .build_marker
+                .as_ref()
+                .map(|marker| Arc::new(path.join(marker))),
+            built: Default::default(),
+        }
+    }
+}
+
+impl Backend {
+    /// Runs the configured save command, and the wasm build for Rust sources,
+    /// returns false if the reload has to be skipped
+    pub(super) async fn run_save_hook(&self, path: &Path, service: &LspFileService) -> bool {
+        let wasm_build = service
+            .builds
+            .wasm_build
+            .as_ref()
+            .filter(|_| wasm::is_source(path));
+        for hook in [service.builds.on_save.as_ref(), wasm_build]
+            .into_iter()
+            .flatten()
+        {
+            self.log(
+                service,
+                MessageType::INFO,
+                format!("running {}", hook.name()),
+            )
+            .await;
+            if let Err(e) = hook.run(&service.workspace, path).await {
+                let message = format!("{} failed: {}", hook.name(), e);
+                service.logger.log(MessageType::ERROR, &message).await;
+                self.client.show_message(MessageType::ERROR, message).await;
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Reloads what changed while the build ran, every page if that isn't known
+    pub(super) async fn build_done(&self, service: &LspFileService) {
+        self.log(service, MessageType::INFO, "build done".to_string())
+            .await;
+        let changed: Vec<PathBuf> = service
+            .builds
+            .built
+            .iter()
+            .map(|path| path.clone())
+            .collect();
+        if changed.is_empty() {
+            return self.reload_pages(service).await;
+        }
+        for path in changed {
+            service.builds.built.remove(&path);
+            self.reload(&path, service, true).await;
+        }
+    }
+
+    /// Keeps the tailwind watcher of a workspace running, restarting it if it exits
+    pub(super) fn run_tailwind(&self, fs: LspFileService) -> JoinHandle<()> {
+        let backend = self.clone();
+        tokio::spawn(async move {
+            let Some(tailwind) = &fs.builds.tailwind else {
+                return;
+            };
+            let mut backoff = Backoff::default();
+            loop {
+                let started = Instant::now();
+                let mut child = match tailwind.spawn(&fs.workspace) {
+                    Ok(child) => child,
+                    Err(e) => {
+                        let message = format!("failed to run {}: {}", tailwind.name(), e);
+                        backend.log(&fs, MessageType::ERROR, message.clone()).await;
+                        backend
+                            .client
+                            .show_message(MessageType::ERROR, message)
+                            .await;
+                        return;
+                    }
+                };
+                // rebuilds and errors are reported on stderr
+                if let Some(stderr) = child.stderr.take() {
+                    let mut lines = BufReader::new(stderr).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if !line.trim().is_empty() {
+                            let message = format!("tailwind: {}", line.trim());
+                            backend.log(&fs, MessageType::LOG, message).await;
+                        }
+                    }
+                }
+                let status = child
+                    .wait()
+                    .await
+                    .map_or_else(|e| e.to_string(), |status| status.to_string());
+                let delay = backoff.failed(started.elapsed());
+                let message = format!(
+                    "{} exited ({}), restarting in {}s",
+                    tailwind.name(),
+                    status,
+                    delay.as_secs()
+                );
+                backend
+                    .log(&fs, MessageType::WARNING, message.clone())
+                    .await;
+                if backoff.failures() == REPORTED_FAILURES {
+                    backend
+                        .client
+                        .show_message(MessageType::ERROR, message)
+                        .await;
+                }
+                tokio::time::sleep(delay).await;
+            }
+        })
+    }
+}
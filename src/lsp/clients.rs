@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::MessageType;
+
+use crate::log::Logger;
+use crate::proxy::Proxy;
+use crate::relay::{ClientFeatures, Relay};
+use crate::Config;
+
+use super::{Backend, LspFileService};
+
+/// `liveServer/clientConnected`, a browser connected to the relay of a workspace
+enum ClientConnected {}
+
+impl Notification for ClientConnected {
+    type Params = Value;
+    const METHOD: &'static str = "liveServer/clientConnected";
+}
+
+/// `liveServer/clientDisconnected`, a browser closed its connection to the relay
+enum ClientDisconnected {}
+
+impl Notification for ClientDisconnected {
+    type Params = Value;
+    const METHOD: &'static str = "liveServer/clientDisconnected";
+}
+
+/// Features of the injected client script
+pub(super) fn client_features(config: &Config) -> ClientFeatures {
+    ClientFeatures {
+        sync: config.sync.unwrap_or_default(),
+        console: config.console.unwrap_or_default(),
+        hmr: config.hmr.unwrap_or_default(),
+        reload: config.sse_reload.unwrap_or_default(),
+        presence: config.presence.unwrap_or_default(),
+        follow: config.follow.unwrap_or_default(),
+        documents: config.markdown.unwrap_or_default(),
+        inspector: config.inspector.unwrap_or_default(),
+        toolbar: config.toolbar.unwrap_or_default(),
+    }
+}
+
+/// Proxy of the configured routes for the server `name`, recording below the workspace at `path`.
+/// Invalid routes are dropped with a warning
+pub(super) fn proxy(
+    config: &Config,
+    name: &str,
+    path: &Path,
+    warnings: &mut Vec<String>,
+) -> Option<Proxy> {
+    let routes = config.proxy.as_ref().filter(|routes| !routes.is_empty())?;
+    let recordings = path.join(
+        config
+            .proxy_recordings
+            .as_deref()
+            .unwrap_or(Path::new("recordings")),
+    );
+    match Proxy::new(routes, config.proxy_mode.unwrap_or_default(), recordings) {
+        Ok(proxy) => Some(proxy),
+        Err(e) => {
+            warnings.push(format!("invalid proxy for {}: {}", name, e));
+            None
+        }
+    }
+}
+
+impl Backend {
+    /// Passes events browsers reported over the relay on to the client
+    pub(super) fn forward_reports(
+        &self,
+        name: &str,
+        relay: &Arc<Relay>,
+        logger: &Logger,
+        presence: bool,
+        workspace: &Arc<PathBuf>,
+    ) -> JoinHandle<()> {
+        let mut reports = relay.reports();
+        let backend = self.clone();
+        let workspace = workspace.clone();
+        let client = self.client.clone();
+        let logger = logger.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            loop {
+                let report = match reports.recv().await {
+                    Ok(report) => report,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let field = |key: &str| report.get(key).and_then(Value::as_str).unwrap_or_default();
+                if matches!(field("type"), "connect" | "disconnect") {
+                    // the browser count lenses are outdated now
+                    let _ = client.code_lens_refresh().await;
+                    if !presence {
+                        continue;
+                    }
+                    let mut params = report.get("client").cloned().unwrap_or_default();
+                    if let Value::Object(params) = &mut params {
+                        params.insert("workspace".to_string(), Value::from(name.as_str()));
+                    }
+                    match field("type") {
+                        "connect" => client.send_notification::<ClientConnected>(params).await,
+                        _ => client.send_notification::<ClientDisconnected>(params).await,
+                    }
+                } else if field("type") == "console" {
+                    let ty = match field("level") {
+                        "error" => MessageType::ERROR,
+                        "warn" => MessageType::WARNING,
+                        "info" => MessageType::INFO,
+                        _ => MessageType::LOG,
+                    };
+                    let message = format!(
+                        "[{}] console.{} {}: {}",
+                        name,
+                        field("level"),
+                        field("page"),
+                        field("message")
+                    );
+                    logger.log(ty, &message).await;
+                    client.log_message(ty, message).await;
+                } else if field("type") == "pause" {
+                    let state = report
+                        .get("paused")
+                        .and_then(Value::as_bool)
+                        .unwrap_or_default();
+                    let service = backend
+                        .workspace_folders
+                        .read()
+                        .await
+                        .get(workspace.as_ref())
+                        .map(|(_, service)| service.clone());
+                    if let Some(service) = service {
+                        backend.pause_reload(&service, state, "the toolbar").await;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Refreshes the browser count lenses as reload sockets open and close
+    pub(super) fn refresh_client_lenses(&self, fs: LspFileService) -> JoinHandle<()> {
+        let client = self.client.clone();
+        let mut sockets = fs.sockets.subscribe();
+        tokio::spawn(async move {
+            while sockets.changed().await.is_ok() {
+                let _ = client.code_lens_refresh().await;
+            }
+        })
+    }
+}
@@ -0,0 +1,673 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tower_lsp::lsp_types::MessageType;
+
+use super::addresses::lan_address;
+use super::{Backend, LspFileService};
+use crate::log::iso_date;
+use crate::random;
+use crate::screenshot;
+use crate::scripts;
+use crate::share;
+
+/// Commands of `workspace/executeCommand`, the ones advertised in `initialize`
+#[derive(Clone, Copy)]
+pub(super) enum Command {
+    OpenProjectWeb,
+    StartProjectServer,
+    BroadcastToBrowsers,
+    ExportProjectStatic,
+    CaptureProjectScreenshot,
+    SetProjectOffline,
+    ForceProjectReload,
+    PauseLiveReload,
+    ResumeLiveReload,
+    CopyLanUrl,
+    ShareProjectTemporarily,
+    ProjectRequestStats,
+    ExportProjectHar,
+    RunNpmScript,
+}
+
+impl Command {
+    pub(super) const ALL: [Command; 14] = [
+        Command::OpenProjectWeb,
+        Command::StartProjectServer,
+        Command::BroadcastToBrowsers,
+        Command::ExportProjectStatic,
+        Command::CaptureProjectScreenshot,
+        Command::SetProjectOffline,
+        Command::ForceProjectReload,
+        Command::PauseLiveReload,
+        Command::ResumeLiveReload,
+        Command::CopyLanUrl,
+        Command::ShareProjectTemporarily,
+        Command::ProjectRequestStats,
+        Command::ExportProjectHar,
+        Command::RunNpmScript,
+    ];
+
+    /// Name the client sends the command with
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            Command::OpenProjectWeb => "openProjectWeb",
+            Command::StartProjectServer => "startProjectServer",
+            Command::BroadcastToBrowsers => "broadcastToBrowsers",
+            Command::ExportProjectStatic => "exportProjectStatic",
+            Command::CaptureProjectScreenshot => "captureProjectScreenshot",
+            Command::SetProjectOffline => "setProjectOffline",
+            Command::ForceProjectReload => "forceProjectReload",
+            Command::PauseLiveReload => "pauseLiveReload",
+            Command::ResumeLiveReload => "resumeLiveReload",
+            Command::CopyLanUrl => "copyLanUrl",
+            Command::ShareProjectTemporarily => "shareProjectTemporarily",
+            Command::ProjectRequestStats => "projectRequestStats",
+            Command::ExportProjectHar => "exportProjectHar",
+            Command::RunNpmScript => "runNpmScript",
+        }
+    }
+
+    /// Command of a name, `None` if the server has no such command
+    pub(super) fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|command| command.name() == name)
+    }
+}
+
+impl Backend {
+    /// Runs a command with the arguments of its `workspace/executeCommand` request
+    pub(super) async fn execute(
+        &self,
+        command: Command,
+        args: &[Value],
+    ) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        match command {
+            Command::OpenProjectWeb => self.open_project_web(args).await,
+            Command::StartProjectServer => self.start_server(args).await,
+            Command::BroadcastToBrowsers => self.broadcast_to_browsers(args).await,
+            Command::ExportProjectStatic => self.export_static(args).await,
+            Command::CaptureProjectScreenshot => self.capture_screenshot(args).await,
+            Command::SetProjectOffline => self.set_offline(args).await,
+            Command::ForceProjectReload => self.force_reload(args).await,
+            Command::PauseLiveReload => self.set_reload_paused(args, true).await,
+            Command::ResumeLiveReload => self.set_reload_paused(args, false).await,
+            Command::CopyLanUrl => self.copy_lan_url(args).await,
+            Command::ShareProjectTemporarily => self.share_temporarily(args).await,
+            Command::ProjectRequestStats => self.request_stats(args).await,
+            Command::ExportProjectHar => self.export_har(args).await,
+            Command::RunNpmScript => self.run_npm_script(args).await,
+        }
+    }
+
+    /// `openProjectWeb`, opens a page of a workspace in the browser, serving the workspace if it waited for it.
+    /// Takes the workspace root and the page
+    async fn open_project_web(&self, args: &[Value]) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let (Some(project), Some(file)) = (
+            args.first().and_then(Value::as_str),
+            args.get(1).and_then(Value::as_str),
+        ) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "URL argument missing",
+            ));
+        };
+        let Some(service) = self
+            .workspace_folders
+            .read()
+            .await
+            .get(Path::new(project))
+            .map(|(_, service)| service.clone())
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "URL argument invalid",
+            ));
+        };
+        self.activate(&service).await;
+        if !self
+            .open_browser(&service, &service.page_url(file).await)
+            .await
+        {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "failed to open browser",
+            ));
+        }
+        Ok(None)
+    }
+
+    /// `broadcastToBrowsers`, sends a script (a string) or an event (an object with a `type`)
+    /// to the browsers of one workspace, or of every workspace if only the payload is given
+    async fn broadcast_to_browsers(
+        &self,
+        args: &[Value],
+    ) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let (workspace, payload) = match args {
+            [payload] => (None, payload),
+            [Value::String(workspace), payload] => (Some(Path::new(workspace)), payload),
+            _ => {
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                    "payload argument missing",
+                ))
+            }
+        };
+        let event = match payload {
+            Value::String(code) => json!({ "type": "script", "code": code }),
+            Value::Object(event) if event.get("type").is_some_and(Value::is_string) => {
+                payload.clone()
+            }
+            _ => {
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                    "payload has to be a script or an event with a type",
+                ))
+            }
+        };
+        let mut clients = 0;
+        for (path, (_, fs)) in self.workspace_folders.read().await.iter() {
+            if workspace.is_some_and(|workspace| workspace != path) {
+                continue;
+            }
+            if let Some(relay) = &fs.relay {
+                relay.broadcast(&event);
+                clients += relay.clients();
+            }
+        }
+        Ok(Some(json!({ "clients": clients })))
+    }
+
+    /// `projectRequestStats`, how often each url of one workspace, or of every workspace, was requested
+    async fn request_stats(&self, args: &[Value]) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let workspace = match args {
+            [] => None,
+            [Value::String(workspace)] => Some(Path::new(workspace)),
+            _ => {
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                    "workspace argument invalid",
+                ))
+            }
+        };
+        let mut workspaces = vec![];
+        for (path, (name, fs)) in self.workspace_folders.read().await.iter() {
+            if workspace.is_some_and(|workspace| workspace != path) {
+                continue;
+            }
+            let mut requests: Vec<(String, u64)> = fs
+                .requests
+                .iter()
+                .map(|entry| (entry.key().clone(), *entry.value()))
+                .collect();
+            // most requested first
+            requests.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            let requests: Vec<Value> = requests
+                .into_iter()
+                .map(|(path, count)| json!({ "path": path, "count": count }))
+                .collect();
+            workspaces.push(json!({ "name": name, "root": path, "requests": requests }));
+        }
+        Ok(Some(json!({ "workspaces": workspaces })))
+    }
+
+    /// `copyLanUrl`, the url devices on the local network open a workspace at, for the editor to copy.
+    /// Takes the workspace root, which can be left out while only one is open
+    async fn copy_lan_url(&self, args: &[Value]) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let workspace = match args {
+            [] => None,
+            [Value::String(workspace)] => Some(Path::new(workspace)),
+            _ => {
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                    "workspace argument invalid",
+                ))
+            }
+        };
+        let service = self
+            .workspace_folders
+            .read()
+            .await
+            .iter()
+            .find(|(path, _)| workspace.is_none_or(|workspace| workspace == path.as_path()))
+            .map(|(_, (_, service))| service.clone());
+        let Some(service) = service else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "workspace argument invalid",
+            ));
+        };
+        let public = self.bind.read().await.is_unspecified();
+        match service.lan_url(public).await {
+            Some(url) => {
+                self.client
+                    .show_message(MessageType::INFO, format!("Reachable at {}", url))
+                    .await;
+                Ok(Some(json!({ "url": url })))
+            }
+            None => Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "the server only answers on this machine, enable `public` or `listen`",
+            )),
+        }
+    }
+
+    /// `shareProjectTemporarily`, opens a workspace to the network behind a random token until `share_ttl` passed.
+    /// Takes the workspace root and optionally the minutes to share it for
+    async fn share_temporarily(&self, args: &[Value]) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let (workspace, minutes) = match args {
+            [Value::String(workspace)] => (workspace, None),
+            [Value::String(workspace), Value::Number(minutes)] => (workspace, minutes.as_u64()),
+            _ => {
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                    "workspace argument missing",
+                ))
+            }
+        };
+        let Some(service) = self
+            .workspace_folders
+            .read()
+            .await
+            .get(Path::new(workspace))
+            .map(|(_, service)| service.clone())
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "unknown workspace",
+            ));
+        };
+        self.activate(&service).await;
+        service.listening().await;
+        let ttl = minutes.map_or(service.sharing.ttl, |minutes| {
+            Duration::from_secs(minutes.saturating_mul(60))
+        });
+        let listener = share::bind().await.map_err(|e| {
+            tower_lsp::jsonrpc::Error::invalid_params(format!("failed to open a share: {}", e))
+        })?;
+        let public_port = listener.local_addr().map_or(0, |address| address.port());
+        let token = random::token().map_err(|e| {
+            tower_lsp::jsonrpc::Error::invalid_params(format!("failed to open a share: {}", e))
+        })?;
+        let host = lan_address()
+            .await
+            .map_or("localhost".to_string(), |address| address.to_string());
+        let url = format!("http://{}:{}/{}/", host, public_port, token);
+        let server = SocketAddr::new(service.addresses.host, *service.port.read().await);
+        let limits = service.http.limits;
+        let backend = self.clone();
+        let shared = service.clone();
+        let task = tokio::spawn(async move {
+            let _ = tokio::time::timeout(ttl, share::serve(listener, server, token, limits)).await;
+            let message = format!("Sharing {} ended", shared.root.display());
+            backend.log(&shared, MessageType::INFO, message).await;
+        });
+        if let Some(previous) = service
+            .sharing
+            .share
+            .lock()
+            .await
+            .replace(task.abort_handle())
+        {
+            previous.abort();
+        }
+        self.tasks
+            .lock()
+            .await
+            .entry(service.id.to_path_buf())
+            .or_default()
+            .push(task);
+        let message = format!("Shared at {} for {} minutes", url, ttl.as_secs() / 60);
+        self.log(&service, MessageType::INFO, message.clone()).await;
+        self.client.show_message(MessageType::INFO, message).await;
+        Ok(Some(json!({
+            "url": url,
+            // `null` for a share outlasting the clock
+            "expires": SystemTime::now().checked_add(ttl).map(iso_date),
+        })))
+    }
+
+    /// `setProjectOffline`, turns the offline simulation of one workspace, or of every workspace
+    /// if only the state is given, on or off
+    async fn set_offline(&self, args: &[Value]) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let (workspace, offline) = match args {
+            [Value::Bool(offline)] => (None, *offline),
+            [Value::String(workspace), Value::Bool(offline)] => {
+                (Some(Path::new(workspace)), *offline)
+            }
+            _ => {
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                    "offline argument missing",
+                ))
+            }
+        };
+        let mut workspaces = 0;
+        for (path, (_, fs)) in self.workspace_folders.read().await.iter() {
+            if workspace.is_some_and(|workspace| workspace != path) {
+                continue;
+            }
+            fs.simulation.offline.store(offline, Ordering::Relaxed);
+            workspaces += 1;
+        }
+        Ok(Some(
+            json!({ "offline": offline, "workspaces": workspaces }),
+        ))
+    }
+
+    /// `startProjectServer`, serves a workspace that isn't started automatically, returns its url
+    async fn start_server(&self, args: &[Value]) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let [Value::String(workspace)] = args else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "workspace argument missing",
+            ));
+        };
+        let Some(service) = self
+            .workspace_folders
+            .read()
+            .await
+            .get(Path::new(workspace))
+            .map(|(_, service)| service.clone())
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "unknown workspace",
+            ));
+        };
+        self.activate(&service).await;
+        Ok(Some(json!({ "url": service.page_url("").await })))
+    }
+
+    /// `pauseLiveReload` and `resumeLiveReload`, of one workspace or of every one
+    async fn set_reload_paused(
+        &self,
+        args: &[Value],
+        paused: bool,
+    ) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let workspace = match args {
+            [] => None,
+            [Value::String(workspace)] => Some(Path::new(workspace)),
+            _ => {
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                    "expected a workspace or no argument",
+                ))
+            }
+        };
+        let services: Vec<LspFileService> = self
+            .workspace_folders
+            .read()
+            .await
+            .iter()
+            .filter(|(path, _)| workspace.is_none_or(|workspace| workspace == *path))
+            .map(|(_, (_, service))| service.clone())
+            .collect();
+        for service in &services {
+            self.pause_reload(service, paused, "the editor").await;
+        }
+        Ok(Some(
+            json!({ "paused": paused, "workspaces": services.len() }),
+        ))
+    }
+
+    /// `forceProjectReload`, reloads every page of a workspace though nothing changed, even while live reload is paused
+    async fn force_reload(&self, args: &[Value]) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let [Value::String(workspace)] = args else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "workspace argument missing",
+            ));
+        };
+        let Some(service) = self
+            .workspace_folders
+            .read()
+            .await
+            .get(Path::new(workspace))
+            .map(|(_, service)| service.clone())
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "unknown workspace",
+            ));
+        };
+        self.log(&service, MessageType::INFO, "forced reload".to_string())
+            .await;
+        service.cache.clear();
+        self.reload_pages(&service).await;
+        Ok(None)
+    }
+
+    /// `runNpmScript`, starts a script of the workspace `package.json` and streams its output to the log.
+    ///
+    /// It runs until it exits or the workspace stops, a script can't be started twice at once.
+    async fn run_npm_script(&self, args: &[Value]) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let [Value::String(workspace), Value::String(script)] = args else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "workspace and script arguments missing",
+            ));
+        };
+        let Some(service) = self
+            .workspace_folders
+            .read()
+            .await
+            .get(Path::new(workspace))
+            .map(|(_, service)| service.clone())
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "unknown workspace",
+            ));
+        };
+        if !scripts::list(&service.workspace).await.contains(script) {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                "package.json has no script {}",
+                script
+            )));
+        }
+        if !service.scripts.insert(script.clone()) {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                "{} is already running",
+                script
+            )));
+        }
+        let mut child = match scripts::spawn(&service.workspace, script) {
+            Ok(child) => child,
+            Err(e) => {
+                service.scripts.remove(script);
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                    "failed to run {}: {}",
+                    script, e
+                )));
+            }
+        };
+        let name = format!(
+            "{} run {}",
+            scripts::package_manager(&service.workspace),
+            script
+        );
+        self.log(&service, MessageType::INFO, format!("running {}", name))
+            .await;
+        let backend = self.clone();
+        let script = script.clone();
+        let id = service.id.to_path_buf();
+        let task = tokio::spawn(async move {
+            tokio::join!(
+                backend.forward_output(&service, &script, child.stdout.take()),
+                backend.forward_output(&service, &script, child.stderr.take()),
+            );
+            let status = child.wait().await;
+            service.scripts.remove(&script);
+            let (ty, message) = match status {
+                Ok(status) if status.success() => (MessageType::INFO, format!("{} finished", name)),
+                Ok(status) => (MessageType::ERROR, format!("{} failed ({})", name, status)),
+                Err(e) => (MessageType::ERROR, format!("{} failed: {}", name, e)),
+            };
+            backend.log(&service, ty, message.clone()).await;
+            backend.client.show_message(ty, message).await;
+        });
+        // stopped along with the workspace
+        self.tasks.lock().await.entry(id).or_default().push(task);
+        Ok(None)
+    }
+
+    /// Logs the lines a script writes to `output`, prefixed with its name
+    async fn forward_output(
+        &self,
+        service: &LspFileService,
+        script: &str,
+        output: Option<impl AsyncRead + Unpin>,
+    ) {
+        let Some(output) = output else {
+            return;
+        };
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            self.log(service, MessageType::LOG, format!("[{}] {}", script, line))
+                .await;
+        }
+    }
+
+    /// `exportProjectStatic`, writes the served workspace to a directory, taken relative to the workspace root
+    async fn export_static(&self, args: &[Value]) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let [Value::String(workspace), Value::String(target)] = args else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "workspace and target arguments missing",
+            ));
+        };
+        let Some((_, service)) = self
+            .workspace_folders
+            .read()
+            .await
+            .get(Path::new(workspace))
+            .cloned()
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "workspace argument invalid",
+            ));
+        };
+        let target = service.workspace.join(target);
+        match service.export(&target).await {
+            Ok((files, failed)) => {
+                let message = format!("Exported {} files to {}", files, target.display());
+                self.log(&service, MessageType::INFO, message).await;
+                for path in &failed {
+                    let message = format!("failed to export {}", path.display());
+                    self.log(&service, MessageType::WARNING, message).await;
+                }
+                Ok(Some(
+                    json!({ "files": files, "failed": failed, "target": target }),
+                ))
+            }
+            Err(e) => {
+                let message = format!("export to {} failed: {}", target.display(), e);
+                self.client.show_message(MessageType::ERROR, &message).await;
+                Err(tower_lsp::jsonrpc::Error::invalid_params(message))
+            }
+        }
+    }
+
+    /// `exportProjectHar`, writes the latest requests of a workspace server to a HAR file in the workspace
+    async fn export_har(&self, args: &[Value]) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let [Value::String(workspace), Value::String(target)] = args else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "workspace and target arguments missing",
+            ));
+        };
+        let Some((_, service)) = self
+            .workspace_folders
+            .read()
+            .await
+            .get(Path::new(workspace))
+            .cloned()
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "workspace argument invalid",
+            ));
+        };
+        let target = service.workspace.join(target);
+        let origin = service.page_url("").await;
+        let har = service.har.export(origin.trim_end_matches('/'));
+        let entries = har["log"]["entries"].as_array().map_or(0, Vec::len);
+        let written = match target.parent() {
+            Some(dir) => tokio::fs::create_dir_all(dir).await,
+            None => Ok(()),
+        };
+        let written = match written {
+            Ok(()) => tokio::fs::write(&target, har.to_string()).await,
+            Err(e) => Err(e),
+        };
+        match written {
+            Ok(()) => {
+                let message = format!("Exported {} requests to {}", entries, target.display());
+                self.log(&service, MessageType::INFO, message).await;
+                Ok(Some(json!({ "entries": entries, "target": target })))
+            }
+            Err(e) => {
+                let message = format!("HAR export to {} failed: {}", target.display(), e);
+                self.client.show_message(MessageType::ERROR, &message).await;
+                Err(tower_lsp::jsonrpc::Error::invalid_params(message))
+            }
+        }
+    }
+
+    /// `captureProjectScreenshot`, saves a png of a page into the workspace.
+    ///
+    /// Takes the workspace root, the page and optionally `{ "width", "height", "output" }`.
+    async fn capture_screenshot(
+        &self,
+        args: &[Value],
+    ) -> tower_lsp::jsonrpc::Result<Option<Value>> {
+        let (Some(workspace), Some(file)) = (
+            args.first().and_then(Value::as_str),
+            args.get(1).and_then(Value::as_str),
+        ) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "workspace and page arguments missing",
+            ));
+        };
+        let options = args.get(2).cloned().unwrap_or_default();
+        let size = |key: &str, default: u32| {
+            options
+                .get(key)
+                .and_then(Value::as_u64)
+                .map_or(default, |v| v as u32)
+        };
+        let (width, height) = (size("width", 1280), size("height", 800));
+        let Some((_, service)) = self
+            .workspace_folders
+            .read()
+            .await
+            .get(Path::new(workspace))
+            .cloned()
+        else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "workspace argument invalid",
+            ));
+        };
+        let file = file.trim_start_matches('/');
+        let output = match options.get("output").and_then(Value::as_str) {
+            Some(output) => service.workspace.join(output),
+            None => {
+                let stem = Path::new(file)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "index".to_string());
+                service
+                    .workspace
+                    .join("screenshots")
+                    .join(format!("{}-{}x{}.png", stem, width, height))
+            }
+        };
+        self.activate(&service).await;
+        service.listening().await;
+        // the browser runs here, so it skips a public url and reaches the server at its bind address
+        let address = SocketAddr::new(service.addresses.host, *service.port.read().await);
+        let url = format!("http://{}/{}", address, file.trim_start_matches('/'));
+        match screenshot::capture(
+            service.browsers.headless.as_deref(),
+            &url,
+            width,
+            height,
+            &output,
+        )
+        .await
+        {
+            Ok(()) => {
+                let message = format!("Saved screenshot of {} to {}", url, output.display());
+                self.log(&service, MessageType::INFO, message).await;
+                Ok(Some(json!({ "path": output })))
+            }
+            Err(e) => {
+                let message = format!("screenshot of {} failed: {}", url, e);
+                self.client.show_message(MessageType::ERROR, &message).await;
+                Err(tower_lsp::jsonrpc::Error::invalid_params(message))
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde_json::json;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range, Url};
+
+use crate::compile::{self, Compiler, ASCIIDOC_COMMAND, RST_COMMAND};
+use crate::markdown;
+use crate::mount::Mounts;
+use crate::templates::{Rendered, Templates};
+use crate::uri::url_path;
+use crate::{Config, HighlightTheme};
+
+use super::{Backend, LspFileService};
+
+/// Pages of a workspace rendered from templates and documents
+#[derive(Clone)]
+pub(super) struct Documents {
+    pub(super) templates: Option<Arc<Templates>>,
+    /// Render `page.md` for `page.html`
+    pub(super) markdown: bool,
+    pub(super) highlight_theme: HighlightTheme,
+    /// Template languages and documents compiled to `page.html` by a command
+    pub(super) compilers: Arc<Vec<Compiler>>,
+    /// Browser bundles of the document renderers, served but not watched
+    pub(super) vendor: Arc<Mounts>,
+    /// Substitute `{{ env.NAME }}` in served html
+    pub(super) env: bool,
+}
+
+impl Documents {
+    /// Renderers configured for the workspace at `path` serving `root`
+    pub(super) fn new(config: &Config, path: &Path, root: &Path) -> Self {
+        let markdown = config.markdown.unwrap_or_default();
+        let mut vendor = Mounts::default();
+        if markdown {
+            let dir = config
+                .mermaid_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("node_modules/mermaid/dist"));
+            vendor.push(path, markdown::MERMAID_URL, &dir);
+            let dir = config
+                .katex_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("node_modules/katex/dist"));
+            vendor.push(path, markdown::KATEX_URL, &dir);
+        }
+        Self {
+            templates: config.templates.unwrap_or_default().then(|| {
+                Arc::new(Templates::new(
+                    root,
+                    config.template_partials.as_deref(),
+                    config.template_data.as_deref(),
+                    config.env.unwrap_or_default(),
+                ))
+            }),
+            markdown,
+            highlight_theme: config.highlight_theme.unwrap_or_default(),
+            compilers: Arc::new(
+                [
+                    (&["pug"][..], config.pug_command.clone()),
+                    (&["haml"], config.haml_command.clone()),
+                    (
+                        &["adoc", "asciidoc"],
+                        document_command(
+                            config.asciidoc,
+                            config.asciidoc_command.clone(),
+                            &ASCIIDOC_COMMAND,
+                        ),
+                    ),
+                    (
+                        &["rst"],
+                        document_command(config.rst, config.rst_command.clone(), &RST_COMMAND),
+                    ),
+                ]
+                .into_iter()
+                .filter_map(|(extensions, command)| Compiler::new(extensions, command?))
+                .collect(),
+            ),
+            vendor: Arc::new(vendor),
+            env: config.env.unwrap_or_default(),
+        }
+    }
+}
+
+impl LspFileService {
+    /// Page a markdown document or a compiled source renders to, if rendering it is enabled
+    pub(super) fn document_page(&self, file: &Path) -> Option<PathBuf> {
+        if self.documents.markdown && markdown::is_markdown(file) {
+            markdown::page_for(file)
+        } else {
+            self.documents
+                .compilers
+                .iter()
+                .find_map(|compiler| compiler.page_for(file))
+        }
+    }
+
+    /// Renders the document an html page without a file of its own is generated from
+    pub(super) async fn render_document(&self, page: &Path) -> Option<Rendered> {
+        let name = page.file_stem().unwrap_or_default().to_string_lossy();
+        if self.documents.markdown {
+            for source in markdown::source_for(page) {
+                if let Ok(text) = self.overlay.read(&source).await {
+                    return Some(Rendered {
+                        html: markdown::render(
+                            &String::from_utf8_lossy(&text),
+                            &name,
+                            self.documents.highlight_theme,
+                        ),
+                        includes: vec![source],
+                    });
+                }
+            }
+        }
+        for compiler in self.documents.compilers.iter() {
+            for source in compiler.source_for(page) {
+                if let Ok(text) = self.overlay.read(&source).await {
+                    let html = match compiler.compile(&self.workspace, &source, &text).await {
+                        Ok(html) => String::from_utf8_lossy(&html).into_owned(),
+                        Err(e) => compile::error_page(&source.display().to_string(), &e),
+                    };
+                    return Some(Rendered {
+                        html,
+                        includes: vec![source],
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Backend {
+    /// Scrolls browsers showing the rendered page of a document to `line`
+    pub(super) async fn scroll_preview(&self, uri: &Url, line: u32) {
+        for (path, service) in self.get_documents(uri).await {
+            let (Some(relay), true) = (&service.relay, service.client_features.documents) else {
+                continue;
+            };
+            let Some(page) = service.document_page(&service.served_path(&path)) else {
+                continue;
+            };
+            let rel = url_path(page.strip_prefix(service.root.as_ref()).unwrap_or(&page));
+            relay.broadcast(&json!({ "type": "line", "path": format!("/{}", rel), "line": line }));
+        }
+    }
+
+    /// Compiles a changed template and reports its error as a diagnostic, cleared once it compiles
+    pub(super) async fn check_compile(
+        &self,
+        path: &Path,
+        compiler: &Compiler,
+        service: &LspFileService,
+    ) {
+        let (Ok(text), Ok(uri)) = (service.overlay.read(path).await, Url::from_file_path(path))
+        else {
+            return;
+        };
+        let diagnostics = match compiler.compile(&service.workspace, path, &text).await {
+            Ok(_) => vec![],
+            Err(message) => {
+                let (line, character) = compile::error_position(&message).unwrap_or_default();
+                let position = Position::new(line, character);
+                vec![Diagnostic {
+                    range: Range::new(position, position),
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some(compiler.name().to_string()),
+                    message,
+                    ..Default::default()
+                }]
+            }
+        };
+        self.client
+            .publish_diagnostics(uri, diagnostics, None)
+            .await;
+    }
+}
+
+/// Command rendering a document format if it is `enabled`, `default` unless one is configured
+fn document_command(
+    enabled: Option<bool>,
+    command: Option<Vec<String>>,
+    default: &[&str],
+) -> Option<Vec<String>> {
+    enabled
+        .unwrap_or_default()
+        .then(|| command.unwrap_or_else(|| default.iter().map(|arg| arg.to_string()).collect()))
+}
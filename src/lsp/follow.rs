@@ -0,0 +1,56 @@
+use serde::Deserialize;
+use serde_json::json;
+use tower_lsp::lsp_types::{Position, Url};
+
+use crate::uri::url_path;
+
+use super::{is_html, Backend};
+
+/// Params of `liveServer/activeDocument`
+#[derive(Deserialize)]
+pub(super) struct ActiveDocument {
+    uri: Url,
+    /// Cursor in the document, rendered pages scroll to its line
+    position: Option<Position>,
+}
+
+impl Backend {
+    /// `liveServer/activeDocument`, sent by editor extensions when the focused document changes
+    /// or the cursor moves in it
+    pub(super) async fn active_document(&self, params: ActiveDocument) {
+        self.follow(&params.uri).await;
+        if let Some(position) = params.position {
+            self.scroll_preview(&params.uri, position.line).await;
+        }
+    }
+
+    /// Navigates browsers following the editor to the page of a document
+    pub(super) async fn follow(&self, uri: &Url) {
+        for (path, service) in self.get_documents(uri).await {
+            let (Some(relay), true) = (&service.relay, service.client_features.follow) else {
+                continue;
+            };
+            let mut page = service.served_path(&path);
+            if let Some(rendered) = service
+                .documents
+                .templates
+                .as_ref()
+                .and_then(|t| t.page_for(&page))
+            {
+                page = rendered;
+            }
+            if let Some(rendered) = service.document_page(&page) {
+                page = rendered;
+            }
+            if !is_html(&page) {
+                continue;
+            }
+            // a page outside the root has no path on the server
+            let Ok(rel) = page.strip_prefix(service.root.as_ref()) else {
+                continue;
+            };
+            let target = format!("/{}", url_path(rel));
+            relay.broadcast(&json!({ "type": "navigate", "path": target }));
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::csp;
+use crate::gateway::{self, Request, Route};
+use crate::headers;
+use crate::limits::Limits;
+use crate::rules::HeaderRules;
+use crate::{Config, TrailingSlash};
+
+use super::LspFileService;
+
+/// How the server of a workspace answers requests
+#[derive(Clone)]
+pub(super) struct Http {
+    /// Files served for a directory url, the first one that exists wins
+    pub(super) index_files: Arc<[String]>,
+    /// Serve `about.html` for `/about`
+    pub(super) clean_urls: bool,
+    /// Send precompressed variants of assets to browsers accepting them
+    pub(super) precompressed: bool,
+    /// Redirects between directory urls with and without a trailing slash
+    pub(super) trailing_slash: TrailingSlash,
+    /// Size from which assets are streamed from disk instead of read into memory
+    pub(super) stream_threshold: u64,
+    /// Caps on the connections to the server, its relay has its own count
+    pub(super) limits: Limits,
+    /// How long idle connections to the server are kept open for the next request
+    pub(super) keep_alive: Duration,
+    /// Response headers per glob of the url path
+    pub(super) header_rules: Arc<HeaderRules>,
+    /// Headers of `cross_origin_isolation` and `security_headers`, sent with every response
+    pub(super) headers: Arc<Vec<(&'static str, &'static str)>>,
+    /// Content-Security-Policy sent with every response but the generated ones
+    pub(super) csp: Option<Arc<csp::Policy>>,
+    /// Charset declared for html on disk that isn't utf-8 and declares none
+    pub(super) charset: Option<Arc<str>>,
+}
+
+impl Http {
+    /// Answers configured for the server `name`, a policy without a nonce is dropped with a warning
+    pub(super) fn new(config: &Config, name: &str, warnings: &mut Vec<String>) -> Self {
+        let csp = match config.csp.as_deref().map(csp::Policy::new) {
+            Some(Ok(policy)) => Some(Arc::new(policy)),
+            Some(Err(e)) => {
+                warnings.push(format!(
+                    "csp of {} needs a nonce: {}, serving without it",
+                    name, e
+                ));
+                None
+            }
+            None => None,
+        };
+        let mut preset_headers = vec![];
+        if config.cross_origin_isolation.unwrap_or_default() {
+            preset_headers.extend(headers::ISOLATION);
+        }
+        if config.security_headers.unwrap_or_default() {
+            preset_headers.extend(headers::SECURITY);
+        }
+        Self {
+            index_files: config
+                .index_files
+                .clone()
+                .unwrap_or_else(|| vec!["index.html".to_string()])
+                .into(),
+            clean_urls: config.clean_urls.unwrap_or_default(),
+            precompressed: config.precompressed.unwrap_or(true),
+            trailing_slash: config.trailing_slash.unwrap_or_default(),
+            stream_threshold: config.stream_threshold.unwrap_or(gateway::STREAM_THRESHOLD),
+            limits: Limits {
+                total: config.max_connections,
+                per_address: config.max_connections_per_address,
+            },
+            keep_alive: config
+                .keep_alive_timeout
+                .map_or(gateway::KEEP_ALIVE, Duration::from_secs),
+            header_rules: Arc::new(
+                config
+                    .header_rules
+                    .as_ref()
+                    .map(HeaderRules::new)
+                    .unwrap_or_default(),
+            ),
+            headers: Arc::new(preset_headers),
+            csp,
+            charset: config.charset.as_deref().map(Arc::from),
+        }
+    }
+}
+
+impl LspFileService {
+    /// Redirect of a directory url to the trailing slash policy, `None` if it follows it already
+    pub(super) fn redirect(&self, request: &Request) -> Option<Route> {
+        let path = request.path();
+        // `//host/` would redirect to another site, and the root always has its slash
+        if path.starts_with("//") || path == "/" {
+            return None;
+        }
+        let location = match (self.http.trailing_slash, path.ends_with('/')) {
+            (TrailingSlash::Add, false) => format!("{}/", path),
+            (TrailingSlash::Remove, true) => path.trim_end_matches('/').to_string(),
+            _ => return None,
+        };
+        Some(Route::Redirect(location + request.query()))
+    }
+
+    /// Index file of a directory and the html file of a clean url, `path` itself otherwise
+    pub(super) async fn index(&self, path: PathBuf) -> PathBuf {
+        let is_dir = tokio::fs::metadata(&path)
+            .await
+            .is_ok_and(|metadata| metadata.is_dir());
+        let exists = is_dir || self.overlay.exists(&path).await;
+        let dir = match (is_dir, exists, path.file_name()) {
+            (true, _, _) => Some(path.as_path()),
+            // the server asking for the default index of a directory that has another one
+            (_, false, Some(name)) if name == "index.html" => path.parent(),
+            _ => None,
+        };
+        if let Some(dir) = dir {
+            for name in self.http.index_files.iter() {
+                let index = dir.join(name);
+                if self.overlay.exists(&index).await {
+                    return index;
+                }
+            }
+        }
+        if self.http.clean_urls && !exists && path.extension().is_none() {
+            let html = path.with_extension("html");
+            if self.overlay.exists(&html).await {
+                return html;
+            }
+        }
+        path
+    }
+}
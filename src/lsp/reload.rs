@@ -0,0 +1,238 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+use tower_lsp::lsp_types::MessageType;
+
+use crate::debounce::{self, Batch, Debounce};
+use crate::uri::url_path;
+use crate::Config;
+
+use super::{is_module, Backend, LspFileService};
+
+/// Live reload of the pages of a workspace
+#[derive(Clone)]
+pub(super) struct Reloads {
+    /// Hold back reloads, toggled from the toolbar or with `pauseLiveReload`
+    pub(super) paused: Arc<AtomicBool>,
+    /// Whether a change was held back while paused
+    pub(super) missed: Arc<AtomicBool>,
+    /// Per extension delay before a change reloads
+    pub(super) debounce: Arc<Debounce>,
+    /// Reloads collected to be sent together
+    pub(super) batch: Arc<Batch>,
+}
+
+impl Reloads {
+    /// Reload timing of the configuration, a reconfigured workspace stays paused
+    pub(super) fn new(config: &Config, previous: Option<&LspFileService>) -> Self {
+        Self {
+            paused: previous
+                .map(|previous| previous.reloads.paused.clone())
+                .unwrap_or_default(),
+            missed: previous
+                .map(|previous| previous.reloads.missed.clone())
+                .unwrap_or_default(),
+            debounce: Arc::new(
+                config
+                    .reload_delay
+                    .as_ref()
+                    .map(Debounce::new)
+                    .unwrap_or_default(),
+            ),
+            batch: Arc::new(Batch::new(
+                config
+                    .reload_batch
+                    .map_or(debounce::BATCH_WINDOW, Duration::from_millis),
+            )),
+        }
+    }
+}
+
+impl Backend {
+    pub(super) async fn update_file(&self, path: &Path, service: &LspFileService, saved: bool) {
+        self.log(
+            service,
+            MessageType::INFO,
+            format!("File updated: {}", path.display()),
+        )
+        .await;
+        service.cache.invalidate(path);
+        if let Some(compiler) = service
+            .documents
+            .compilers
+            .iter()
+            .find(|c| c.is_source(path))
+        {
+            self.check_compile(path, compiler, service).await;
+        }
+        if let Some(marker) = &service.builds.build_marker {
+            if marker.as_path() == path {
+                return self.build_done(service).await;
+            }
+        }
+        if service.ignore.is_ignored(&service.served_path(path)) {
+            return;
+        }
+        // a build writing its output isn't reloaded into until it is done
+        if service.builds.build_marker.is_some() {
+            service.builds.built.insert(path.to_path_buf());
+            return;
+        }
+        let delay = service.reloads.debounce.delay(path);
+        if delay.is_zero() {
+            return self.reload(path, service, saved).await;
+        }
+        let generation = service.reloads.debounce.touch(path);
+        let backend = self.clone();
+        let service = service.clone();
+        let path = path.to_path_buf();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if service.reloads.debounce.settle(&path, generation) {
+                backend.reload(&path, &service, saved).await;
+            }
+        });
+    }
+
+    /// Pauses or resumes live reload of a workspace, resuming reloads every page once if changes were held back
+    pub(super) async fn pause_reload(&self, service: &LspFileService, paused: bool, from: &str) {
+        if service.reloads.paused.swap(paused, Ordering::Relaxed) == paused {
+            return;
+        }
+        let message = match paused {
+            true => format!("live reload paused from {}", from),
+            false => format!("live reload resumed from {}", from),
+        };
+        self.log(service, MessageType::INFO, message).await;
+        if let Some(relay) = &service.relay {
+            relay.broadcast(&json!({ "type": "paused", "paused": paused }));
+        }
+        if !paused && service.reloads.missed.swap(false, Ordering::Relaxed) {
+            self.reload_pages(service).await;
+        }
+    }
+
+    /// Reloads the browsers showing `path`, or hot swaps it
+    pub(super) async fn reload(&self, path: &Path, service: &LspFileService, saved: bool) {
+        if service.reloads.paused.load(Ordering::Relaxed) {
+            service.reloads.missed.store(true, Ordering::Relaxed);
+            return;
+        }
+        let original = path;
+        let path = &service.served_path(path);
+        if !service.middleware.should_reload(path) {
+            return;
+        }
+        // pages reload so they fetch the edited routes again
+        if service
+            .mock_api
+            .as_deref()
+            .is_some_and(|file| file == original)
+        {
+            self.reload_pages(service).await;
+            return;
+        }
+        // the pages including a bundle reload with any of its sources
+        let bundled = service
+            .builds
+            .bundler
+            .as_ref()
+            .filter(|bundler| bundler.invalidate(original));
+        if bundled.is_none()
+            && service.client_features.hmr
+            && is_module(path)
+            && (saved || *self.eager.read().await)
+        {
+            if let Some(relay) = &service.relay {
+                let rel = url_path(path.strip_prefix(service.root.as_ref()).unwrap_or(path));
+                self.log(service, MessageType::INFO, format!("hot update /{}", rel))
+                    .await;
+                relay.broadcast(&json!({ "type": "hmr", "path": format!("/{}", rel) }));
+                return;
+            }
+        }
+        // assets only reload the pages known to include them
+        if service.is_source(path) {
+            // the output of a build can't be traced back to its sources
+            if saved {
+                self.reload_pages(service).await;
+            }
+            return;
+        }
+        let mut targets = service.graph.dependents(path);
+        if path != original {
+            targets.extend(service.graph.dependents(original));
+        }
+        for output in bundled.iter().flat_map(|bundler| bundler.outputs()) {
+            targets.extend(service.graph.dependents(output));
+        }
+        if targets.is_empty() {
+            targets.push(path.to_path_buf());
+        }
+        for target in targets {
+            let target = service.served_path(&target);
+            let rel = Path::new("/").join(
+                target
+                    .strip_prefix(service.root.as_ref())
+                    .unwrap_or(&target),
+            );
+            self.call_custom_function(&service.id, &rel, saved).await;
+        }
+    }
+
+    /// Reloads every page served so far
+    pub(super) async fn reload_pages(&self, service: &LspFileService) {
+        let mut pages = service.graph.pages();
+        if pages.is_empty() {
+            pages.push(service.root.to_path_buf());
+        }
+        for page in pages {
+            let rel =
+                Path::new("/").join(page.strip_prefix(service.root.as_ref()).unwrap_or(&page));
+            self.call_custom_function(&service.id, &rel, true).await;
+        }
+    }
+
+    async fn call_custom_function(&self, workspace: &PathBuf, file_path: &Path, saved: bool) {
+        if !*self.eager.read().await && !saved {
+            return;
+        }
+        let folders = self.workspace_folders.read().await;
+        let Some((_, fs)) = folders.get(workspace) else {
+            return;
+        };
+        if fs.reloads.batch.window().is_zero() {
+            return self.send_reload(fs, vec![file_path.to_path_buf()]).await;
+        }
+        if fs.reloads.batch.add(file_path) {
+            let backend = self.clone();
+            let fs = fs.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(fs.reloads.batch.window()).await;
+                backend.send_reload(&fs, fs.reloads.batch.take()).await;
+            });
+        }
+    }
+
+    /// Reloads the browsers showing any of `paths` with one event
+    async fn send_reload(&self, fs: &LspFileService, paths: Vec<PathBuf>) {
+        let message = match paths.len() {
+            0 => return,
+            1 => "reload".to_string(),
+            len => format!("reload of {} pages", len),
+        };
+        self.log(fs, MessageType::INFO, message).await;
+        // every signal reloads the browsers on the websocket of the server, so a batch sends one
+        fs.sig.send_signal(paths[0].clone());
+        if let (Some(relay), true) = (&fs.relay, fs.client_features.reload) {
+            let paths: Vec<String> = paths
+                .iter()
+                .map(|path| format!("/{}", url_path(path)))
+                .collect();
+            relay.broadcast(&json!({ "type": "reload", "path": paths[0], "paths": paths }));
+        }
+    }
+}
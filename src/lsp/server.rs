@@ -0,0 +1,258 @@
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpListener;
+use tokio::task::{JoinHandle, JoinSet};
+use tower_lsp::lsp_types::MessageType;
+
+use crate::gateway::Gateway;
+use crate::supervise::{self, Backoff};
+use crate::PortStrategy;
+
+use super::addresses::free_port;
+use super::{Backend, LspFileService, REPORTED_FAILURES};
+
+/// How often servers with `idle_stop` are checked for activity
+const IDLE_CHECK: Duration = Duration::from_secs(30);
+
+impl Backend {
+    /// Stops the http server of a workspace once it was idle for `idle`, opening a page starts it again
+    pub(super) fn stop_when_idle(&self, fs: LspFileService, idle: Duration) -> JoinHandle<()> {
+        let backend = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(IDLE_CHECK.min(idle)).await;
+                if fs.clients() > 0 {
+                    fs.touch();
+                }
+                if !fs.serving.load(Ordering::Relaxed) || fs.idle() < idle {
+                    continue;
+                }
+                if let Ok(mut server) = fs.server.lock() {
+                    server.drain(..).for_each(|task| task.abort());
+                }
+                fs.serving.store(false, Ordering::Relaxed);
+                let message = format!(
+                    "Stopped the idle server of {}, it starts again with the next page opened",
+                    fs.root.display()
+                );
+                backend.log(&fs, MessageType::INFO, message).await;
+            }
+        })
+    }
+
+    /// Starts serving a workspace unless it already is, returns the tasks running it
+    pub(super) fn serve_once(&self, fs: &LspFileService) -> Vec<JoinHandle<()>> {
+        if fs.serving.swap(true, Ordering::Relaxed) {
+            return vec![];
+        }
+        fs.touch();
+        let tasks = self.serve(fs);
+        if let Ok(mut server) = fs.server.lock() {
+            *server = tasks.iter().map(JoinHandle::abort_handle).collect();
+        }
+        tasks
+    }
+
+    /// Starts serving a workspace that waited for its first page
+    pub(super) async fn activate(&self, fs: &LspFileService) {
+        let tasks = self.serve_once(fs);
+        if tasks.is_empty() {
+            return;
+        }
+        self.tasks
+            .lock()
+            .await
+            .entry(fs.id.to_path_buf())
+            .or_default()
+            .extend(tasks);
+        let message = format!(
+            "Serving {} at port {}",
+            fs.root.display(),
+            *fs.port.read().await
+        );
+        self.log(fs, MessageType::INFO, message).await;
+    }
+
+    /// Starts the http server of a workspace, and the watcher of its mounts
+    fn serve(&self, fs: &LspFileService) -> Vec<JoinHandle<()>> {
+        let path = fs.root.to_path_buf();
+        let f = fs.clone();
+        let backend = self.clone();
+        let mut tasks = vec![tokio::spawn(async move {
+            let requested = *f.port.read().await;
+            let mut attempts = 1;
+            let mut backoff = Backoff::default();
+            loop {
+                let port = *f.port.read().await;
+                let host = f.addresses.server;
+                let public = host.is_unspecified();
+                // the gateway answers on the port, so binding it tells why serving fails
+                let listener = match TcpListener::bind((host, port)).await {
+                    Ok(listener) => listener,
+                    Err(e) if e.kind() == ErrorKind::AddrInUse => {
+                        let next = match f.addresses.port_strategy {
+                            PortStrategy::Fail => None,
+                            PortStrategy::Increment if attempts < f.addresses.port_attempts => {
+                                port.checked_add(1)
+                            }
+                            PortStrategy::Increment => None,
+                            PortStrategy::Random => free_port(host).await,
+                        };
+                        let Some(next) = next else {
+                            let message = format!(
+                                "Port {} of {} is in use, the server was not started",
+                                port,
+                                f.root.display()
+                            );
+                            backend.log(&f, MessageType::ERROR, message.clone()).await;
+                            if backend.retry_serving(&f, host, message).await {
+                                attempts = 1;
+                                continue;
+                            }
+                            return;
+                        };
+                        let message = format!("Port {} is in use, trying {}", port, next);
+                        backend.log(&f, MessageType::WARNING, message).await;
+                        *f.port.write().await = next;
+                        attempts += 1;
+                        continue;
+                    }
+                    Err(e) => {
+                        let message = format!("Failed to bind {}:{}: {}", host, port, e);
+                        backend.log(&f, MessageType::ERROR, message.clone()).await;
+                        if backend.retry_serving(&f, host, message).await {
+                            continue;
+                        }
+                        return;
+                    }
+                };
+                // the server itself listens behind the gateway
+                let Some(inner) = free_port(Ipv4Addr::LOCALHOST.into()).await else {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                };
+                if port != requested {
+                    let message = format!("Serving {} at port {}", f.root.display(), port);
+                    backend
+                        .client
+                        .show_message(MessageType::INFO, message)
+                        .await;
+                }
+                if let Some(url) = f.lan_url(public).await {
+                    let message = format!("Also reachable at {}", url);
+                    backend.log(&f, MessageType::INFO, message).await;
+                }
+                let server = rusty_live_server::serve(
+                    path.clone(),
+                    inner,
+                    false,
+                    Some(f.sig.clone()),
+                    f.clone(),
+                );
+                let gateway = Gateway::new(f.clone(), inner, f.http.limits, f.http.keep_alive);
+                let mut listeners = JoinSet::new();
+                listeners.spawn(gateway.clone().serve(listener));
+                if let Some(socket) = &f.addresses.unix_socket {
+                    if let Err(e) = serve_unix(&gateway, socket, &mut listeners) {
+                        let message = format!("Failed to bind {}: {}", socket.display(), e);
+                        backend.log(&f, MessageType::ERROR, message).await;
+                    }
+                }
+                let started = Instant::now();
+                let result = supervise::run(async move {
+                    tokio::select! {
+                        _ = server => {}
+                        _ = listeners.join_next() => {}
+                    }
+                })
+                .await;
+                let reason = match result {
+                    Err(e) if e.is_panic() => "crashed",
+                    _ => "stopped",
+                };
+                let delay = backoff.failed(started.elapsed());
+                let message = format!(
+                    "Server on port {} {}, restarting in {}s",
+                    port,
+                    reason,
+                    delay.as_secs()
+                );
+                backend.log(&f, MessageType::WARNING, message.clone()).await;
+                if backoff.failures() == REPORTED_FAILURES {
+                    backend
+                        .client
+                        .show_message(MessageType::ERROR, message)
+                        .await;
+                }
+                tokio::time::sleep(delay).await;
+            }
+        })];
+        if !fs.mounts.is_empty() {
+            tasks.push(self.watch_mounts(fs.clone()));
+        }
+        if !fs.addresses.listen.is_empty() {
+            tasks.push(self.forward_addresses(fs.clone()));
+        }
+        if fs.builds.tailwind.is_some() {
+            tasks.push(self.run_tailwind(fs.clone()));
+        }
+        if fs.sharing.tunnel.is_some() {
+            tasks.push(self.keep_tunnel(fs.clone()));
+        }
+        tasks.push(self.refresh_client_lenses(fs.clone()));
+        tasks
+    }
+
+    /// Asks the user to retry a server that failed to start or to move it to a free port,
+    /// returns false if they dismissed it
+    async fn retry_serving(&self, fs: &LspFileService, host: IpAddr, message: String) -> bool {
+        match self
+            .ask(MessageType::ERROR, message, &["Retry", "Pick another port"])
+            .await
+            .as_deref()
+        {
+            Some("Retry") => true,
+            Some(_) => match free_port(host).await {
+                Some(port) => {
+                    *fs.port.write().await = port;
+                    true
+                }
+                None => false,
+            },
+            None => false,
+        }
+    }
+}
+
+/// Lets a reverse proxy connect through the unix socket at `path`, replacing a socket a previous run left behind
+#[cfg(unix)]
+fn serve_unix(
+    gateway: &Arc<Gateway<LspFileService>>,
+    path: &Path,
+    listeners: &mut JoinSet<()>,
+) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // any other file at the path is kept and fails the bind
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    listeners.spawn(gateway.clone().serve_unix(listener));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve_unix(
+    _: &Arc<Gateway<LspFileService>>,
+    _: &Path,
+    _: &mut JoinSet<()>,
+) -> std::io::Result<()> {
+    let message = "unix sockets aren't supported on this platform";
+    Err(std::io::Error::new(ErrorKind::Unsupported, message))
+}
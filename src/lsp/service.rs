@@ -0,0 +1,574 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use rusty_live_server::{Dir, Error, File, FileSystemInterface};
+use serde_json::json;
+use tokio::fs::File as TokioFile;
+use tokio::io::AsyncReadExt;
+use tower_lsp::lsp_types::MessageType;
+
+use crate::charset;
+use crate::env;
+use crate::gateway::{self, Request, Route, Site, StaticFile};
+use crate::har;
+use crate::inspector;
+use crate::mock::{self, Mocks};
+use crate::network;
+use crate::overlay::{Lookup, Overlay};
+use crate::proxy::{Proxy, Response};
+use crate::relay::{inject, is_allowed_host};
+use crate::responsive;
+use crate::uri::{percent_decode, url_path};
+
+use super::{is_html, is_module, is_stylesheet, LspFileService};
+
+/// Decrements the in flight counter of a service when the request is done
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct LspDir {
+    entries: std::vec::IntoIter<PathBuf>,
+}
+
+enum LspFile {
+    Content(Vec<u8>),
+    File(TokioFile),
+}
+
+impl LspFile {
+    async fn new(overlay: &Overlay, path: &Path) -> Result<Self, Error> {
+        Ok(match overlay.lookup(path) {
+            Lookup::Buffer(v) => LspFile::Content(v),
+            Lookup::Deleted => return Err(std::io::Error::from(ErrorKind::NotFound).into()),
+            Lookup::Disk => LspFile::File(TokioFile::open(path).await?),
+        })
+    }
+
+    /// Size of the body, if known
+    async fn len(&self) -> Option<u64> {
+        match self {
+            LspFile::Content(c) => Some(c.len() as u64),
+            LspFile::File(file) => file.metadata().await.ok().map(|metadata| metadata.len()),
+        }
+    }
+}
+
+impl File for LspFile {
+    async fn read_to_end(&mut self) -> Vec<u8> {
+        match self {
+            LspFile::Content(c) => std::mem::take(c),
+            LspFile::File(file) => {
+                // sized up front, growing by doubling can take twice the file size at peak
+                let size = file.metadata().await.map_or(0, |metadata| metadata.len());
+                let mut buffer = Vec::with_capacity(size as usize);
+                let _ = file.read_to_end(&mut buffer).await;
+                buffer
+            }
+        }
+    }
+}
+
+impl Dir for LspDir {
+    async fn get_next(&mut self) -> Result<Option<PathBuf>, Error> {
+        Ok(self.entries.next())
+    }
+}
+
+impl FileSystemInterface for LspFileService {
+    async fn get_dir(&self, path: &Path) -> Result<impl Dir, rusty_live_server::Error> {
+        if self.simulation.offline.load(Ordering::Relaxed) {
+            return Err(std::io::Error::from(ErrorKind::NotConnected).into());
+        }
+        if self.ignore.is_ignored(path) {
+            return Err(std::io::Error::from(ErrorKind::NotFound).into());
+        }
+        let mut entries = self.overlay.list_dir(&self.locate(path)).await?;
+        entries.retain(|entry| !self.ignore.is_ignored(&self.served_path(entry)));
+        Ok(LspDir {
+            entries: entries.into_iter(),
+        })
+    }
+
+    async fn get_file(&self, path: &Path) -> Result<impl File, rusty_live_server::Error> {
+        let started = SystemTime::now();
+        let start = Instant::now();
+        let url = self.begin_request(path).await;
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let _done = InFlight(&self.in_flight);
+        let file = self.serve_file(path).await;
+        let len = match &file {
+            Ok(file) => file.len().await,
+            Err(_) => None,
+        };
+        let status = match &file {
+            Ok(_) => 200,
+            Err(_) => 404,
+        };
+        let wait = start.elapsed();
+        // the body is handed over at once, so the transfer is simulated by holding it back
+        if let (Some(kbps), Some(len)) = (self.simulation.throttle, len) {
+            tokio::time::sleep(network::transfer_time(len, kbps)).await;
+        }
+        self.record(&url, status, len, started, wait, start.elapsed() - wait)
+            .await;
+        file
+    }
+}
+
+impl Site for LspFileService {
+    fn is_offline(&self) -> bool {
+        self.simulation.offline.load(Ordering::Relaxed)
+    }
+
+    fn allows(&self, host: &str) -> bool {
+        self.addresses
+            .hosts
+            .read()
+            .is_ok_and(|hosts| is_allowed_host(host, &hosts))
+    }
+
+    fn proxy(&self) -> Option<&Proxy> {
+        self.proxy.as_deref()
+    }
+
+    /// Routes of the definition as it is now, so edits apply to the next request
+    async fn mock(&self, request: &Request) -> Option<(Response, Duration)> {
+        let file = self.mock_api.as_ref()?;
+        if !mock::is_below(request.path(), &self.mock_api_prefix) {
+            return None;
+        }
+        let mocks = match self.overlay.read(file).await {
+            Ok(text) => Mocks::parse(file, &text, &self.mock_api_prefix),
+            Err(e) => Err(e.to_string()),
+        };
+        match mocks {
+            Ok(mocks) => mocks.answer(&request.method, request.path()),
+            Err(e) => {
+                let message = format!("mock api {}: {}", file.display(), e);
+                self.logger.log(MessageType::WARNING, &message).await;
+                None
+            }
+        }
+    }
+
+    fn socket(&self, open: bool) {
+        self.sockets.send_modify(|sockets| match open {
+            true => *sockets += 1,
+            false => *sockets = sockets.saturating_sub(1),
+        });
+    }
+
+    /// Redirects of directory urls, and precompressed variants and large assets the server would send as they are on disk
+    async fn route(&self, request: &Request) -> Option<Route> {
+        let path = self.request_path(request.path())?;
+        if self.ignore.is_ignored(&path) || self.is_generated(&path) {
+            return None;
+        }
+        let file = self.locate(&path);
+        let metadata = tokio::fs::metadata(&file).await.ok()?;
+        if metadata.is_dir() {
+            return self.redirect(request);
+        }
+        // everything rendered, transformed or edited is left to the server
+        let as_on_disk = metadata.is_file()
+            && self.middleware.is_empty()
+            && !is_html(&file)
+            && !self.overlay.is_open(&file)
+            && self.overlay.exists(&file).await
+            && self.source_maps.get(&file).is_none()
+            && !self
+                .builds
+                .bundler
+                .as_ref()
+                .is_some_and(|bundler| bundler.outputs().any(|output| output == file));
+        if !as_on_disk {
+            return None;
+        }
+        let processed = is_stylesheet(&file) && self.builds.css.is_some();
+        let variant = match (
+            self.http.precompressed && !processed,
+            request.header("accept-encoding"),
+        ) {
+            (true, Some(accepted)) => gateway::precompressed(&file, accepted).await,
+            _ => None,
+        };
+        // scripts and stylesheets get their source maps linked by the server
+        let streamed = !is_module(&file)
+            && !is_stylesheet(&file)
+            && metadata.len() >= self.http.stream_threshold;
+        let (served, encoding) = match variant {
+            Some((variant, encoding)) => (variant, Some(encoding)),
+            None if streamed => (file.clone(), None),
+            None => return None,
+        };
+        Some(Route::File(StaticFile {
+            url: self.begin_request(&path).await,
+            path: served,
+            content_type: gateway::mime_type(&file),
+            encoding,
+            throttle: self.simulation.throttle,
+        }))
+    }
+
+    async fn headers(&self, request: &Request) -> Vec<(String, String)> {
+        let (Some(path), Some(file)) = (
+            percent_decode(request.path()),
+            self.request_path(request.path()),
+        ) else {
+            return vec![];
+        };
+        let mut headers: Vec<(String, String)> = self
+            .http
+            .headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        // the pages of the client features aren't the workspace's to restrict
+        if let Some(csp) = self.http.csp.as_ref().filter(|_| !self.is_generated(&file)) {
+            // read at serving time, the port changes when the server moves to another one
+            let port = *self.port.read().await;
+            let relay = self.relay.as_ref().map(|relay| relay.port());
+            let policy = csp.header(port, relay);
+            headers.push(("Content-Security-Policy".to_string(), policy));
+        }
+        // the server picks the Content-Type by extension, streaming compilation refuses anything but application/wasm
+        if path.ends_with(".wasm") {
+            headers.push(("Content-Type".to_string(), "application/wasm".to_string()));
+        }
+        for (name, value) in self.http.header_rules.headers(&path) {
+            headers.retain(|(preset, _)| !preset.eq_ignore_ascii_case(&name));
+            headers.push((name, value));
+        }
+        headers
+    }
+
+    async fn record(
+        &self,
+        url: &str,
+        status: u16,
+        size: Option<u64>,
+        started: SystemTime,
+        wait: Duration,
+        receive: Duration,
+    ) {
+        self.access_log.record(url, status, size).await;
+        let request = self.har.record(started, url, status, size, wait, receive);
+        if let (true, Some(relay)) = (self.client_features.inspector, &self.relay) {
+            relay.broadcast(&json!({ "type": "request", "request": request }));
+        }
+    }
+}
+
+impl LspFileService {
+    /// Directory served over http, the workspace folder unless `serve_dir` is set
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Port of the server, which it listens on once started
+    pub async fn port(&self) -> u16 {
+        *self.port.read().await
+    }
+
+    /// Url of the site root, behind `public_url` if a proxy serves the workspace
+    pub async fn url(&self) -> String {
+        self.page_url("").await
+    }
+
+    async fn serve_file(&self, path: &Path) -> Result<LspFile, Error> {
+        if self.simulation.offline.load(Ordering::Relaxed) {
+            return Err(std::io::Error::from(ErrorKind::NotConnected).into());
+        }
+        if let (true, Some(relay)) = (self.client_features.inspector, &self.relay) {
+            if path == self.root.join(inspector::PATH) {
+                let limit = match self.har.capacity() {
+                    0 => har::DEFAULT_ENTRIES,
+                    capacity => capacity,
+                };
+                let page = inspector::page(relay.port(), self.har.summaries(), limit);
+                return Ok(LspFile::Content(page.into_bytes()));
+            }
+        }
+        if let Some(widths) = &self.responsive {
+            if path == self.root.join(responsive::PATH) {
+                return Ok(LspFile::Content(responsive::page(widths).into_bytes()));
+            }
+        }
+        self.render(path).await
+    }
+
+    /// Contents of a served path as a browser gets them, without the side effects of a request
+    async fn render(&self, path: &Path) -> Result<LspFile, Error> {
+        if self.ignore.is_ignored(path) {
+            return Err(std::io::Error::from(ErrorKind::NotFound).into());
+        }
+        let path = self.index(self.locate(path)).await;
+        let mut file = self.resolve(&path).await?;
+        if !self.middleware.is_empty() {
+            let content = file.read_to_end().await;
+            file = LspFile::Content(self.middleware.transform(&path, content));
+        }
+        Ok(file)
+    }
+
+    /// Records activity, postponing an idle stop
+    pub(super) fn touch(&self) {
+        let active = self.started.elapsed().as_millis() as u64;
+        self.active.store(active, Ordering::Relaxed);
+    }
+
+    /// Browsers with a page of the workspace open, by their reload sockets,
+    /// or by the relay if more are connected to it, like pages reloading over server sent events
+    pub(super) fn clients(&self) -> usize {
+        let relay = self.relay.as_ref().map_or(0, |relay| relay.clients());
+        (*self.sockets.borrow()).max(relay)
+    }
+
+    /// How long nothing was requested
+    pub(super) fn idle(&self) -> Duration {
+        let active = Duration::from_millis(self.active.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(active)
+    }
+
+    /// File system path of a request, after rewrites and mounts
+    fn locate(&self, path: &Path) -> PathBuf {
+        let path = self.middleware.rewrite(&self.root, path);
+        self.mounts
+            .resolve(&self.root, &path)
+            .or_else(|| self.documents.vendor.resolve(&self.root, &path))
+            .unwrap_or(path)
+    }
+
+    /// Logs a request after the simulated latency, returns its url
+    async fn begin_request(&self, path: &Path) -> String {
+        self.logger
+            .log(MessageType::LOG, &format!("GET {}", path.display()))
+            .await;
+        let url = format!(
+            "/{}",
+            url_path(path.strip_prefix(self.root.as_ref()).unwrap_or(path))
+        );
+        if let Some(latency) = &self.simulation.latency {
+            tokio::time::sleep(latency.delay()).await;
+        }
+        self.touch();
+        *self.requests.entry(url.clone()).or_default() += 1;
+        url
+    }
+
+    /// File under the root a request path names, `None` if it leaves the root or can't be decoded
+    fn request_path(&self, path: &str) -> Option<PathBuf> {
+        let path = percent_decode(path)?;
+        let mut file = self.root.to_path_buf();
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            if matches!(segment, "." | "..") || segment.contains(['\\', ':']) {
+                return None;
+            }
+            file.push(segment);
+        }
+        Some(file)
+    }
+
+    /// Pages and scripts of the client features, which the server answers from memory
+    fn is_generated(&self, path: &Path) -> bool {
+        [inspector::PATH, responsive::PATH]
+            .iter()
+            .any(|generated| path == self.root.join(generated))
+    }
+
+    /// True for files of the watched sources that aren't served themselves
+    pub(super) fn is_source(&self, file: &Path) -> bool {
+        self.builds
+            .sources
+            .as_ref()
+            .is_some_and(|sources| file.starts_with(sources.as_ref()))
+            && !file.starts_with(self.root.as_ref())
+    }
+
+    /// Path under the root a file is served at, files of mounted directories map into their mount
+    pub(super) fn served_path(&self, file: &Path) -> PathBuf {
+        if file.starts_with(self.root.as_ref()) {
+            return file.to_path_buf();
+        }
+        self.mounts
+            .served_path(&self.root, file)
+            .unwrap_or_else(|| file.to_path_buf())
+    }
+
+    /// Serves a path through templates, the overlay and the transformation stages
+    async fn resolve(&self, path: &Path) -> Result<LspFile, Error> {
+        if let Some(bundler) = &self.builds.bundler {
+            match bundler.bundle(&self.workspace, path).await {
+                Some(Ok(bundle)) => return Ok(LspFile::Content(bundle)),
+                Some(Err(e)) => {
+                    let message = format!("bundling {} failed: {}", path.display(), e);
+                    self.logger.log(MessageType::ERROR, &message).await;
+                    let script = format!("console.error({});\n", json!(message));
+                    return Ok(LspFile::Content(script.into_bytes()));
+                }
+                None => {}
+            }
+        }
+        if let Some(templates) = &self.documents.templates {
+            if !self.overlay.exists(path).await {
+                if let Some((template, syntax)) = templates.find(&self.overlay, path).await {
+                    let rendered = templates.render(&self.overlay, &template, syntax).await;
+                    let html = charset::declare_utf8(rendered.html.into_bytes());
+                    let content = self.process_html(path, html, &rendered.includes).await;
+                    return Ok(LspFile::Content(content));
+                }
+            }
+        }
+        if is_html(path) && !self.overlay.exists(path).await {
+            if let Some(rendered) = self.render_document(path).await {
+                let content = self
+                    .process_html(path, rendered.html.into_bytes(), &rendered.includes)
+                    .await;
+                return Ok(LspFile::Content(content));
+            }
+        }
+        if let Some(map) = self.source_maps.get(path) {
+            return Ok(LspFile::Content(map));
+        }
+        let asset = !is_html(path) && !is_module(path) && !is_stylesheet(path);
+        // open buffers change without a file event, they are never cached
+        let cacheable = asset && !self.overlay.is_open(path);
+        // taken before reading, a change while reading leaves the entry stale rather than wrong
+        let modified = match cacheable {
+            true => tokio::fs::metadata(path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            false => None,
+        };
+        if let Some(content) = modified.and_then(|modified| self.cache.get(path, modified)) {
+            return Ok(LspFile::Content(content));
+        }
+        let mut file = LspFile::new(&self.overlay, path).await?;
+        if let Some(modified) = modified {
+            let content = file.read_to_end().await;
+            self.cache.insert(path, &content, modified);
+            file = LspFile::Content(content);
+        } else if is_html(path) {
+            // buffers come from the editor as utf-8, whatever the file on disk is encoded in
+            let buffered = matches!(file, LspFile::Content(_));
+            let mut content = file.read_to_end().await;
+            content = match (buffered, &self.http.charset) {
+                (true, _) => charset::declare_utf8(content),
+                (false, Some(fallback)) => charset::fallback(content, fallback),
+                (false, None) => content,
+            };
+            file = LspFile::Content(self.process_html(path, content, &[]).await);
+        } else if is_module(path) || is_stylesheet(path) {
+            let mut content = file.read_to_end().await;
+            if let (Some(css), true) = (&self.builds.css, is_stylesheet(path)) {
+                match css.process(&self.workspace, path, &content).await {
+                    Ok(processed) => content = processed,
+                    Err(e) => {
+                        let message = format!("css processing {} failed: {}", path.display(), e);
+                        self.logger.log(MessageType::WARNING, &message).await;
+                    }
+                }
+            }
+            file = LspFile::Content(self.source_maps.link(&self.overlay, path, content).await);
+        }
+        Ok(file)
+    }
+
+    /// Writes every served file into `target` as a browser would get it.
+    /// Returns how many were written and the served paths of the files that couldn't be read
+    pub(super) async fn export(&self, target: &Path) -> std::io::Result<(usize, Vec<PathBuf>)> {
+        // the copy has no relay to connect to
+        let exporter = LspFileService {
+            relay: None,
+            ..self.clone()
+        };
+        let mut pending = vec![(self.root.to_path_buf(), self.root.to_path_buf())];
+        for dir in self.mounts.dirs() {
+            if let Some(served) = self.mounts.served_path(&self.root, dir) {
+                pending.push((dir.to_path_buf(), served));
+            }
+        }
+        let mut written = 0;
+        let mut failed = vec![];
+        while let Some((dir, served_dir)) = pending.pop() {
+            for entry in self.overlay.list_dir(&dir).await? {
+                let Some(name) = entry.file_name() else {
+                    continue;
+                };
+                if entry.starts_with(target) {
+                    continue;
+                }
+                let mut served = served_dir.join(name);
+                if self.ignore.is_ignored(&served) {
+                    continue;
+                }
+                if tokio::fs::metadata(&entry)
+                    .await
+                    .is_ok_and(|metadata| metadata.is_dir())
+                {
+                    pending.push((entry, served));
+                    continue;
+                }
+                if let Some(templates) = &self.documents.templates {
+                    if templates.is_partial(&entry) {
+                        continue;
+                    }
+                    if let Some(page) = templates.page_for(&served) {
+                        served = page;
+                    }
+                }
+                if let Some(page) = self.document_page(&served) {
+                    served = page;
+                }
+                // read past the request stats, logs and simulation, the export isn't a visit
+                let Ok(mut file) = exporter.render(&served).await else {
+                    failed.push(served);
+                    continue;
+                };
+                let content = file.read_to_end().await;
+                let out = target.join(served.strip_prefix(self.root.as_ref()).unwrap_or(&served));
+                if let Some(parent) = out.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(&out, content).await?;
+                written += 1;
+            }
+        }
+        Ok((written, failed))
+    }
+
+    /// Records the assets of a served page and injects the client script
+    async fn process_html(&self, path: &Path, content: Vec<u8>, sources: &[PathBuf]) -> Vec<u8> {
+        let content = match (self.documents.env, String::from_utf8(content)) {
+            (true, Ok(html)) => env::substitute(&html).into_bytes(),
+            (_, Ok(html)) => html.into_bytes(),
+            // legacy encoded files are served untouched
+            (_, Err(e)) => e.into_bytes(),
+        };
+        self.graph.record(
+            &self.root,
+            path,
+            &String::from_utf8_lossy(&content),
+            sources,
+        );
+        match &self.relay {
+            Some(relay) => inject(
+                content,
+                &relay.client_script(
+                    &self.client_features,
+                    self.http.csp.as_ref().map(|csp| csp.nonce()),
+                    self.reloads.paused.load(Ordering::Relaxed),
+                ),
+            ),
+            None => content,
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+use tokio::task::{AbortHandle, JoinHandle};
+use tower_lsp::lsp_types::MessageType;
+
+use crate::share;
+use crate::supervise::Backoff;
+use crate::tunnel::Tunnel;
+use crate::Config;
+
+use super::addresses::url_host;
+use super::{Backend, LspFileService, REPORTED_FAILURES};
+
+/// Ways of reaching a workspace from outside the network it is served on
+#[derive(Clone)]
+pub(super) struct Sharing {
+    /// Open share of `shareProjectTemporarily`, sharing again replaces it
+    pub(super) share: Arc<Mutex<Option<AbortHandle>>>,
+    /// How long a share stays open
+    pub(super) ttl: Duration,
+    /// Tunnel through a relay kept open while the server runs
+    pub(super) tunnel: Option<Arc<Tunnel>>,
+}
+
+impl Sharing {
+    /// Share and tunnel of the server `name`, which names the tunnel unless `tunnel_name` is set
+    pub(super) fn new(config: &Config, name: &str) -> Self {
+        Self {
+            share: Default::default(),
+            ttl: config.share_ttl.map_or(share::DEFAULT_TTL, |minutes| {
+                Duration::from_secs(minutes.saturating_mul(60))
+            }),
+            tunnel: config.tunnel.clone().map(|relay| {
+                let name = config.tunnel_name.as_deref().unwrap_or(name);
+                Arc::new(Tunnel {
+                    relay,
+                    // the name is a path segment and a token of the relay protocol
+                    name: name
+                        .chars()
+                        .map(
+                            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                                true => c,
+                                false => '-',
+                            },
+                        )
+                        .collect(),
+                    secret: config.tunnel_secret.clone(),
+                })
+            }),
+        }
+    }
+}
+
+impl Backend {
+    /// Keeps the tunnel of a workspace to its relay open, reconnecting if it closes
+    pub(super) fn keep_tunnel(&self, fs: LspFileService) -> JoinHandle<()> {
+        let backend = self.clone();
+        tokio::spawn(async move {
+            let Some(tunnel) = &fs.sharing.tunnel else {
+                return;
+            };
+            let mut backoff = Backoff::default();
+            let mut announced = None;
+            loop {
+                let started = Instant::now();
+                let result = match tunnel.open().await {
+                    Ok(registered) => {
+                        if announced.as_ref() != Some(&registered.url) {
+                            let message =
+                                format!("Tunneled through the relay at {}", registered.url);
+                            backend.log(&fs, MessageType::INFO, message).await;
+                            announced = Some(registered.url.clone());
+                            // the relay passes on the host visitors requested
+                            if let (Some(host), Ok(mut hosts)) =
+                                (url_host(&registered.url), fs.addresses.hosts.write())
+                            {
+                                if !hosts.contains(&host) {
+                                    hosts.push(host);
+                                }
+                            }
+                        }
+                        let server = SocketAddr::new(fs.addresses.host, *fs.port.read().await);
+                        registered.forward(server).await
+                    }
+                    Err(e) => Err(e),
+                };
+                let reason = match result {
+                    Ok(()) => "closed".to_string(),
+                    Err(e) => e.to_string(),
+                };
+                let delay = backoff.failed(started.elapsed());
+                let message = format!(
+                    "Tunnel to {} failed ({}), reconnecting in {}s",
+                    tunnel.relay,
+                    reason,
+                    delay.as_secs()
+                );
+                backend
+                    .log(&fs, MessageType::WARNING, message.clone())
+                    .await;
+                if backoff.failures() == REPORTED_FAILURES {
+                    backend
+                        .client
+                        .show_message(MessageType::ERROR, message)
+                        .await;
+                }
+                tokio::time::sleep(delay).await;
+            }
+        })
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::network::{Latency, Throttle};
+use crate::Config;
+
+use super::LspFileService;
+
+/// Network conditions the server of a workspace simulates
+#[derive(Clone)]
+pub(super) struct Simulation {
+    /// Simulated bandwidth in kilobits per second
+    pub(super) throttle: Option<u64>,
+    /// Simulated delay before every response
+    pub(super) latency: Option<Latency>,
+    /// Fail every request, the reload channel and relay keep working
+    pub(super) offline: Arc<AtomicBool>,
+}
+
+impl Simulation {
+    /// Conditions configured for the server `name`, a reconfigured one stays offline unless `offline` is set.
+    /// An unknown throttle preset is dropped with a warning
+    pub(super) fn new(
+        config: &Config,
+        name: &str,
+        previous: Option<&LspFileService>,
+        warnings: &mut Vec<String>,
+    ) -> Self {
+        let throttle = config.throttle.as_ref().map(Throttle::kbps);
+        if throttle == Some(None) {
+            warnings.push(format!(
+                "unknown throttle preset for {}, serving unthrottled",
+                name
+            ));
+        }
+        Self {
+            throttle: throttle.flatten(),
+            latency: config.latency.clone(),
+            offline: Arc::new(AtomicBool::new(config.offline.unwrap_or_else(|| {
+                previous.is_some_and(|previous| previous.simulation.offline.load(Ordering::Relaxed))
+            }))),
+        }
+    }
+}
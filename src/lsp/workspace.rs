@@ -0,0 +1,584 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rusty_live_server::Signal;
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
+use tower_lsp::lsp_types::notification::{DidChangeWatchedFiles, Notification};
+use tower_lsp::lsp_types::{
+    DidChangeWatchedFilesRegistrationOptions, FileSystemWatcher, GlobPattern, MessageType, OneOf,
+    Registration, RelativePattern, Unregistration, Url,
+};
+
+use crate::cache::{self, AssetCache};
+use crate::har::{self, Har};
+use crate::ignore::{self, Ignore};
+use crate::log::{AccessLog, Logger, DEFAULT_MAX_SIZE};
+use crate::middleware::{Middlewares, ReloadFilter, Rewrites};
+use crate::mock;
+use crate::mount::Mounts;
+use crate::overlay::{self, Overlay};
+use crate::relay::Relay;
+use crate::responsive;
+use crate::uri::uri_to_path;
+use crate::watch::{Poller, POLL_INTERVAL};
+use crate::{config, Config, OpenOnStart, ServerConfig};
+
+use super::addresses::Addresses;
+use super::browsers::Browsers;
+use super::builds::Builds;
+use super::documents::Documents;
+use super::http::Http;
+use super::reload::Reloads;
+use super::sharing::Sharing;
+use super::simulation::Simulation;
+use super::{clients, Backend, LspFileService};
+
+/// Registration of the file watchers below the served roots
+const WATCHERS_ID: &str = "liveServer/watchers";
+
+impl Backend {
+    /// Builds the main server of a workspace and the extra `servers` it configures,
+    /// returned with their id, name and tasks
+    pub(super) async fn build_workspace(
+        &self,
+        name: &str,
+        path: &Path,
+        config: &Config,
+        previous: &HashMap<PathBuf, LspFileService>,
+    ) -> Vec<(PathBuf, String, LspFileService, Vec<JoinHandle<()>>)> {
+        let mut servers = vec![(path.to_path_buf(), name.to_string(), None)];
+        for server in config.servers.iter().flatten() {
+            let name = format!("{}: {}", name, server.dir.display());
+            servers.push((path.join(&server.dir), name, Some(server)));
+        }
+        let mut built = vec![];
+        for (id, name, server) in servers {
+            let (fs, tasks) = self
+                .build_service(&name, path, server, config, previous.get(&id))
+                .await;
+            built.push((id, name, fs, tasks));
+        }
+        built
+    }
+
+    /// Builds the file service of a workspace and starts its relay.
+    ///
+    /// A reconfigured workspace keeps the port, buffers and recorded pages of its `previous` service.
+    async fn build_service(
+        &self,
+        name: &str,
+        path: &Path,
+        server: Option<&ServerConfig>,
+        config: &Config,
+        previous: Option<&LspFileService>,
+    ) -> (LspFileService, Vec<JoinHandle<()>>) {
+        let mut tasks = vec![];
+        let (logger, access_log) = self.open_logs(name, config).await;
+        let bind = *self.bind.read().await;
+        let mut warnings = vec![];
+        let addresses = Addresses::new(config, bind, path, server, &mut warnings);
+        let http = Http::new(config, name, &mut warnings);
+        let simulation = Simulation::new(config, name, previous, &mut warnings);
+        let proxy = clients::proxy(config, name, path, &mut warnings);
+        for message in warnings {
+            logger.log(MessageType::WARNING, &message).await;
+            self.client.log_message(MessageType::WARNING, message).await;
+        }
+        let mock_api = config
+            .mock_api
+            .as_ref()
+            .map(|file| path.join(file))
+            .or_else(|| mock::detect(path));
+        let client_features = clients::client_features(config);
+        let root = match (server, &config.serve_dir) {
+            (Some(server), _) => path.join(&server.dir),
+            (None, Some(dir)) => path.join(dir),
+            (None, None) => path.to_path_buf(),
+        };
+        let id = Arc::new(match server {
+            Some(_) => root.clone(),
+            None => path.to_path_buf(),
+        });
+        let port = match (previous, server.and_then(|server| server.port)) {
+            (Some(previous), _) => previous.port.clone(),
+            (None, Some(port)) => Arc::new(RwLock::new(port)),
+            (None, None) => Arc::new(RwLock::new(*self.port.read().await)),
+        };
+        // every page gets the client script, so closing notices and broadcasts reach it
+        let relay = match Relay::start(
+            bind,
+            port.clone(),
+            config.allowed_hosts.as_deref().unwrap_or_default(),
+            http.limits,
+        )
+        .await
+        {
+            Ok((relay, handle)) => {
+                let reports =
+                    self.forward_reports(name, &relay, &logger, client_features.presence, &id);
+                tasks.extend([handle, reports]);
+                Some(relay)
+            }
+            Err(e) => {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("failed to start relay for {}: {}", name, e),
+                    )
+                    .await;
+                None
+            }
+        };
+        let mut mounts = Mounts::default();
+        for dir in config.shared_dirs.iter().flatten() {
+            mounts.push_dir(path, dir);
+        }
+        for (url, dir) in config.mounts.iter().flatten() {
+            mounts.push(path, url, dir);
+        }
+        let fs = LspFileService {
+            port,
+            id,
+            workspace: Arc::new(path.to_path_buf()),
+            sig: Signal::default(),
+            overlay: {
+                let overlay: Arc<Overlay> = previous
+                    .map(|previous| previous.overlay.clone())
+                    .unwrap_or_default();
+                overlay.set_max_size(config.overlay_max_size.unwrap_or(overlay::DEFAULT_MAX_SIZE));
+                overlay
+            },
+            graph: previous
+                .map(|previous| previous.graph.clone())
+                .unwrap_or_default(),
+            source_maps: previous
+                .map(|previous| previous.source_maps.clone())
+                .unwrap_or_default(),
+            middleware: self.middleware_for(config, path),
+            mounts: Arc::new(mounts),
+            documents: Documents::new(config, path, &root),
+            builds: Builds::new(config, path, &root),
+            addresses,
+            http,
+            simulation,
+            reloads: Reloads::new(config, previous),
+            sharing: Sharing::new(config, name),
+            browsers: Browsers::new(config),
+            relay,
+            client_features,
+            mock_api: mock_api.map(Arc::new),
+            mock_api_prefix: config
+                .mock_api_prefix
+                .as_deref()
+                .unwrap_or(mock::PREFIX)
+                .into(),
+            proxy: proxy.map(Arc::new),
+            responsive: config.responsive.unwrap_or_default().then(|| {
+                config
+                    .responsive_widths
+                    .as_deref()
+                    .unwrap_or(&responsive::WIDTHS)
+                    .into()
+            }),
+            ignore: Arc::new(load_ignore(config, path, &root).await),
+            requests: Default::default(),
+            in_flight: Default::default(),
+            root: Arc::new(root),
+            logger,
+            access_log,
+            har: Arc::new(Har::new(config.har_entries.unwrap_or(har::DEFAULT_ENTRIES))),
+            cache: Arc::new(AssetCache::new(
+                config.cache_entries.unwrap_or(cache::DEFAULT_ENTRIES),
+                config.cache_max_size.unwrap_or(cache::DEFAULT_MAX_SIZE),
+            )),
+            started: Instant::now(),
+            auto_start: config.auto_start.unwrap_or(true),
+            serving: Default::default(),
+            sockets: Arc::new(watch::channel(0).0),
+            server: Default::default(),
+            active: Default::default(),
+            scripts: Default::default(),
+        };
+        if let Some(minutes) = config.idle_stop {
+            let timeout = Duration::from_secs(minutes.saturating_mul(60));
+            tasks.push(self.stop_when_idle(fs.clone(), timeout));
+        }
+        (fs, tasks)
+    }
+
+    /// Log and access log of the server `name`, both discarded without a `log_dir`
+    async fn open_logs(&self, name: &str, config: &Config) -> (Logger, AccessLog) {
+        let logger = match &config.log_dir {
+            Some(dir) => {
+                let max_size = config.log_max_size.unwrap_or(DEFAULT_MAX_SIZE);
+                match Logger::open(dir, name, max_size).await {
+                    Ok(logger) => logger,
+                    Err(e) => {
+                        self.client
+                            .log_message(
+                                MessageType::WARNING,
+                                format!("failed to open log file for {}: {}", name, e),
+                            )
+                            .await;
+                        Logger::default()
+                    }
+                }
+            }
+            None => Logger::default(),
+        };
+        let access_log = match (&config.log_dir, config.access_log.unwrap_or_default()) {
+            (Some(dir), true) => {
+                let max_size = config.log_max_size.unwrap_or(DEFAULT_MAX_SIZE);
+                match AccessLog::open(dir, name, max_size).await {
+                    Ok(log) => log,
+                    Err(e) => {
+                        let message = format!("failed to open access log for {}: {}", name, e);
+                        logger.log(MessageType::WARNING, &message).await;
+                        AccessLog::default()
+                    }
+                }
+            }
+            _ => AccessLog::default(),
+        };
+        (logger, access_log)
+    }
+
+    /// Rewrites, reload filters and the hooks of the embedding applying to the workspace at `path`
+    fn middleware_for(&self, config: &Config, path: &Path) -> Middlewares {
+        let mut middleware = Middlewares::default();
+        if let Some(rewrites) = &config.rewrites {
+            middleware.push(Arc::new(Rewrites::new(rewrites)));
+        }
+        if config.reload_include.is_some() || config.reload_exclude.is_some() {
+            middleware.push(Arc::new(ReloadFilter::new(
+                config.reload_include.as_deref(),
+                config.reload_exclude.as_deref(),
+            )));
+        }
+        for hooks in self
+            .middleware
+            .iter()
+            .filter(|hooks| hooks.applies_to(path))
+        {
+            middleware.push(hooks.clone());
+        }
+        middleware
+    }
+
+    /// Serves the directory of the first html or document file opened without a workspace
+    pub(super) async fn serve_single_file(&self, uri: &Url) {
+        let Some(path) = uri_to_path(uri) else {
+            return;
+        };
+        let page = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("html" | "htm" | "md" | "markdown" | "adoc" | "asciidoc" | "rst")
+        );
+        let Some(dir) = path.parent().filter(|_| page) else {
+            return;
+        };
+        if !std::mem::take(&mut *self.single_file.write().await) {
+            return;
+        }
+        let name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "Unnamed Workspace".to_string());
+        self.start_workspace(&name, dir).await;
+        self.register_watchers(true).await;
+    }
+
+    /// Roots of the open workspace folders
+    async fn workspaces(&self) -> BTreeSet<PathBuf> {
+        self.workspace_folders
+            .read()
+            .await
+            .values()
+            .map(|(_, fs)| fs.workspace.to_path_buf())
+            .collect()
+    }
+
+    /// Stops every server of a workspace and forgets it, returns the removed services.
+    ///
+    /// Their ports are free again once this returns, the overlays go with the last clone of a service.
+    pub(super) async fn stop_workspace(
+        &self,
+        workspace: &Path,
+    ) -> HashMap<PathBuf, LspFileService> {
+        let removed: HashMap<PathBuf, LspFileService> = self
+            .workspace_folders
+            .read()
+            .await
+            .iter()
+            .filter(|(_, (_, fs))| fs.workspace.as_path() == workspace)
+            .map(|(id, (_, fs))| (id.clone(), fs.clone()))
+            .collect();
+        for id in removed.keys() {
+            let old = self.tasks.lock().await.remove(id).unwrap_or_default();
+            for task in old {
+                // awaited, so the port is free again before a new server binds it
+                task.abort();
+                let _ = task.await;
+            }
+            self.workspace_folders.write().await.remove(id);
+        }
+        removed
+    }
+
+    /// Builds and serves a workspace folder added after initialization
+    pub(super) async fn start_workspace(&self, name: &str, path: &Path) {
+        let (config, errors) = config::load(&*self.options.read().await, path).await;
+        for error in errors {
+            self.client.log_message(MessageType::WARNING, error).await;
+        }
+        let open = matches!(config.open_on_start.unwrap_or_default(), OpenOnStart::All);
+        let servers = self
+            .build_workspace(name, path, &config, &HashMap::new())
+            .await;
+        for (id, server_name, fs, mut tasks) in servers {
+            let open = open && id == path;
+            if fs.auto_start || open {
+                tasks.extend(self.serve_once(&fs));
+            }
+            if open {
+                tasks.push(self.open_when_listening(fs.clone()));
+            }
+            self.tasks.lock().await.insert(id.clone(), tasks);
+            let message = match fs.serving.load(Ordering::Relaxed) {
+                true => format!(
+                    "Opend Workspace: {} at port {}",
+                    server_name,
+                    *fs.port.read().await
+                ),
+                false => format!(
+                    "Opend Workspace: {}, serving once a page is opened",
+                    server_name
+                ),
+            };
+            self.log(&fs, MessageType::INFO, message).await;
+            self.workspace_folders
+                .write()
+                .await
+                .insert(id, (server_name, fs));
+        }
+    }
+
+    /// Rebuilds a workspace from its current configuration and restarts its tasks
+    async fn reconfigure(&self, workspace: &Path) {
+        let Some((name, primary)) = self.workspace_folders.read().await.get(workspace).cloned()
+        else {
+            return;
+        };
+        let (config, errors) = config::load(&*self.options.read().await, workspace).await;
+        for error in errors {
+            self.log(&primary, MessageType::WARNING, error).await;
+        }
+        let previous = self.stop_workspace(workspace).await;
+        let servers = self
+            .build_workspace(&name, workspace, &config, &previous)
+            .await;
+        for (id, server_name, fs, mut tasks) in servers {
+            let served = previous
+                .get(&id)
+                .is_some_and(|previous| previous.serving.load(Ordering::Relaxed));
+            if fs.auto_start || served {
+                tasks.extend(self.serve_once(&fs));
+            }
+            self.tasks.lock().await.insert(id.clone(), tasks);
+            self.workspace_folders
+                .write()
+                .await
+                .insert(id, (server_name, fs));
+        }
+        self.register_watchers(true).await;
+        self.log(
+            &primary,
+            MessageType::INFO,
+            format!("Reloaded configuration of {}", name),
+        )
+        .await;
+    }
+
+    /// Resolves a document uri to its path and the workspace serving it
+    pub(super) async fn get_workspace_for_file(
+        &self,
+        uri: &Url,
+    ) -> Option<(PathBuf, LspFileService)> {
+        let file_path = uri_to_path(uri)?;
+        let folders = self.workspace_folders.read().await;
+        // the most specific root wins, servers of a workspace can be nested in it
+        let served = folders
+            .values()
+            .filter(|(_, service)| file_path.starts_with(service.root.as_ref()))
+            .max_by_key(|(_, service)| service.root.components().count());
+        let (_, service) = served.or_else(|| {
+            folders.values().find(|(_, service)| {
+                service.mounts.contains(&file_path) || service.is_source(&file_path)
+            })
+        })?;
+        Some((file_path, service.clone()))
+    }
+
+    /// Like [`Self::get_workspace_for_file`], but virtual documents resolve to their mount in every workspace
+    pub(super) async fn get_documents(&self, uri: &Url) -> Vec<(PathBuf, LspFileService)> {
+        if let Some(document) = self.get_workspace_for_file(uri).await {
+            return vec![document];
+        }
+        let Some(route) = self.virtual_documents.read().await.get(uri).cloned() else {
+            return vec![];
+        };
+        self.workspace_folders
+            .read()
+            .await
+            .values()
+            .map(|(_, service)| (service.root.join(&route), service.clone()))
+            .collect()
+    }
+
+    /// Reloads on changes in mounted directories, the editor only reports files of its workspace
+    pub(super) fn watch_mounts(&self, service: LspFileService) -> JoinHandle<()> {
+        let backend = self.clone();
+        tokio::spawn(async move {
+            let mut poller =
+                Poller::new(service.mounts.dirs().map(Path::to_path_buf).collect()).await;
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                for path in poller.changes().await {
+                    backend.update_file(&path, &service, true).await;
+                }
+            }
+        })
+    }
+
+    /// Asks the client to report changes below the served roots, including files that aren't open.
+    ///
+    /// `replace` drops the previous registration first, after a configuration changed the roots.
+    pub(super) async fn register_watchers(&self, replace: bool) {
+        let (dynamic, relative) = *self.watch_files.read().await;
+        if !dynamic {
+            return;
+        }
+        let mut dirs: Vec<PathBuf> = vec![];
+        for (_, fs) in self.workspace_folders.read().await.values() {
+            dirs.push(fs.root.to_path_buf());
+            dirs.extend(fs.builds.sources.as_deref().cloned());
+        }
+        dirs.sort();
+        dirs.dedup();
+        let watchers = dirs
+            .iter()
+            .filter_map(|dir| {
+                let glob_pattern = match relative {
+                    true => GlobPattern::Relative(RelativePattern {
+                        base_uri: OneOf::Right(Url::from_directory_path(dir).ok()?),
+                        pattern: "**/*".to_string(),
+                    }),
+                    false => GlobPattern::String(format!(
+                        "{}/**/*",
+                        dir.to_string_lossy().replace('\\', "/")
+                    )),
+                };
+                Some(FileSystemWatcher {
+                    glob_pattern,
+                    kind: None,
+                })
+            })
+            .collect();
+        if replace {
+            let _ = self
+                .client
+                .unregister_capability(vec![Unregistration {
+                    id: WATCHERS_ID.to_string(),
+                    method: DidChangeWatchedFiles::METHOD.to_string(),
+                }])
+                .await;
+        }
+        let registration = Registration {
+            id: WATCHERS_ID.to_string(),
+            method: DidChangeWatchedFiles::METHOD.to_string(),
+            register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
+                watchers,
+            })
+            .ok(),
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("Failed to register file watchers: {}", e),
+                )
+                .await;
+        }
+    }
+
+    /// Restarts every workspace whose configuration file, or the global one, changed
+    pub(super) fn watch_config(&self) -> JoinHandle<()> {
+        let backend = self.clone();
+        tokio::spawn(async move {
+            let mut workspaces = backend.workspaces().await;
+            let mut poller = Poller::new(config_files(&workspaces)).await;
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                let current = backend.workspaces().await;
+                if current != workspaces {
+                    // folders were added or removed, watch the files of the current ones
+                    workspaces = current;
+                    poller = Poller::new(config_files(&workspaces)).await;
+                    continue;
+                }
+                let changed = poller.changes().await;
+                if changed.is_empty() {
+                    continue;
+                }
+                let global = config::global_paths();
+                for workspace in &workspaces {
+                    // all watched files of a workspace lie in its root
+                    if changed.iter().any(|path| {
+                        path.parent() == Some(workspace.as_path()) || global.contains(path)
+                    }) {
+                        backend.reconfigure(workspace).await;
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Paths of the workspace at `path` that are neither served nor reload, its ignore files applied
+async fn load_ignore(config: &Config, path: &Path, root: &Path) -> Ignore {
+    let mut ignore = Ignore::new(
+        root,
+        config.default_exclusions.unwrap_or(true),
+        config.include.as_deref().unwrap_or_default(),
+    );
+    let mut ignore_files = vec![ignore::FILE_NAME];
+    if config.gitignore.unwrap_or(true) {
+        // the dedicated file is layered on top, so it can re-include ignored paths
+        ignore_files.insert(0, ".gitignore");
+    }
+    for file in ignore_files {
+        if let Ok(content) = tokio::fs::read_to_string(path.join(file)).await {
+            ignore.add_patterns(path, &content);
+        }
+    }
+    ignore
+}
+
+/// Configuration and ignore files of `workspaces`, and the global configuration
+fn config_files(workspaces: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    workspaces
+        .iter()
+        .flat_map(|path| {
+            config::workspace_paths(path)
+                .into_iter()
+                .chain([path.join(ignore::FILE_NAME), path.join(".gitignore")])
+        })
+        .chain(config::global_paths())
+        .collect()
+}
@@ -0,0 +1,480 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+
+use crate::{highlight, HighlightTheme};
+
+const STYLE: &str = "body{margin:0;font:16px/1.6 system-ui,sans-serif;color:#1f2328}\
+main{max-width:860px;margin:0 auto;padding:32px}\
+pre{padding:16px;overflow:auto;background:#f6f8fa;border-radius:6px}\
+code{font:85% ui-monospace,monospace}:not(pre)>code{padding:.2em .4em;background:#eff1f3;border-radius:6px}\
+blockquote{margin:0;padding:0 1em;color:#59636e;border-left:.25em solid #d1d9e0}\
+table{border-collapse:collapse}th,td{padding:6px 13px;border:1px solid #d1d9e0}\
+img{max-width:100%}h1,h2{padding-bottom:.3em;border-bottom:1px solid #d1d9e0}";
+
+/// Url the mermaid bundle is served under, diagrams render once `mermaid.min.js` loads from it
+pub const MERMAID_URL: &str = "__live-server/mermaid";
+/// Url the KaTeX bundle is served under, with `katex.min.js`, `katex.min.css` and their fonts
+pub const KATEX_URL: &str = "__live-server/katex";
+
+/// Markdown file rendering to `page`, e.g. `notes.md` for `notes.html`
+pub fn source_for(page: &Path) -> [PathBuf; 2] {
+    [page.with_extension("md"), page.with_extension("markdown")]
+}
+
+/// Page a markdown file renders to, `None` for other files
+pub fn page_for(file: &Path) -> Option<PathBuf> {
+    is_markdown(file).then(|| file.with_extension("html"))
+}
+
+pub fn is_markdown(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("md" | "markdown")
+    )
+}
+
+/// Renders a markdown document into a page titled after its first heading, or `name`.
+///
+/// Covers CommonMark plus GFM tables, task lists and strikethrough. Every block carries the zero based
+/// source line it starts at as `data-line`. `mermaid` fences become diagrams and `$tex$` or `$$tex$$` formulas,
+/// their source stays visible if the bundle can't be loaded. Code blocks of known languages are highlighted with `theme`.
+pub fn render(source: &str, name: &str, theme: HighlightTheme) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_MATH;
+    let starts: Vec<usize> = std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(at, _)| at + 1))
+        .collect();
+    let mut renderer = Renderer {
+        theme,
+        ..Renderer::default()
+    };
+    for (event, range) in Parser::new_ext(source, options).into_offset_iter() {
+        let line = starts.partition_point(|&start| start <= range.start) - 1;
+        renderer.event(event, line);
+    }
+    if renderer.highlighted {
+        renderer
+            .out
+            .push_str(&format!("<style>{}</style>\n", highlight::style(theme)));
+    }
+    if renderer.formulas {
+        renderer.out.push_str(&format!(
+            "<link rel=\"stylesheet\" href=\"/{0}/katex.min.css\">\n\
+             <script src=\"/{0}/katex.min.js\"></script>\n\
+             <script>window.katex && document.querySelectorAll(\".math\").forEach((e) => \
+             katex.render(e.textContent, e, {{ displayMode: e.classList.contains(\"display\"), throwOnError: false }}));</script>\n",
+            KATEX_URL
+        ));
+    }
+    if renderer.diagrams {
+        renderer.out.push_str(&format!(
+            "<script src=\"/{}/mermaid.min.js\"></script>\n\
+             <script>window.mermaid && mermaid.initialize({{ startOnLoad: true }});</script>\n",
+            MERMAID_URL
+        ));
+    }
+    let title = renderer.title.take().unwrap_or_else(|| name.to_string());
+    page(&title, &renderer.out)
+}
+
+/// Standalone page around a rendered document
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<main>\n{}</main>\n</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        body
+    )
+}
+
+#[derive(Default)]
+struct Renderer {
+    out: String,
+    title: Option<String>,
+    /// Heading ids handed out so far, repeated ones get a counter appended
+    slugs: HashMap<String, usize>,
+    /// Whether a mermaid diagram was rendered
+    diagrams: bool,
+    /// Whether a formula was rendered
+    formulas: bool,
+    theme: HighlightTheme,
+    /// Whether a code block was highlighted
+    highlighted: bool,
+    heading: Option<Heading>,
+    /// Source line, info string and content of the code block being read
+    code: Option<(usize, String, String)>,
+    image: Option<Image>,
+    /// Alignments of the columns of the current table
+    align: Vec<Alignment>,
+    /// Column of the next cell
+    column: usize,
+    /// Whether the current cells are in the header row
+    head: bool,
+}
+
+/// Heading being rendered, its id and the title need all of its text
+struct Heading {
+    level: usize,
+    line: usize,
+    /// Where its content starts in `out`
+    start: usize,
+    text: String,
+}
+
+/// Image being rendered, its text becomes the `alt`
+struct Image {
+    src: String,
+    title: String,
+    alt: String,
+}
+
+impl Renderer {
+    /// Renders an event of the source, `line` is where it starts
+    fn event(&mut self, event: Event, line: usize) {
+        if self.image.is_some() {
+            return self.image_event(event);
+        }
+        if let Some((_, _, code)) = &mut self.code {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => {
+                    if let Some((line, info, code)) = self.code.take() {
+                        self.code(line, &info, &code);
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+        if let (Some(heading), Event::Text(text) | Event::Code(text)) = (&mut self.heading, &event)
+        {
+            heading.text.push_str(text);
+        }
+        match event {
+            Event::Start(tag) => self.start(tag, line),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.out.push_str(&escape(&text)),
+            Event::Code(code) => self
+                .out
+                .push_str(&format!("<code>{}</code>", escape(&code))),
+            Event::InlineMath(tex) => {
+                self.formulas = true;
+                self.out
+                    .push_str(&format!("<span class=\"math\">{}</span>", escape(&tex)));
+            }
+            Event::DisplayMath(tex) => {
+                self.formulas = true;
+                self.out.push_str(&format!(
+                    "<span class=\"math display\">{}</span>",
+                    escape(tex.trim())
+                ));
+            }
+            Event::Html(html) | Event::InlineHtml(html) => self.out.push_str(&html),
+            Event::SoftBreak => self.out.push('\n'),
+            Event::HardBreak => self.out.push_str("<br>\n"),
+            Event::Rule => self.out.push_str(&format!("<hr data-line=\"{}\">\n", line)),
+            Event::TaskListMarker(checked) => self.out.push_str(&format!(
+                "<input type=\"checkbox\" disabled{}> ",
+                if checked { " checked" } else { "" }
+            )),
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag, line: usize) {
+        let html = match tag {
+            Tag::Paragraph => format!("<p data-line=\"{}\">", line),
+            Tag::Heading { level, .. } => {
+                self.heading = Some(Heading {
+                    level: level as usize,
+                    line,
+                    start: self.out.len(),
+                    text: String::new(),
+                });
+                return;
+            }
+            Tag::BlockQuote(_) => format!("<blockquote data-line=\"{}\">\n", line),
+            Tag::CodeBlock(kind) => {
+                let info = match kind {
+                    CodeBlockKind::Fenced(info) => info.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((line, info, String::new()));
+                return;
+            }
+            Tag::List(Some(1)) => format!("<ol data-line=\"{}\">\n", line),
+            Tag::List(Some(first)) => format!("<ol start=\"{}\" data-line=\"{}\">\n", first, line),
+            Tag::List(None) => format!("<ul data-line=\"{}\">\n", line),
+            Tag::Item => format!("<li data-line=\"{}\">", line),
+            Tag::Table(align) => {
+                self.align = align;
+                format!("<table data-line=\"{}\">\n", line)
+            }
+            Tag::TableHead => {
+                self.head = true;
+                self.column = 0;
+                format!("<thead>\n<tr data-line=\"{}\">", line)
+            }
+            Tag::TableRow => {
+                self.column = 0;
+                format!("<tr data-line=\"{}\">", line)
+            }
+            Tag::TableCell => {
+                let style = match self.align.get(self.column) {
+                    Some(Alignment::Left) => " style=\"text-align:left\"",
+                    Some(Alignment::Center) => " style=\"text-align:center\"",
+                    Some(Alignment::Right) => " style=\"text-align:right\"",
+                    _ => "",
+                };
+                self.column += 1;
+                format!("<{}{}>", if self.head { "th" } else { "td" }, style)
+            }
+            Tag::Emphasis => "<em>".to_string(),
+            Tag::Strong => "<strong>".to_string(),
+            Tag::Strikethrough => "<del>".to_string(),
+            Tag::Link {
+                dest_url, title, ..
+            } => match title.is_empty() {
+                true => format!("<a href=\"{}\">", escape(&dest_url)),
+                false => format!(
+                    "<a href=\"{}\" title=\"{}\">",
+                    escape(&dest_url),
+                    escape(&title)
+                ),
+            },
+            Tag::Image {
+                dest_url, title, ..
+            } => {
+                self.image = Some(Image {
+                    src: dest_url.to_string(),
+                    title: title.to_string(),
+                    alt: String::new(),
+                });
+                return;
+            }
+            _ => return,
+        };
+        self.out.push_str(&html);
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        let html = match tag {
+            TagEnd::Paragraph => "</p>\n",
+            TagEnd::Heading(_) => return self.heading(),
+            TagEnd::BlockQuote(_) => "</blockquote>\n",
+            TagEnd::List(true) => "</ol>\n",
+            TagEnd::List(false) => "</ul>\n",
+            TagEnd::Item => "</li>\n",
+            TagEnd::Table => "</tbody>\n</table>\n",
+            TagEnd::TableHead => {
+                self.head = false;
+                "</tr>\n</thead>\n<tbody>\n"
+            }
+            TagEnd::TableRow => "</tr>\n",
+            TagEnd::TableCell => match self.head {
+                true => "</th>",
+                false => "</td>",
+            },
+            TagEnd::Emphasis => "</em>",
+            TagEnd::Strong => "</strong>",
+            TagEnd::Strikethrough => "</del>",
+            TagEnd::Link => "</a>",
+            _ => return,
+        };
+        self.out.push_str(html);
+    }
+
+    /// Wraps the content of the finished heading, now that its id is known
+    fn heading(&mut self) {
+        let Some(heading) = self.heading.take() else {
+            return;
+        };
+        let content = self.out.split_off(heading.start);
+        let slug = slug(&heading.text);
+        let count = self.slugs.entry(slug.clone()).or_default();
+        let id = match *count {
+            0 => slug,
+            n => format!("{}-{}", slug, n),
+        };
+        *count += 1;
+        if heading.level == 1 && self.title.is_none() {
+            self.title = Some(heading.text);
+        }
+        self.out.push_str(&format!(
+            "<h{0} id=\"{1}\" data-line=\"{2}\">{3}</h{0}>\n",
+            heading.level,
+            escape(&id),
+            heading.line,
+            content
+        ));
+    }
+
+    fn image_event(&mut self, event: Event) {
+        match event {
+            Event::End(TagEnd::Image) => {
+                let Some(image) = self.image.take() else {
+                    return;
+                };
+                self.out.push_str(&format!(
+                    "<img src=\"{}\" alt=\"{}\"",
+                    escape(&image.src),
+                    escape(&image.alt)
+                ));
+                if !image.title.is_empty() {
+                    self.out
+                        .push_str(&format!(" title=\"{}\"", escape(&image.title)));
+                }
+                self.out.push('>');
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(image) = &mut self.image {
+                    image.alt.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn code(&mut self, line: usize, info: &str, code: &str) {
+        let language = info.split_whitespace().next().unwrap_or_default();
+        if language == "mermaid" {
+            self.diagrams = true;
+            self.out.push_str(&format!(
+                "<pre class=\"mermaid\" data-line=\"{}\">{}</pre>\n",
+                line,
+                escape(code)
+            ));
+            return;
+        }
+        self.out
+            .push_str(&format!("<pre data-line=\"{}\"><code", line));
+        if !language.is_empty() {
+            self.out
+                .push_str(&format!(" class=\"language-{}\"", escape(language)));
+        }
+        self.out.push('>');
+        match highlight::highlight(language, code).filter(|_| self.theme != HighlightTheme::None) {
+            Some(html) => {
+                self.highlighted = true;
+                self.out.push_str(&html);
+            }
+            None => self.out.push_str(&escape(code)),
+        }
+        self.out.push_str("</code></pre>\n");
+    }
+}
+
+fn slug(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' | '-' => Some('-'),
+            c if c.is_alphanumeric() || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(source: &str) -> String {
+        let page = render(source, "notes", HighlightTheme::None);
+        let start = page.find("<main>\n").unwrap() + "<main>\n".len();
+        page[start..page.find("</main>").unwrap()].to_string()
+    }
+
+    #[test]
+    fn marks_blocks_with_their_source_line() {
+        let html =
+            body("# Notes & *more*\n\nSome **bold**, `code` and [a link](https://example.com).\n");
+        assert_eq!(
+            html,
+            "<h1 id=\"notes--more\" data-line=\"0\">Notes &amp; <em>more</em></h1>\n\
+             <p data-line=\"2\">Some <strong>bold</strong>, <code>code</code> and \
+             <a href=\"https://example.com\">a link</a>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn titles_the_page_after_the_first_top_level_heading() {
+        let page = render(
+            "## Intro\n\n# Usage\n\n## Usage\n",
+            "notes",
+            HighlightTheme::None,
+        );
+        assert!(page.contains("<title>Usage</title>"));
+        // repeated headings get distinct ids
+        assert!(page.contains("<h1 id=\"usage\" data-line=\"2\">"));
+        assert!(page.contains("<h2 id=\"usage-1\" data-line=\"4\">"));
+        let untitled = render("just text\n", "notes", HighlightTheme::None);
+        assert!(untitled.contains("<title>notes</title>"));
+    }
+
+    #[test]
+    fn renders_tables_task_lists_and_strikethrough() {
+        let html = body("- [x] done\n- [ ] open\n\n| a | b |\n|---|--:|\n| 1 | 2 |\n\n~~gone~~\n");
+        assert!(
+            html.contains("<li data-line=\"0\"><input type=\"checkbox\" disabled checked> done")
+        );
+        assert!(html.contains("<li data-line=\"1\"><input type=\"checkbox\" disabled> open"));
+        assert!(html
+            .contains("<tr data-line=\"3\"><th>a</th><th style=\"text-align:right\">b</th></tr>"));
+        assert!(html
+            .contains("<tr data-line=\"5\"><td>1</td><td style=\"text-align:right\">2</td></tr>"));
+        assert!(html.contains("<p data-line=\"7\"><del>gone</del></p>"));
+    }
+
+    #[test]
+    fn renders_formulas_and_diagrams_with_their_bundles() {
+        let page = render(
+            "a \\$5 bill, $x^2$\n\n```mermaid\ngraph TD\n```\n",
+            "notes",
+            HighlightTheme::None,
+        );
+        assert!(page.contains("a $5 bill, <span class=\"math\">x^2</span>"));
+        assert!(page.contains("<pre class=\"mermaid\" data-line=\"2\">graph TD\n</pre>"));
+        assert!(page.contains(&format!("/{}/katex.min.js", KATEX_URL)));
+        assert!(page.contains(&format!("/{}/mermaid.min.js", MERMAID_URL)));
+        // neither bundle is loaded for plain documents
+        assert!(!render("plain", "notes", HighlightTheme::None).contains("<script"));
+    }
+
+    #[test]
+    fn maps_pages_to_their_sources() {
+        assert_eq!(
+            source_for(Path::new("docs/notes.html")),
+            [
+                PathBuf::from("docs/notes.md"),
+                PathBuf::from("docs/notes.markdown")
+            ]
+        );
+        assert_eq!(
+            page_for(Path::new("notes.markdown")),
+            Some(PathBuf::from("notes.html"))
+        );
+        assert_eq!(page_for(Path::new("notes.txt")), None);
+    }
+}
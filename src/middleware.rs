@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::uri::url_path;
+
+/// Hooks into serving and reloading of a workspace, registered with [`crate::ConfigBuilder::middleware`].
+///
+/// Every hook has a default that leaves the request, response or reload untouched,
+/// so an implementation only overrides what it needs.
+pub trait Middleware: Send + Sync {
+    /// Whether the hooks apply to the workspace folder `workspace`, every one by default
+    fn applies_to(&self, _workspace: &Path) -> bool {
+        true
+    }
+
+    /// Path to serve instead of `path`, which is already joined onto `root`
+    fn rewrite(&self, _root: &Path, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    /// Transforms a response body before it is sent
+    fn transform(&self, _path: &Path, body: Vec<u8>) -> Vec<u8> {
+        body
+    }
+
+    /// Return false to suppress the reload caused by a change of `path`
+    fn should_reload(&self, _path: &Path) -> bool {
+        true
+    }
+}
+
+/// Middlewares of a workspace, applied in registration order
+#[derive(Clone, Default)]
+pub struct Middlewares {
+    list: Vec<Arc<dyn Middleware>>,
+}
+
+impl Middlewares {
+    pub fn push(&mut self, middleware: Arc<dyn Middleware>) {
+        self.list.push(middleware);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn rewrite(&self, root: &Path, path: &Path) -> PathBuf {
+        self.list
+            .iter()
+            .fold(path.to_path_buf(), |path, middleware| {
+                middleware.rewrite(root, &path).unwrap_or(path)
+            })
+    }
+
+    pub fn transform(&self, path: &Path, body: Vec<u8>) -> Vec<u8> {
+        self.list
+            .iter()
+            .fold(body, |body, middleware| middleware.transform(path, body))
+    }
+
+    pub fn should_reload(&self, path: &Path) -> bool {
+        self.list
+            .iter()
+            .all(|middleware| middleware.should_reload(path))
+    }
+}
+
+/// Rewrites urls from the `rewrites` config. A rule ending in `*` matches every url with that prefix.
+pub struct Rewrites {
+    rules: Vec<(String, String)>,
+}
+
+impl Rewrites {
+    pub fn new(rules: &HashMap<String, String>) -> Self {
+        let mut rules: Vec<_> = rules
+            .iter()
+            .map(|(from, to)| (from.clone(), to.clone()))
+            .collect();
+        // the most specific rule wins
+        rules.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        Self { rules }
+    }
+}
+
+impl Middleware for Rewrites {
+    fn rewrite(&self, root: &Path, path: &Path) -> Option<PathBuf> {
+        let url = format!("/{}", url_path(path.strip_prefix(root).ok()?));
+        let (_, to) = self
+            .rules
+            .iter()
+            .find(|(from, _)| match from.strip_suffix('*') {
+                Some(prefix) => url.starts_with(prefix),
+                None => url == *from,
+            })?;
+        Some(root.join(to.trim_start_matches('/')))
+    }
+}
+
+/// Extensions that reload whatever the `reload_include`/`reload_exclude` lists say
+const ALWAYS_RELOAD: [&str; 6] = ["html", "htm", "css", "js", "mjs", "map"];
+
+/// Suppresses reloads by file extension, from the `reload_include` and `reload_exclude` config
+pub struct ReloadFilter {
+    include: Option<Vec<String>>,
+    exclude: Vec<String>,
+}
+
+impl ReloadFilter {
+    pub fn new(include: Option<&[String]>, exclude: Option<&[String]>) -> Self {
+        let normalize = |list: &[String]| {
+            list.iter()
+                .map(|ext| ext.trim_start_matches('.').to_ascii_lowercase())
+                .collect::<Vec<_>>()
+        };
+        Self {
+            include: include.map(normalize),
+            exclude: exclude.map(normalize).unwrap_or_default(),
+        }
+    }
+}
+
+impl Middleware for ReloadFilter {
+    fn should_reload(&self, path: &Path) -> bool {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        if ALWAYS_RELOAD.contains(&ext.as_str()) {
+            return true;
+        }
+        if self.exclude.contains(&ext) {
+            return false;
+        }
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.contains(&ext))
+    }
+}
@@ -0,0 +1,386 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_json::{Map, Number, Value};
+use yaml_rust2::{Yaml, YamlLoader};
+
+use crate::proxy::Response;
+
+/// Definitions looked up in the workspace unless `mock_api` names one
+const DEFINITION_FILES: [&str; 3] = ["mock-api.yaml", "mock-api.yml", "mock-api.json"];
+
+/// Url prefix of the routes unless `mock_api_prefix` is set
+pub const PREFIX: &str = "/api";
+
+/// Mock API definition in `workspace`, if there is one
+pub fn detect(workspace: &Path) -> Option<PathBuf> {
+    DEFINITION_FILES
+        .iter()
+        .map(|file| workspace.join(file))
+        .find(|file| file.is_file())
+}
+
+/// Whether `path`, the path of a request, is below `prefix`, where the routes are served
+pub fn is_below(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_matches('/');
+    prefix.is_empty()
+        || path
+            .strip_prefix('/')
+            .and_then(|path| path.strip_prefix(prefix))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Routes of a mock API definition, answered by the gateway
+pub struct Mocks {
+    routes: Vec<Route>,
+}
+
+struct Route {
+    /// `:name` matches one segment, a trailing `*` the rest of the path
+    path: String,
+    /// Uppercase, `*` for any
+    method: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Value,
+    delay: Duration,
+}
+
+impl Mocks {
+    /// Routes of the definition `text` of `file`, with their paths under `prefix`.
+    ///
+    /// ```yaml
+    /// routes:
+    ///   - path: /users/:id
+    ///     method: GET
+    ///     status: 200
+    ///     headers:
+    ///       X-Total-Count: 1
+    ///     body:
+    ///       id: 1
+    ///       name: Ada
+    ///     delay: 300
+    /// ```
+    pub fn parse(file: &Path, text: &[u8], prefix: &str) -> Result<Self, String> {
+        let text = std::str::from_utf8(text).map_err(|e| e.to_string())?;
+        let definition = match file.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(text).map_err(|e| e.to_string())?,
+            _ => {
+                let documents = YamlLoader::load_from_str(text).map_err(|e| e.to_string())?;
+                documents.first().map_or(Ok(Value::Null), json)?
+            }
+        };
+        let routes = match &definition {
+            Value::Array(routes) => routes,
+            Value::Object(definition) => match definition.get("routes") {
+                Some(Value::Array(routes)) => routes,
+                _ => return Err("expected a `routes` list".to_string()),
+            },
+            _ => return Err("expected a `routes` list".to_string()),
+        };
+        let prefix = prefix.trim_matches('/');
+        let routes = routes
+            .iter()
+            .enumerate()
+            .map(|(at, value)| route(value, prefix).map_err(|e| format!("route {}: {}", at + 1, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { routes })
+    }
+
+    /// Response of the first route matching `method` on `path` and how long it is held back,
+    /// `None` if no route matches the path. A path whose routes all have other methods answers 405
+    pub fn answer(&self, method: &str, path: &str) -> Option<(Response, Duration)> {
+        let routes: Vec<_> = self
+            .routes
+            .iter()
+            .filter(|route| matches(&route.path, path))
+            .collect();
+        if routes.is_empty() {
+            return None;
+        }
+        let Some(route) = routes
+            .iter()
+            .find(|route| route.method == "*" || route.method.eq_ignore_ascii_case(method))
+        else {
+            let allowed: Vec<_> = routes.iter().map(|route| route.method.as_str()).collect();
+            let response = Response {
+                status: 405,
+                reason: reason(405).to_string(),
+                headers: vec![("Allow".to_string(), allowed.join(", "))],
+                body: vec![],
+            };
+            return Some((response, Duration::ZERO));
+        };
+        let mut headers = route.headers.clone();
+        let body = match &route.body {
+            // these statuses can't have a body
+            _ if matches!(route.status, 204 | 205 | 304) => vec![],
+            Value::Null => vec![],
+            Value::String(text) => text.clone().into_bytes(),
+            body => {
+                if !headers
+                    .iter()
+                    .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+                {
+                    headers.push(("Content-Type".to_string(), "application/json".to_string()));
+                }
+                body.to_string().into_bytes()
+            }
+        };
+        let response = Response {
+            status: route.status,
+            reason: reason(route.status).to_string(),
+            headers,
+            body,
+        };
+        Some((response, route.delay))
+    }
+}
+
+fn route(value: &Value, prefix: &str) -> Result<Route, String> {
+    let route = value.as_object().ok_or("expected a mapping")?;
+    let path = route
+        .get("path")
+        .and_then(Value::as_str)
+        .ok_or("expected a `path`")?;
+    let method = match route.get("method") {
+        None => "*".to_string(),
+        Some(method) => method
+            .as_str()
+            .ok_or("`method` isn't a string")?
+            .to_ascii_uppercase(),
+    };
+    let status = match route.get("status") {
+        None => 200,
+        // informational statuses aren't final responses
+        Some(status) => status
+            .as_u64()
+            .filter(|status| (200..=599).contains(status))
+            .ok_or("`status` isn't between 200 and 599")? as u16,
+    };
+    let headers = match route.get("headers") {
+        None | Some(Value::Null) => vec![],
+        Some(Value::Object(headers)) => headers
+            .iter()
+            .map(|(name, value)| match value {
+                Value::String(value) => (name.clone(), value.clone()),
+                value => (name.clone(), value.to_string()),
+            })
+            .collect(),
+        Some(_) => return Err("`headers` isn't a mapping".to_string()),
+    };
+    let delay = match route.get("delay") {
+        None => 0,
+        Some(delay) => delay
+            .as_u64()
+            .ok_or("`delay` isn't a number of milliseconds")?,
+    };
+    let path = match prefix.is_empty() {
+        true => format!("/{}", path.trim_start_matches('/')),
+        false => format!("/{}/{}", prefix, path.trim_start_matches('/')),
+    };
+    Ok(Route {
+        path,
+        method,
+        status,
+        headers,
+        body: route.get("body").cloned().unwrap_or_default(),
+        delay: Duration::from_millis(delay),
+    })
+}
+
+/// Whether the route `pattern` matches `path`, `:name` matches one non-empty segment and a trailing `*` the rest
+fn matches(pattern: &str, path: &str) -> bool {
+    let mut expected: Vec<&str> = pattern.split('/').collect();
+    let actual: Vec<&str> = path.split('/').collect();
+    let rest = expected.last() == Some(&"*");
+    if rest {
+        expected.pop();
+    }
+    let fits = match rest {
+        true => actual.len() >= expected.len(),
+        false => actual.len() == expected.len(),
+    };
+    fits && expected
+        .iter()
+        .zip(&actual)
+        .all(|(part, actual)| match part.starts_with(':') {
+            true => !actual.is_empty(),
+            false => part == actual,
+        })
+}
+
+/// Json of a yaml value, keys that aren't strings are written out
+fn json(yaml: &Yaml) -> Result<Value, String> {
+    Ok(match yaml {
+        Yaml::Null => Value::Null,
+        Yaml::Boolean(value) => Value::Bool(*value),
+        Yaml::Integer(value) => Value::from(*value),
+        Yaml::Real(text) => yaml
+            .as_f64()
+            .and_then(Number::from_f64)
+            .map(Value::Number)
+            .ok_or_else(|| format!("{} isn't a finite number", text))?,
+        Yaml::String(text) => Value::String(text.clone()),
+        Yaml::Array(items) => Value::Array(items.iter().map(json).collect::<Result<_, _>>()?),
+        Yaml::Hash(entries) => {
+            let mut object = Map::new();
+            for (key, value) in entries {
+                let key = match json(key)? {
+                    Value::String(key) => key,
+                    key => key.to_string(),
+                };
+                object.insert(key, json(value)?);
+            }
+            Value::Object(object)
+        }
+        Yaml::Alias(_) | Yaml::BadValue => return Err("invalid value".to_string()),
+    })
+}
+
+/// Reason phrase of the common statuses, empty for the others
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        205 => "Reset Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        422 => "Unprocessable Content",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFINITION: &str = "\
+routes:
+  - path: /users/:id
+    method: get
+    headers:
+      X-Total-Count: 1
+    body:
+      id: 1
+      name: Ada
+      score: 0.5
+  - path: /users
+    method: POST
+    status: 201
+    body: created
+    delay: 300
+  - path: /files/*
+";
+
+    fn mocks(definition: &str) -> Mocks {
+        Mocks::parse(Path::new("mock-api.yaml"), definition.as_bytes(), PREFIX).unwrap()
+    }
+
+    #[test]
+    fn matches_segments_and_rests() {
+        assert!(matches("/api/users/:id", "/api/users/7"));
+        assert!(!matches("/api/users/:id", "/api/users/"));
+        assert!(!matches("/api/users/:id", "/api/users/7/posts"));
+        assert!(matches("/api/files/*", "/api/files/a/b.txt"));
+        assert!(matches("/api/files/*", "/api/files"));
+        assert!(!matches("/api/files/*", "/api/other"));
+    }
+
+    #[test]
+    fn answers_routes_under_the_prefix() {
+        let mocks = mocks(DEFINITION);
+        let (response, delay) = mocks.answer("GET", "/api/users/7").unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(delay, Duration::ZERO);
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "id": 1, "name": "Ada", "score": 0.5 })
+        );
+        let header = |name: &str| {
+            response
+                .headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(header("X-Total-Count"), Some("1"));
+        assert_eq!(header("Content-Type"), Some("application/json"));
+
+        let (created, delay) = mocks.answer("POST", "/api/users").unwrap();
+        assert_eq!(
+            (created.status, created.body.as_slice()),
+            (201, &b"created"[..])
+        );
+        assert_eq!(delay, Duration::from_millis(300));
+        assert!(mocks.answer("GET", "/users/7").is_none());
+        assert!(mocks.answer("GET", "/api/posts").is_none());
+    }
+
+    #[test]
+    fn refuses_other_methods() {
+        let (response, _) = mocks(DEFINITION).answer("DELETE", "/api/users").unwrap();
+        assert_eq!(response.status, 405);
+        assert_eq!(
+            response.headers,
+            vec![("Allow".to_string(), "POST".to_string())]
+        );
+    }
+
+    #[test]
+    fn parses_json_and_route_lists() {
+        let json = r#"{"routes": [{"path": "ping", "body": "pong"}]}"#;
+        let mocks = Mocks::parse(Path::new("mock-api.json"), json.as_bytes(), "").unwrap();
+        assert_eq!(mocks.answer("PUT", "/ping").unwrap().0.body, b"pong");
+
+        let list = mocks("- path: /ping\n  status: 204\n  body: ignored\n");
+        let (response, _) = list.answer("GET", "/api/ping").unwrap();
+        assert_eq!((response.status, response.body.len()), (204, 0));
+    }
+
+    #[test]
+    fn reports_invalid_routes() {
+        let parse = |definition: &str| {
+            Mocks::parse(Path::new("mock-api.yml"), definition.as_bytes(), PREFIX).err()
+        };
+        assert_eq!(
+            parse("routes: {}").as_deref(),
+            Some("expected a `routes` list")
+        );
+        assert_eq!(
+            parse("routes:\n  - method: GET\n").as_deref(),
+            Some("route 1: expected a `path`")
+        );
+        assert_eq!(
+            parse("- path: /a\n- path: /b\n  status: 99\n").as_deref(),
+            Some("route 2: `status` isn't between 200 and 599")
+        );
+        assert!(parse("routes: [").is_some());
+    }
+
+    #[test]
+    fn finds_routes_below_the_prefix() {
+        assert!(is_below("/api", "/api"));
+        assert!(is_below("/api/users", "api/"));
+        assert!(!is_below("/apis", "/api"));
+        assert!(is_below("/anything", ""));
+    }
+}
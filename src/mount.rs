@@ -0,0 +1,77 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::uri::url_path;
+
+/// Directories outside the workspace root served under an url prefix, e.g. `/shared-assets/`
+#[derive(Default)]
+pub struct Mounts {
+    /// url prefix without slashes -> directory, most specific prefix first
+    list: Vec<(String, PathBuf)>,
+}
+
+impl Mounts {
+    /// Mounts `dir` at `/<url>/`, a relative `dir` is taken relative to `root`
+    pub fn push(&mut self, root: &Path, url: &str, dir: &Path) {
+        let url = url.trim_matches('/').to_string();
+        self.list.push((url, normalize(&root.join(dir))));
+        self.list.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+    }
+
+    /// Mounts `dir` at its own name
+    pub fn push_dir(&mut self, root: &Path, dir: &Path) {
+        let dir = normalize(&root.join(dir));
+        let name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.push(root, &name, &dir);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn dirs(&self) -> impl Iterator<Item = &Path> {
+        self.list.iter().map(|(_, dir)| dir.as_path())
+    }
+
+    pub fn contains(&self, file: &Path) -> bool {
+        self.dirs().any(|dir| file.starts_with(dir))
+    }
+
+    /// File a request for `path`, already joined onto `root`, is served from
+    pub fn resolve(&self, root: &Path, path: &Path) -> Option<PathBuf> {
+        let url = url_path(path.strip_prefix(root).ok()?);
+        self.list.iter().find_map(|(prefix, dir)| {
+            let rest = match url.strip_prefix(prefix.as_str()) {
+                Some("") => "",
+                Some(rest) => rest.strip_prefix('/')?,
+                None => return None,
+            };
+            Some(dir.join(rest))
+        })
+    }
+
+    /// Path under `root` a file of a mounted directory is served at
+    pub fn served_path(&self, root: &Path, file: &Path) -> Option<PathBuf> {
+        self.list.iter().find_map(|(prefix, dir)| {
+            let rest = file.strip_prefix(dir).ok()?;
+            Some(root.join(prefix).join(rest))
+        })
+    }
+}
+
+/// Resolves `..` without touching the file system, the editor reports paths the same way
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,57 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Bandwidth responses are limited to, a preset name or kilobits per second
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum Throttle {
+    Kbps(u64),
+    Preset(String),
+}
+
+impl Throttle {
+    /// Kilobits per second, `None` for unknown presets
+    pub fn kbps(&self) -> Option<u64> {
+        match self {
+            Throttle::Kbps(kbps) => Some(*kbps).filter(|kbps| *kbps > 0),
+            Throttle::Preset(name) => match name.to_ascii_lowercase().as_str() {
+                "2g" => Some(250),
+                "3g" => Some(750),
+                "slow-4g" => Some(1600),
+                "4g" => Some(9000),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Delay before every response in milliseconds, fixed like `300` or jittered like `{"min": 100, "max": 800}`
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum Latency {
+    Fixed(u64),
+    Jittered { min: u64, max: u64 },
+}
+
+impl Latency {
+    pub fn delay(&self) -> Duration {
+        let ms = match *self {
+            Latency::Fixed(ms) => ms,
+            Latency::Jittered { min, max } if max > min => {
+                // randomly seeded per call, good enough to spread delays
+                let random = RandomState::new().build_hasher().finish();
+                min + random % (max - min + 1)
+            }
+            Latency::Jittered { min, .. } => min,
+        };
+        Duration::from_millis(ms)
+    }
+}
+
+/// Time `bytes` take to arrive at `kbps`
+pub fn transfer_time(bytes: u64, kbps: u64) -> Duration {
+    Duration::from_millis(bytes.saturating_mul(8) / kbps)
+}
@@ -0,0 +1,380 @@
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use dashmap::DashMap;
+use tokio::fs::read_dir;
+
+use crate::rope::Rope;
+
+/// Bytes of open documents kept in memory unless `overlay_max_size` is set
+pub const DEFAULT_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// In memory layer on top of the disk.
+///
+/// Holds the buffers of open documents and tombstones for paths the editor deleted or renamed,
+/// so serving sees the same state as the editor even before the disk caught up.
+/// Beyond `max_size` bytes the least recently used buffers are dropped and served from disk, see `trim`.
+pub struct Overlay {
+    entries: DashMap<PathBuf, Entry>,
+    max_size: AtomicUsize,
+    tick: AtomicU64,
+}
+
+enum Entry {
+    /// Content and the tick of its last use
+    Buffer(Rope, AtomicU64),
+    /// Open, but served from disk since the buffer was dropped
+    Evicted,
+    Deleted,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self {
+            entries: Default::default(),
+            max_size: AtomicUsize::new(DEFAULT_MAX_SIZE),
+            tick: Default::default(),
+        }
+    }
+}
+
+/// Result of looking up a path in the overlay
+pub enum Lookup {
+    Buffer(Vec<u8>),
+    Deleted,
+    Disk,
+}
+
+impl Overlay {
+    /// Bytes of buffers kept before `trim` drops some, 0 keeps every one
+    pub fn set_max_size(&self, max_size: usize) {
+        self.max_size.store(max_size, Ordering::Relaxed);
+    }
+
+    pub fn open(&self, path: PathBuf, text: &str) {
+        self.replace(path, Rope::from(text));
+    }
+
+    /// Replaces the buffer of `path` wholesale, opening it if needed
+    pub fn replace(&self, path: PathBuf, rope: Rope) {
+        let used = AtomicU64::new(self.next_tick());
+        self.entries.insert(path, Entry::Buffer(rope, used));
+    }
+
+    /// Drops the buffer of `path`, serving it from disk until it is replaced.
+    /// Returns false if there is no buffer or it only exists in memory
+    pub fn evict(&self, path: &Path) -> bool {
+        if !path.is_file() {
+            return false;
+        }
+        match self.entries.get_mut(path).as_deref_mut() {
+            Some(entry @ Entry::Buffer(..)) => {
+                *entry = Entry::Evicted;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Applies `edit` to the buffer of `path`, returns false if there is no open buffer
+    pub fn edit(&self, path: &Path, edit: impl FnOnce(&mut Rope)) -> bool {
+        match self.entries.get_mut(path).as_deref_mut() {
+            Some(Entry::Buffer(rope, used)) => {
+                edit(rope);
+                *used.get_mut() = self.next_tick();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn close(&self, path: &Path) {
+        self.entries.remove_if(path, |_, entry| {
+            matches!(entry, Entry::Buffer(..) | Entry::Evicted)
+        });
+    }
+
+    /// Whether `path` is open in the editor, with a buffer or served from disk
+    pub fn is_open(&self, path: &Path) -> bool {
+        matches!(
+            self.entries.get(path).as_deref(),
+            Some(Entry::Buffer(..) | Entry::Evicted)
+        )
+    }
+
+    /// Drops the least recently used buffers until the rest fit into the maximum size, returns their paths.
+    ///
+    /// Only buffers of files on disk are dropped, documents that exist nowhere else stay, and so does `current`,
+    /// the document just edited, whose next changes would be lost without its buffer.
+    pub fn trim(&self, current: &Path) -> Vec<PathBuf> {
+        let max_size = self.max_size.load(Ordering::Relaxed);
+        if max_size == 0 {
+            return vec![];
+        }
+        let mut buffers: Vec<(u64, usize, PathBuf)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| match entry.value() {
+                Entry::Buffer(rope, used) => Some((
+                    used.load(Ordering::Relaxed),
+                    rope.len(),
+                    entry.key().clone(),
+                )),
+                _ => None,
+            })
+            .collect();
+        let mut size: usize = buffers.iter().map(|(_, len, _)| len).sum();
+        buffers.sort_unstable_by_key(|(used, _, _)| *used);
+        let mut evicted = vec![];
+        for (_, len, path) in buffers {
+            if size <= max_size {
+                break;
+            }
+            if path == current || !path.is_file() {
+                continue;
+            }
+            self.entries.insert(path.clone(), Entry::Evicted);
+            size -= len;
+            evicted.push(path);
+        }
+        evicted
+    }
+
+    fn next_tick(&self) -> u64 {
+        self.tick.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Hides `path` and everything below it
+    pub fn delete(&self, path: &Path) {
+        self.entries.retain(|p, _| !p.starts_with(path));
+        self.entries.insert(path.to_path_buf(), Entry::Deleted);
+    }
+
+    /// Clears tombstones, the path exists again
+    pub fn create(&self, path: &Path) {
+        self.entries
+            .remove_if(path, |_, entry| matches!(entry, Entry::Deleted));
+    }
+
+    /// Moves the buffers below `from` to `to` and hides `from`
+    pub fn rename(&self, from: &Path, to: &Path) {
+        let moved: Vec<PathBuf> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.key().starts_with(from))
+            .map(|entry| entry.key().clone())
+            .collect();
+        for old in moved {
+            let new = to.join(old.strip_prefix(from).unwrap_or(Path::new("")));
+            if let Some((_, entry @ (Entry::Buffer(..) | Entry::Evicted))) =
+                self.entries.remove(&old)
+            {
+                self.entries.insert(new, entry);
+            }
+        }
+        self.entries
+            .retain(|p, entry| !(to.starts_with(p) && matches!(entry, Entry::Deleted)));
+        self.entries.insert(from.to_path_buf(), Entry::Deleted);
+    }
+
+    pub fn lookup(&self, path: &Path) -> Lookup {
+        if self.is_deleted(path) {
+            return Lookup::Deleted;
+        }
+        match self.entries.get(path).as_deref() {
+            Some(Entry::Buffer(rope, used)) => {
+                used.store(self.next_tick(), Ordering::Relaxed);
+                Lookup::Buffer(rope.to_bytes())
+            }
+            _ => Lookup::Disk,
+        }
+    }
+
+    /// Reads a file, open buffers take precedence over the disk
+    pub async fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        match self.lookup(path) {
+            Lookup::Buffer(content) => Ok(content),
+            Lookup::Deleted => Err(ErrorKind::NotFound.into()),
+            Lookup::Disk => tokio::fs::read(path).await,
+        }
+    }
+
+    pub async fn exists(&self, path: &Path) -> bool {
+        if self.is_deleted(path) {
+            return false;
+        }
+        if matches!(self.entries.get(path).as_deref(), Some(Entry::Buffer(..))) {
+            return true;
+        }
+        tokio::fs::metadata(path).await.is_ok()
+    }
+
+    fn is_deleted(&self, path: &Path) -> bool {
+        path.ancestors()
+            .any(|p| matches!(self.entries.get(p).as_deref(), Some(Entry::Deleted)))
+    }
+
+    /// Lists a directory with deleted entries removed and buffers that only exist in memory added
+    pub async fn list_dir(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+        if self.is_deleted(path) {
+            return Err(ErrorKind::NotFound.into());
+        }
+        let mut seen = HashSet::new();
+        let mut entries = vec![];
+        // directories can exist only in memory, e.g. the mount of virtual documents
+        let on_disk = match read_dir(path).await {
+            Ok(mut dir) => {
+                while let Some(entry) = dir.next_entry().await? {
+                    let entry = entry.path();
+                    if !self.is_deleted(&entry) && seen.insert(entry.clone()) {
+                        entries.push(entry);
+                    }
+                }
+                true
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => false,
+            Err(e) => return Err(e),
+        };
+        for entry in self.entries.iter() {
+            if matches!(entry.value(), Entry::Buffer(..))
+                && entry.key().parent() == Some(path)
+                && seen.insert(entry.key().clone())
+            {
+                entries.push(entry.key().clone());
+            }
+        }
+        if !on_disk && entries.is_empty() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What serving `path` sees, the buffer content, `disk` or `deleted`
+    fn served(overlay: &Overlay, path: impl AsRef<Path>) -> String {
+        match overlay.lookup(path.as_ref()) {
+            Lookup::Buffer(content) => String::from_utf8(content).unwrap(),
+            Lookup::Disk => "disk".to_string(),
+            Lookup::Deleted => "deleted".to_string(),
+        }
+    }
+
+    #[test]
+    fn buffers_take_precedence_until_closed() {
+        let overlay = Overlay::default();
+        overlay.open(PathBuf::from("/site/index.html"), "<p>draft</p>");
+        assert_eq!(served(&overlay, "/site/index.html"), "<p>draft</p>");
+        overlay.close(Path::new("/site/index.html"));
+        assert_eq!(served(&overlay, "/site/index.html"), "disk");
+    }
+
+    #[test]
+    fn deletes_hide_everything_below_until_created_again() {
+        let overlay = Overlay::default();
+        overlay.open(PathBuf::from("/site/docs/a.html"), "a");
+        overlay.delete(Path::new("/site/docs"));
+        assert_eq!(served(&overlay, "/site/docs/a.html"), "deleted");
+        assert_eq!(served(&overlay, "/site/docs/b.html"), "deleted");
+        overlay.create(Path::new("/site/docs"));
+        // the buffer went with the delete, the file is read from disk again
+        assert_eq!(served(&overlay, "/site/docs/a.html"), "disk");
+    }
+
+    #[test]
+    fn renames_move_buffers_and_clear_the_tombstone_of_the_destination() {
+        let overlay = Overlay::default();
+        overlay.open(PathBuf::from("/site/old/a.html"), "a");
+        overlay.delete(Path::new("/site/new"));
+        overlay.rename(Path::new("/site/old"), Path::new("/site/new"));
+        assert_eq!(served(&overlay, "/site/new/a.html"), "a");
+        assert_eq!(served(&overlay, "/site/new/b.html"), "disk");
+        assert_eq!(served(&overlay, "/site/old/a.html"), "deleted");
+    }
+
+    /// Fresh directory with `files` on disk, removed again by the caller
+    fn workspace(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "live-server-overlay-{}-{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            std::fs::write(dir.join(file), "on disk").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn trims_the_least_recently_used_buffers_of_files_on_disk() {
+        let dir = workspace("trim", &["a.html", "b.html"]);
+        let overlay = Overlay::default();
+        overlay.open(dir.join("a.html"), "aaaaaaaaaa");
+        overlay.open(dir.join("b.html"), "bbbbbbbbbb");
+        // only in memory, so it stays whatever its age
+        overlay.open(dir.join("new.html"), "nnnnnnnnnn");
+        // serving counts as a use, `b` becomes the oldest
+        served(&overlay, dir.join("a.html"));
+
+        let new = dir.join("new.html");
+        overlay.set_max_size(20);
+        assert_eq!(overlay.trim(&new), vec![dir.join("b.html")]);
+        overlay.set_max_size(5);
+        // the document just edited stays whatever its size
+        assert!(overlay.trim(&dir.join("a.html")).is_empty());
+        assert_eq!(overlay.trim(&new), vec![dir.join("a.html")]);
+        assert_eq!(overlay.trim(&new), Vec::<PathBuf>::new());
+
+        for (file, expected) in [
+            ("a.html", "disk"),
+            ("b.html", "disk"),
+            ("new.html", "nnnnnnnnnn"),
+        ] {
+            assert_eq!(served(&overlay, dir.join(file)), expected);
+            assert!(overlay.is_open(&dir.join(file)));
+        }
+        // opening an evicted buffer again brings it back into memory
+        overlay.open(dir.join("b.html"), "edited");
+        assert_eq!(served(&overlay, dir.join("b.html")), "edited");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_every_buffer_without_a_maximum() {
+        let dir = workspace("unlimited", &["a.html"]);
+        let overlay = Overlay::default();
+        overlay.set_max_size(0);
+        overlay.open(dir.join("a.html"), "aaaaaaaaaa");
+        assert!(overlay.trim(&dir.join("a.html")).is_empty());
+        assert_eq!(served(&overlay, dir.join("a.html")), "aaaaaaaaaa");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn evicts_only_buffers_of_files_on_disk() {
+        let dir = workspace("evict", &["a.html"]);
+        let overlay = Overlay::default();
+        overlay.open(dir.join("a.html"), "a");
+        overlay.open(dir.join("new.html"), "n");
+        assert!(overlay.evict(&dir.join("a.html")));
+        assert!(!overlay.evict(&dir.join("new.html")));
+        // already served from disk
+        assert!(!overlay.evict(&dir.join("a.html")));
+        assert_eq!(served(&overlay, dir.join("a.html")), "disk");
+        assert_eq!(served(&overlay, dir.join("new.html")), "n");
+        // edits need a buffer, until the document is replaced the file is served from disk
+        assert!(!overlay.edit(&dir.join("a.html"), |_| {}));
+        overlay.replace(dir.join("a.html"), Rope::from("synced"));
+        assert_eq!(served(&overlay, dir.join("a.html")), "synced");
+        assert!(overlay.evict(&dir.join("a.html")));
+        overlay.close(&dir.join("a.html"));
+        assert!(!overlay.is_open(&dir.join("a.html")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
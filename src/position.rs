@@ -0,0 +1,167 @@
+use tower_lsp::lsp_types::{Position, PositionEncodingKind};
+
+use crate::rope::Rope;
+
+/// Unit in which the `character` of a [`Position`] is counted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    /// Mandatory for every client and the default if nothing was negotiated
+    #[default]
+    Utf16,
+}
+
+impl PositionEncoding {
+    /// Picks utf-8 if the client offers it, because it matches the buffers without conversion
+    pub fn negotiate(offered: &[PositionEncodingKind]) -> Self {
+        if offered.contains(&PositionEncodingKind::UTF8) {
+            PositionEncoding::Utf8
+        } else {
+            PositionEncoding::Utf16
+        }
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+        }
+    }
+
+    fn len(self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+        }
+    }
+}
+
+/// Converts a position into a byte index of `rope`.
+/// Positions past the end of a line resolve to the end of that line, before its `\n`, `\r\n` or `\r`,
+/// and lines past the end of the text to its end.
+pub fn get_byte_index_from_position(
+    rope: &Rope,
+    position: Position,
+    encoding: PositionEncoding,
+) -> usize {
+    let Some(line_start) = rope.line_start(position.line) else {
+        return rope.len();
+    };
+
+    let mut units = 0;
+    for (i, c) in rope.chars_from(line_start) {
+        // stopping at the `\r` keeps edits from landing inside a `\r\n`
+        if c == '\n' || c == '\r' || units >= position.character as usize {
+            return i;
+        }
+        units += encoding.len(c);
+    }
+    rope.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic xorshift, so failures reproduce without a seed
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn text(&mut self, len: usize) -> String {
+            const PIECES: [&str; 8] = ["a", "b", " ", "\n", "\r\n", "\r", "é", "😀"];
+            (0..len).map(|_| PIECES[self.below(PIECES.len())]).collect()
+        }
+    }
+
+    /// Byte index of a position resolved on the plain string, line by line
+    fn reference(text: &str, position: Position, encoding: PositionEncoding) -> usize {
+        let mut start = 0;
+        for _ in 0..position.line {
+            let rest = &text[start..];
+            let Some(at) = rest.find(['\n', '\r']) else {
+                return text.len();
+            };
+            start += at + if rest[at..].starts_with("\r\n") { 2 } else { 1 };
+        }
+        let line = &text[start..];
+        let line = &line[..line.find(['\n', '\r']).unwrap_or(line.len())];
+        let mut units = 0;
+        for (i, c) in line.char_indices() {
+            if units >= position.character as usize {
+                return start + i;
+            }
+            units += encoding.len(c);
+        }
+        start + line.len()
+    }
+
+    #[test]
+    fn incremental_edits_match_rebuilding_the_string() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        for encoding in [PositionEncoding::Utf8, PositionEncoding::Utf16] {
+            for round in 0..20 {
+                let mut text = random.text(if round % 2 == 0 { 50 } else { 2000 });
+                let mut rope = Rope::from(text.as_str());
+                for _ in 0..200 {
+                    let lines = text.matches('\n').count() + text.matches('\r').count() + 2;
+                    // lines and characters reach past the end to cover the clamping
+                    let mut position =
+                        || Position::new(random.below(lines) as u32, random.below(12) as u32);
+                    let (a, b) = (position(), position());
+                    let (start, end) = if (a.line, a.character) <= (b.line, b.character) {
+                        (a, b)
+                    } else {
+                        (b, a)
+                    };
+                    let len = random.below(4);
+                    let insert = random.text(len);
+
+                    let range = reference(&text, start, encoding)..reference(&text, end, encoding);
+                    assert_eq!(
+                        get_byte_index_from_position(&rope, start, encoding),
+                        range.start
+                    );
+                    assert_eq!(
+                        get_byte_index_from_position(&rope, end, encoding),
+                        range.end
+                    );
+                    rope.replace(range.clone(), &insert);
+                    text.replace_range(range, &insert);
+                    assert_eq!(rope.to_string(), text);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resolves_line_ends_and_the_end_of_the_text() {
+        let rope = Rope::from("a\r\nb\rc\n😀x");
+        let index = |line, character| {
+            get_byte_index_from_position(
+                &rope,
+                Position::new(line, character),
+                PositionEncoding::Utf16,
+            )
+        };
+        assert_eq!(index(0, 5), 1);
+        assert_eq!(index(1, 0), 3);
+        // a lone `\r` ends the line
+        assert_eq!(index(1, 5), 4);
+        assert_eq!(index(2, 0), 5);
+        // the emoji is two utf-16 units, a position between them resolves to its end
+        assert_eq!(index(3, 0), 7);
+        assert_eq!(index(3, 1), 11);
+        assert_eq!(index(3, 2), 11);
+        assert_eq!(index(3, 3), 12);
+        // the end of the text and past it
+        assert_eq!(index(3, 9), 12);
+        assert_eq!(index(4, 0), 12);
+    }
+}
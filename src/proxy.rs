@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::ProxyMode;
+
+/// Request bodies forwarded to an upstream larger than this are rejected
+pub const MAX_BODY: usize = 16 * 1024 * 1024;
+/// Upstream responses larger than this are answered with a 502 instead
+const MAX_RESPONSE: usize = 64 * 1024 * 1024;
+/// Upstreams that don't answer within this are answered with a 504, so they don't hold gateway connections
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Request headers that describe the connection to the gateway instead of the request,
+/// or that are secrets of the relay and the server the upstream has no business seeing
+const HOP_HEADERS: [&str; 11] = [
+    "host",
+    "connection",
+    "keep-alive",
+    "content-length",
+    "transfer-encoding",
+    "upgrade",
+    "origin",
+    "referer",
+    // recordings stay readable if upstreams answer uncompressed
+    "accept-encoding",
+    "x-live-server-token",
+    // cookies of the site, like the one admitting visitors of a share
+    "cookie",
+];
+
+/// Response of an upstream or a recording
+#[derive(Serialize, Deserialize)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    #[serde(with = "body")]
+    pub body: Vec<u8>,
+}
+
+/// Upstream APIs behind url prefixes of the pages, which the gateway passes requests and websockets on to.
+///
+/// Only plain http upstreams are supported, like a backend running on the same machine.
+pub struct Proxy {
+    /// Prefix, upstream host with port, and the path requests are forwarded under
+    routes: Vec<(String, String, String)>,
+    mode: ProxyMode,
+    recordings: PathBuf,
+}
+
+impl Proxy {
+    /// Routes from prefixes to upstream base urls like `{"/api": "http://localhost:8080"}`
+    pub fn new(
+        routes: &HashMap<String, String>,
+        mode: ProxyMode,
+        recordings: PathBuf,
+    ) -> Result<Self, String> {
+        let mut parsed = routes
+            .iter()
+            .map(|(prefix, upstream)| {
+                let rest = upstream
+                    .strip_prefix("http://")
+                    .ok_or_else(|| format!("{} isn't an http url", upstream))?;
+                let (host, base) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+                let host = match host.contains(':') && !host.ends_with(']') {
+                    true => host.to_string(),
+                    false => format!("{}:80", host),
+                };
+                let prefix = format!("/{}", prefix.trim_matches('/'));
+                Ok((prefix, host, base.trim_end_matches('/').to_string()))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        // the longest prefix wins
+        parsed.sort_by_key(|(prefix, _, _)| std::cmp::Reverse(prefix.len()));
+        Ok(Self {
+            routes: parsed,
+            mode,
+            recordings,
+        })
+    }
+
+    pub fn prefixes(&self) -> Vec<&str> {
+        self.routes
+            .iter()
+            .map(|(prefix, _, _)| prefix.as_str())
+            .collect()
+    }
+
+    /// Route of the longest prefix `target` is below
+    fn route(&self, target: &str) -> Option<&(String, String, String)> {
+        self.routes.iter().find(|(prefix, _, _)| {
+            target
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?']))
+        })
+    }
+
+    /// Whether `target` is below one of the prefixes
+    pub fn routes(&self, target: &str) -> bool {
+        self.route(target).is_some()
+    }
+
+    /// Answers `method` on `target`, a path with query below one of the prefixes
+    pub async fn handle(
+        &self,
+        method: &str,
+        target: &str,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> Response {
+        let Some((prefix, host, base)) = self.route(target) else {
+            return error(404, "Not Found", format!("no upstream for {}", target));
+        };
+        let recording = self.recordings.join(recording_name(method, target, body));
+        if self.mode == ProxyMode::Replay {
+            return match tokio::fs::read(&recording).await {
+                Ok(text) => serde_json::from_slice(&text).unwrap_or_else(|e| {
+                    error(502, "Bad Gateway", format!("invalid recording: {}", e))
+                }),
+                Err(_) => error(
+                    504,
+                    "Gateway Timeout",
+                    format!("no recording of {} {}", method, target),
+                ),
+            };
+        }
+        let path = format!("{}{}", base, &target[prefix.len()..]);
+        let forwarded = forward(host, method, &path, headers, body);
+        let response = match tokio::time::timeout(UPSTREAM_TIMEOUT, forwarded).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => return error(502, "Bad Gateway", format!("{}: {}", host, e)),
+            Err(_) => {
+                let message = format!(
+                    "{} didn't answer within {}s",
+                    host,
+                    UPSTREAM_TIMEOUT.as_secs()
+                );
+                return error(504, "Gateway Timeout", message);
+            }
+        };
+        if self.mode == ProxyMode::Record {
+            let _ = tokio::fs::create_dir_all(&self.recordings).await;
+            if let Ok(text) = serde_json::to_vec_pretty(&response) {
+                let _ = tokio::fs::write(&recording, text).await;
+            }
+        }
+        response
+    }
+
+    /// Connects to the upstream of `target` and sends it the upgrade request,
+    /// the returned stream is piped to the client from its response on.
+    /// Upgraded connections aren't recorded, so `replay` refuses them
+    pub async fn upgrade(
+        &self,
+        method: &str,
+        target: &str,
+        headers: &[(String, String)],
+    ) -> std::io::Result<TcpStream> {
+        let Some((prefix, host, base)) = self.route(target) else {
+            return Err(ErrorKind::NotFound.into());
+        };
+        if self.mode == ProxyMode::Replay {
+            let message = "upgrades aren't recorded";
+            return Err(std::io::Error::new(ErrorKind::Unsupported, message));
+        }
+        let connect = TcpStream::connect(host.as_str());
+        let mut stream = tokio::time::timeout(UPSTREAM_TIMEOUT, connect)
+            .await
+            .map_err(|_| ErrorKind::TimedOut)??;
+        let path = format!("{}{}", base, &target[prefix.len()..]);
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\n",
+            method,
+            match path.is_empty() {
+                true => "/",
+                false => path.as_str(),
+            },
+            host
+        );
+        for (name, value) in headers {
+            let lower = name.to_ascii_lowercase();
+            if matches!(lower.as_str(), "connection" | "upgrade")
+                || !HOP_HEADERS.contains(&lower.as_str())
+            {
+                request.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        Ok(stream)
+    }
+}
+
+fn error(status: u16, reason: &str, message: String) -> Response {
+    Response {
+        status,
+        reason: reason.to_string(),
+        headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+        body: json!({ "error": message }).to_string().into_bytes(),
+    }
+}
+
+/// File of the recording for a request, readable and keyed by a hash of everything that identifies it
+fn recording_name(method: &str, target: &str, body: &[u8]) -> String {
+    // FNV-1a, stable across builds unlike the std hashers
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in [method.as_bytes(), b" ", target.as_bytes(), b"\n", body].concat() {
+        hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+    }
+    let path: String = target
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .trim_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect();
+    format!("{}-{}-{:016x}.json", method, path, hash)
+}
+
+/// Sends one request to `host` over a fresh connection and reads the whole response,
+/// failing if it is larger than `MAX_RESPONSE`
+async fn forward(
+    host: &str,
+    method: &str,
+    path: &str,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> std::io::Result<Response> {
+    let mut stream = TcpStream::connect(host).await?;
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        match path.is_empty() {
+            true => "/",
+            false => path,
+        },
+        host,
+        body.len()
+    );
+    for (name, value) in headers {
+        if !HOP_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+    stream.write_all(body).await?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.trim_end().splitn(3, ' ');
+    let status = parts
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or(ErrorKind::InvalidData)?;
+    let reason = parts.next().unwrap_or_default().to_string();
+    let mut headers = vec![];
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.parse::<usize>().ok(),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            "connection" | "keep-alive" => {}
+            _ => headers.push((name.to_string(), value.to_string())),
+        }
+    }
+    let mut body = vec![];
+    let bodyless = method == "HEAD" || matches!(status, 204 | 304);
+    match content_length {
+        _ if bodyless => {}
+        _ if chunked => loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).await?;
+            let size = usize::from_str_radix(size.trim().split(';').next().unwrap_or("0"), 16)
+                .map_err(|_| ErrorKind::InvalidData)?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            if size > MAX_RESPONSE - start {
+                return Err(too_large());
+            }
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..]).await?;
+            // the line break after the chunk
+            reader.read_line(&mut String::new()).await?;
+        },
+        Some(len) if len > MAX_RESPONSE => return Err(too_large()),
+        Some(len) => {
+            body.resize(len, 0);
+            reader.read_exact(&mut body).await?;
+        }
+        None => {
+            let limit = MAX_RESPONSE as u64 + 1;
+            (&mut reader).take(limit).read_to_end(&mut body).await?;
+            if body.len() > MAX_RESPONSE {
+                return Err(too_large());
+            }
+        }
+    }
+    Ok(Response {
+        status,
+        reason,
+        headers,
+        body,
+    })
+}
+
+fn too_large() -> std::io::Error {
+    let message = format!("response larger than {} bytes", MAX_RESPONSE);
+    std::io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Bodies are recorded as text if they are utf-8, as a list of bytes otherwise
+mod body {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::Value;
+
+    pub fn serialize<S: Serializer>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(body) {
+            Ok(text) => serializer.serialize_str(text),
+            Err(_) => serializer.serialize_bytes(body),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::String(text) => Ok(text.into_bytes()),
+            Value::Array(bytes) => bytes
+                .iter()
+                .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                .collect::<Option<_>>()
+                .ok_or_else(|| D::Error::custom("body bytes out of range")),
+            _ => Err(D::Error::custom("expected the body as a string or bytes")),
+        }
+    }
+}
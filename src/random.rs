@@ -0,0 +1,29 @@
+use std::io::Result;
+
+/// Bytes from the random generator of the operating system, for secrets that must not be guessable
+pub fn bytes<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).map_err(|e| std::io::Error::other(e.to_string()))?;
+    Ok(bytes)
+}
+
+/// 128 random bits in hex, for tokens handed to browsers and visitors
+pub fn token() -> Result<String> {
+    Ok(bytes::<16>()?
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_hex_and_differ() {
+        let (first, second) = (token().unwrap(), token().unwrap());
+        assert_eq!(first.len(), 32);
+        assert!(first.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,419 @@
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+
+use crate::limits::{Connections, Limits};
+use crate::random;
+use crate::share;
+
+/// Bodies of posted events larger than this are rejected
+const MAX_BODY: usize = 64 * 1024;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// Connections that didn't send a whole request by then are closed, so stalled clients don't hold on to a slot
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CLIENT_SCRIPT: &str = include_str!("client.js");
+
+/// Per workspace event channel between the injected client script and the LSP.
+///
+/// Browsers subscribe to `GET /events` (server sent events) and publish with `POST /emit`,
+/// every published event is relayed to all subscribers. `POST /report` sends an event to the LSP only,
+/// as do subscribers connecting and disconnecting with `connect` and `disconnect` reports.
+///
+/// Posts carry the token of the client script, and only pages of the workspace server may read responses.
+pub struct Relay {
+    port: u16,
+    /// Port of the workspace server, whose pages are the only origin allowed
+    site: Arc<RwLock<u16>>,
+    /// Secret of the injected client script, required to post events
+    token: String,
+    connections: Arc<Connections>,
+    /// Host names accepted besides localhost and ip addresses
+    hosts: Vec<String>,
+    events: broadcast::Sender<String>,
+    reports: broadcast::Sender<Value>,
+}
+
+/// Parts of the client script enabled for a workspace
+#[derive(Serialize, Default, Clone)]
+pub struct ClientFeatures {
+    /// Mirror scroll, click and input events between browsers
+    pub sync: bool,
+    /// Report console output and uncaught errors
+    pub console: bool,
+    /// Re-import changed javascript modules instead of reloading the page
+    pub hmr: bool,
+    /// Tell the editor when browsers connect and disconnect
+    pub presence: bool,
+    /// Navigate to the page of the active editor document
+    pub follow: bool,
+    /// Reload over server sent events where the websocket of the live server is blocked
+    pub reload: bool,
+    /// Scroll rendered documents to the line of the editor cursor
+    pub documents: bool,
+    /// Announce requests to the inspector page, the client script ignores them
+    pub inspector: bool,
+    /// Show the toolbar with the connection, last reload and reload controls
+    pub toolbar: bool,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    user_agent: String,
+    host: String,
+    body: Vec<u8>,
+}
+
+impl Relay {
+    /// Binds a free port next to the workspace server and starts accepting connections.
+    ///
+    /// Requests with a `Host` other than localhost, an ip address or one of `hosts` are rejected,
+    /// which keeps pages of rebound domains from talking to the relay, on loopback as well.
+    /// A rebound name resolves to 127.0.0.1 just as well as to a public address.
+    pub async fn start(
+        address: IpAddr,
+        site: Arc<RwLock<u16>>,
+        hosts: &[String],
+        limits: Limits,
+    ) -> std::io::Result<(Arc<Self>, JoinHandle<()>)> {
+        let listener = TcpListener::bind((address, 0)).await?;
+        let (events, _) = broadcast::channel(256);
+        let (reports, _) = broadcast::channel(256);
+        let relay = Arc::new(Self {
+            port: listener.local_addr()?.port(),
+            site,
+            token: random::token()?,
+            connections: Connections::new(limits),
+            hosts: hosts.iter().map(|h| h.to_ascii_lowercase()).collect(),
+            events,
+            reports,
+        });
+        let accept = relay.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((mut stream, address)) = listener.accept().await {
+                let Some(connection) = accept.connections.admit(Some(address.ip())) else {
+                    tokio::spawn(async move {
+                        let _ = respond(&mut stream, "503 Service Unavailable", "", "").await;
+                    });
+                    continue;
+                };
+                let relay = accept.clone();
+                tokio::spawn(async move {
+                    let _ = relay.handle(stream, address).await;
+                    drop(connection);
+                });
+            }
+        });
+        Ok((relay, handle))
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Browsers currently subscribed to the events
+    pub fn clients(&self) -> usize {
+        self.events.receiver_count()
+    }
+
+    /// Sends an event to every connected browser
+    pub fn broadcast(&self, event: &Value) {
+        let _ = self.events.send(event.to_string());
+    }
+
+    /// Events browsers reported to the LSP
+    pub fn reports(&self) -> broadcast::Receiver<Value> {
+        self.reports.subscribe()
+    }
+
+    /// Secret of the client script
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Script tag to inject into served html, carrying `nonce` for a Content-Security-Policy
+    /// and whether live reload is `paused` for the toolbar
+    pub fn client_script(
+        &self,
+        features: &ClientFeatures,
+        nonce: Option<&str>,
+        paused: bool,
+    ) -> String {
+        let config = json!({
+            "relay": self.port,
+            "token": self.token,
+            "features": features,
+            "paused": paused,
+        });
+        let nonce = nonce.map_or(String::new(), |nonce| format!(" nonce=\"{}\"", nonce));
+        format!(
+            "<script{}>window.__liveServer = {};\n{}</script>",
+            nonce, config, CLIENT_SCRIPT
+        )
+    }
+
+    async fn handle(&self, mut stream: TcpStream, address: SocketAddr) -> std::io::Result<()> {
+        let request = match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+            Ok(request) => request?,
+            Err(_) => return Err(ErrorKind::TimedOut.into()),
+        };
+        let cors = self.cors(&request).await;
+        if !is_allowed_host(&request.host, &self.hosts) {
+            return respond(&mut stream, "403 Forbidden", &cors, "").await;
+        }
+        // preflights can't carry the token, they answer nothing but the cors headers
+        let guarded =
+            request.method == "POST" && matches!(request.path.as_str(), "/emit" | "/report");
+        if guarded && request.header("x-live-server-token") != Some(self.token.as_str()) {
+            return respond(&mut stream, "403 Forbidden", &cors, "").await;
+        }
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/events") => {
+                let client = json!({ "address": address.ip(), "userAgent": request.user_agent });
+                let _ = self
+                    .reports
+                    .send(json!({ "type": "connect", "client": client }));
+                let result = self.subscribe(stream, &cors).await;
+                let _ = self
+                    .reports
+                    .send(json!({ "type": "disconnect", "client": client }));
+                result
+            }
+            ("POST", "/emit") => match serde_json::from_slice::<Value>(&request.body) {
+                // scripts may only come from the editor
+                Ok(event) if event.get("type") == Some(&json!("script")) => {
+                    respond(&mut stream, "403 Forbidden", &cors, "").await
+                }
+                Ok(event)
+                    if event.get("type") == Some(&json!("navigate"))
+                        && !event
+                            .get("path")
+                            .and_then(Value::as_str)
+                            .is_some_and(is_path) =>
+                {
+                    respond(&mut stream, "400 Bad Request", &cors, "").await
+                }
+                Ok(event) => {
+                    self.broadcast(&event);
+                    respond(&mut stream, "204 No Content", &cors, "").await
+                }
+                Err(_) => respond(&mut stream, "400 Bad Request", &cors, "").await,
+            },
+            ("POST", "/report") => match serde_json::from_slice::<Value>(&request.body) {
+                Ok(report) => {
+                    let _ = self.reports.send(report);
+                    respond(&mut stream, "204 No Content", &cors, "").await
+                }
+                Err(_) => respond(&mut stream, "400 Bad Request", &cors, "").await,
+            },
+            ("OPTIONS", _) => respond(&mut stream, "204 No Content", &cors, "").await,
+            _ => respond(&mut stream, "404 Not Found", &cors, "").await,
+        }
+    }
+
+    /// Headers letting a page of the workspace server read the response, none for other origins.
+    /// Preflights get the method and headers they ask for, like the token header of the posts.
+    async fn cors(&self, request: &Request) -> String {
+        let Some(origin) = request.header("origin") else {
+            return String::new();
+        };
+        // the client script reaches the relay under the host name of the page
+        let site = match *self.site.read().await {
+            80 => format!("http://{}", host_name(&request.host)),
+            port => format!("http://{}:{}", host_name(&request.host), port),
+        };
+        if !origin.eq_ignore_ascii_case(&site) {
+            return String::new();
+        }
+        let mut headers = format!(
+            "Access-Control-Allow-Origin: {}\r\nVary: Origin\r\n",
+            origin
+        );
+        if request.method == "OPTIONS" {
+            for (name, allow) in [
+                ("access-control-request-method", "Methods"),
+                ("access-control-request-headers", "Headers"),
+            ] {
+                if let Some(value) = request.header(name) {
+                    headers.push_str(&format!("Access-Control-Allow-{}: {}\r\n", allow, value));
+                }
+            }
+        }
+        headers
+    }
+
+    async fn subscribe(&self, mut stream: TcpStream, cors: &str) -> std::io::Result<()> {
+        let mut events = self.events.subscribe();
+        let (mut reader, mut stream) = stream.split();
+        let mut buffer = [0; 64];
+        let head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n{}Connection: keep-alive\r\n\r\n",
+            cors
+        );
+        stream.write_all(head.as_bytes()).await?;
+        loop {
+            let message = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => format!("data: {}\n\n", event),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                },
+                _ = tokio::time::sleep(KEEP_ALIVE) => ": ping\n\n".to_string(),
+                // browsers never send on this connection, so a read only returns once it closed
+                read = reader.read(&mut buffer) => match read {
+                    Ok(0) | Err(_) => return Ok(()),
+                    Ok(_) => continue,
+                },
+            };
+            stream.write_all(message.as_bytes()).await?;
+        }
+    }
+}
+
+/// Request of a client script, its head read by [`share::read_head`], which bounds its size and time
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Request> {
+    let Some(mut head) = share::read_head(stream).await? else {
+        return Err(ErrorKind::InvalidData.into());
+    };
+    let end = head
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or(ErrorKind::InvalidData)?;
+    // what of the body came along with the head
+    let mut body = head.split_off(end + 4);
+    let text = String::from_utf8_lossy(&head);
+    let mut lines = text.split("\r\n");
+    let mut parts = lines.next().unwrap_or_default().split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    let mut user_agent = String::new();
+    let mut host = String::new();
+    let mut headers = vec![];
+    for header in lines {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("user-agent") {
+                user_agent = value.trim().to_string();
+            } else if name.eq_ignore_ascii_case("host") {
+                host = value.trim().to_string();
+            }
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(ErrorKind::InvalidData.into());
+    }
+    // every connection carries one request, anything past the body is dropped
+    let read = body.len().min(content_length);
+    body.resize(content_length, 0);
+    stream.read_exact(&mut body[read..]).await?;
+    Ok(Request {
+        method,
+        path,
+        headers,
+        user_agent,
+        host,
+        body,
+    })
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Whether `path` is an absolute path on the same origin, not a url or a protocol relative `//host`
+fn is_path(path: &str) -> bool {
+    path.starts_with('/') && !path[1..].starts_with(['/', '\\'])
+}
+
+/// Whether `host`, the `Host` header of a request, is localhost, an ip address or one of the lowercase `hosts`.
+///
+/// Pages of a rebound domain send their own name, which keeps them out even if it resolves to 127.0.0.1
+pub fn is_allowed_host(host: &str, hosts: &[String]) -> bool {
+    let name = host_name(host);
+    let name = name
+        .strip_prefix('[')
+        .and_then(|name| name.strip_suffix(']'))
+        .unwrap_or(name)
+        .to_ascii_lowercase();
+    name == "localhost"
+        || name.ends_with(".localhost")
+        || name.parse::<IpAddr>().is_ok()
+        || hosts.contains(&name)
+}
+
+/// `host` without its port, ipv6 addresses stay bracketed
+fn host_name(host: &str) -> &str {
+    match host.find(']') {
+        Some(end) if host.starts_with('[') => &host[..=end],
+        _ => host.split(':').next().unwrap_or_default(),
+    }
+}
+
+/// Writes a response with the `cors` headers from [`Relay::cors`]
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    cors: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        cors,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+/// Inserts `snippet` before the closing body tag, or appends it if there is none
+pub fn inject(html: Vec<u8>, snippet: &str) -> Vec<u8> {
+    let lower = html.to_ascii_lowercase();
+    let at = lower
+        .windows(7)
+        .rposition(|w| w == b"</body>")
+        .unwrap_or(html.len());
+    let mut out = Vec::with_capacity(html.len() + snippet.len());
+    out.extend_from_slice(&html[..at]);
+    out.extend_from_slice(snippet.as_bytes());
+    out.extend_from_slice(&html[at..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_loopback_ip_and_listed_hosts() {
+        let hosts = ["mybox.lan".to_string()];
+        assert!(is_allowed_host("localhost:57391", &hosts));
+        assert!(is_allowed_host("site.localhost", &hosts));
+        assert!(is_allowed_host("192.168.1.20:57391", &hosts));
+        assert!(is_allowed_host("[::1]:57391", &hosts));
+        assert!(is_allowed_host("MyBox.lan:57391", &hosts));
+        assert!(!is_allowed_host("rebound.example:57391", &hosts));
+        assert!(!is_allowed_host("localhost.example", &hosts));
+        assert!(!is_allowed_host("", &hosts));
+    }
+}
@@ -0,0 +1,56 @@
+/// Url of the responsive preview of a workspace
+pub const PATH: &str = "__responsive";
+
+/// Viewport widths unless `responsive_widths` is set: phone, tablet and laptop
+pub const WIDTHS: [u32; 3] = [375, 768, 1280];
+
+const STYLE: &str = "body { font: 13px system-ui, sans-serif; margin: 0; background: #eee; }
+header { display: flex; gap: 8px; padding: 8px; background: #fff; border-bottom: 1px solid #ddd; }
+input { flex: 1; }
+main { display: flex; gap: 16px; padding: 16px; overflow-x: auto; align-items: flex-start; }
+figure { margin: 0; }
+figcaption { padding-bottom: 4px; color: #555; }
+iframe { height: calc(100vh - 110px); border: 1px solid #ccc; background: #fff; }";
+
+/// The page embedding the site in a frame per width, each running its own reload script.
+/// `?path=/about.html` picks the page, the frames follow navigation in any of them.
+const SCRIPT: &str = "const input = document.querySelector('input');
+const frames = Array.from(document.querySelectorAll('iframe'));
+const show = (path) => {
+  input.value = path;
+  history.replaceState(null, '', `?path=${encodeURIComponent(path)}`);
+  for (const frame of frames) {
+    if (frame.contentWindow?.location.pathname + frame.contentWindow?.location.search !== path) frame.src = path;
+  }
+};
+for (const frame of frames) {
+  frame.addEventListener('load', () => {
+    const location = frame.contentWindow.location;
+    show(location.pathname + location.search);
+  });
+}
+document.querySelector('form').addEventListener('submit', (event) => {
+  event.preventDefault();
+  show(input.value.startsWith('/') ? input.value : `/${input.value}`);
+});
+show(new URLSearchParams(location.search).get('path') ?? '/');";
+
+pub fn page(widths: &[u32]) -> String {
+    let frames: String = widths
+        .iter()
+        .map(|width| {
+            format!(
+                "<figure><figcaption>{} px</figcaption><iframe style=\"width: {}px\"></iframe></figure>",
+                width, width
+            )
+        })
+        .collect();
+    format!(
+        "<!doctype html><html><head><meta charset=\"utf-8\"><title>Responsive preview</title><style>{}</style></head>\
+<body><header><form style=\"display: contents\"><input aria-label=\"Page\" value=\"/\"><button>Show</button></form></header>\
+<main>{}</main><script>{}</script></body></html>",
+        STYLE,
+        frames,
+        SCRIPT
+    )
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+use std::ops::Range;
+
+/// Text of an open document, kept in a tree of chunks that caches the bytes, chars and line breaks below every node,
+/// so edits and byte and line lookups take logarithmic time instead of walking or reindexing the text.
+///
+/// A line ends at `\n`, `\r\n` or a lone `\r`, like the positions of the language server protocol count them.
+#[derive(Clone, Default)]
+pub struct Rope(ropey::Rope);
+
+impl Rope {
+    pub fn len(&self) -> usize {
+        self.0.len_bytes()
+    }
+
+    /// Replaces the byte range with `text`. Both ends of the range must lie on char boundaries.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        let start = range.start.min(self.len());
+        let end = range.end.clamp(start, self.len());
+        let start = self.0.byte_to_char(start);
+        self.0.remove(start..self.0.byte_to_char(end));
+        self.0.insert(start, text);
+    }
+
+    /// Byte index of the first char in line `line` (zero based)
+    pub fn line_start(&self, line: u32) -> Option<usize> {
+        let line = line as usize;
+        (line < self.0.len_lines()).then(|| self.0.line_to_byte(line))
+    }
+
+    /// Iterates over the chars starting at byte `from` together with their byte index
+    pub fn chars_from(&self, from: usize) -> impl Iterator<Item = (usize, char)> + '_ {
+        let mut at = from.min(self.len());
+        self.0.chars_at(self.0.byte_to_char(at)).map(move |c| {
+            let start = at;
+            at += c.len_utf8();
+            (start, c)
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len());
+        for chunk in self.0.chunks() {
+            bytes.extend_from_slice(chunk.as_bytes());
+        }
+        bytes
+    }
+}
+
+impl From<&str> for Rope {
+    fn from(text: &str) -> Self {
+        Self(ropey::Rope::from_str(text))
+    }
+}
+
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.0.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compares `rope` with the text it should hold, line by line
+    fn check(rope: &Rope, text: &str) {
+        assert_eq!(rope.to_string(), text);
+        assert_eq!(rope.to_bytes(), text.as_bytes());
+        assert_eq!(rope.len(), text.len());
+        let mut expected = vec![0];
+        expected.extend(text.char_indices().filter_map(|(i, c)| match c {
+            '\n' => Some(i + 1),
+            '\r' if !text[i + 1..].starts_with('\n') => Some(i + 1),
+            _ => None,
+        }));
+        for (line, start) in expected.iter().enumerate() {
+            assert_eq!(rope.line_start(line as u32), Some(*start));
+        }
+        assert_eq!(rope.line_start(expected.len() as u32), None);
+    }
+
+    fn text(len: usize) -> String {
+        (0..len)
+            .map(|i| match i % 37 {
+                0 => '\n',
+                7 => 'é',
+                13 => '\r',
+                _ => char::from(b'a' + (i % 26) as u8),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn looks_up_the_lines_of_long_text() {
+        let text = text(50_000);
+        check(&Rope::from(text.as_str()), &text);
+    }
+
+    #[test]
+    fn edits_anywhere_in_the_text() {
+        let mut text = text(5000);
+        let mut rope = Rope::from(text.as_str());
+        for (range, insert) in [
+            (1024..1024, "x\ny"),
+            (1021..1027, ""),
+            (1014..2524, "joined\n"),
+            (0..0, "start\n"),
+            (text.len()..text.len(), "\nend"),
+            (9000..9100, "past the end"),
+        ] {
+            let range = range.start.min(text.len())..range.end.min(text.len());
+            let range = floor(&text, range.start)..floor(&text, range.end);
+            rope.replace(range.clone(), insert);
+            text.replace_range(range, insert);
+            check(&rope, &text);
+        }
+    }
+
+    #[test]
+    fn deletes_char_by_char() {
+        let mut text = text(3000);
+        let mut rope = Rope::from(text.as_str());
+        while text.len() > 1200 {
+            let at = floor(&text, 1025);
+            let end = at + text[at..].chars().next().map_or(0, char::len_utf8);
+            rope.replace(at..end, "");
+            text.replace_range(at..end, "");
+        }
+        check(&rope, &text);
+    }
+
+    #[test]
+    fn joins_crlf_an_edit_brings_together() {
+        let mut text = format!("{}\rx\n{}", "a".repeat(1023), "b".repeat(1024));
+        let mut rope = Rope::from(text.as_str());
+        check(&rope, &text);
+        assert_eq!(rope.line_start(2), Some(1026));
+        // deleting the `x` between them leaves one `\r\n`
+        rope.replace(1024..1025, "");
+        text.replace_range(1024..1025, "");
+        check(&rope, &text);
+        assert_eq!(rope.line_start(1), Some(1025));
+        assert_eq!(rope.line_start(2), None);
+    }
+
+    #[test]
+    fn iterates_chars_from_a_byte() {
+        let text = text(3000);
+        let rope = Rope::from(text.as_str());
+        let from = floor(&text, 1024);
+        let chars: String = rope.chars_from(from).map(|(_, c)| c).collect();
+        assert_eq!(chars, text[from..]);
+        assert!(rope
+            .chars_from(from)
+            .eq(text[from..].char_indices().map(|(i, c)| (from + i, c))));
+        assert_eq!(rope.chars_from(text.len()).next(), None);
+    }
+
+    fn floor(text: &str, mut at: usize) -> usize {
+        while !text.is_char_boundary(at) {
+            at -= 1;
+        }
+        at
+    }
+}
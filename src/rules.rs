@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use crate::ignore::glob;
+
+/// Headers the gateway frames responses with, which rules can't replace
+const FRAMING: [&str; 4] = [
+    "content-length",
+    "transfer-encoding",
+    "connection",
+    "keep-alive",
+];
+
+/// Response headers per glob of the url path, see `header_rules`.
+///
+/// Globs without a slash match the file name, like `*.woff2`, the others the whole path below the root, like `/fonts/**`
+#[derive(Default)]
+pub struct HeaderRules {
+    /// Shorter globs first, so the headers of longer, more specific ones win
+    rules: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl HeaderRules {
+    pub fn new(rules: &HashMap<String, HashMap<String, String>>) -> Self {
+        let mut rules: Vec<_> = rules
+            .iter()
+            .map(|(pattern, headers)| {
+                let headers = headers
+                    .iter()
+                    .filter(|(name, _)| {
+                        !FRAMING
+                            .iter()
+                            .any(|framing| name.eq_ignore_ascii_case(framing))
+                    })
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect();
+                (pattern.trim_start_matches('/').to_string(), headers)
+            })
+            .collect();
+        rules.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+        Self { rules }
+    }
+
+    /// Headers of the rules matching a decoded url path, one per name
+    pub fn headers(&self, path: &str) -> Vec<(String, String)> {
+        let path = path.trim_start_matches('/');
+        let name = path.rsplit('/').next().unwrap_or_default();
+        let mut matched: Vec<(String, String)> = vec![];
+        for (pattern, headers) in &self.rules {
+            let text = match pattern.contains('/') {
+                true => path,
+                false => name,
+            };
+            if !glob(pattern.as_bytes(), text.as_bytes()) {
+                continue;
+            }
+            for (name, value) in headers {
+                matched.retain(|(matched, _)| !matched.eq_ignore_ascii_case(name));
+                matched.push((name.clone(), value.clone()));
+            }
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[(&str, &[(&str, &str)])]) -> HeaderRules {
+        let rules = rules
+            .iter()
+            .map(|(pattern, headers)| {
+                let headers = headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect();
+                (pattern.to_string(), headers)
+            })
+            .collect();
+        HeaderRules::new(&rules)
+    }
+
+    #[test]
+    fn matches_names_and_paths() {
+        let rules = rules(&[
+            ("*.woff2", &[("Cache-Control", "max-age=31536000")]),
+            ("/docs/**", &[("X-Robots-Tag", "noindex")]),
+        ]);
+        let cached = vec![("Cache-Control".to_string(), "max-age=31536000".to_string())];
+        assert_eq!(rules.headers("/fonts/inter.woff2"), cached);
+        assert_eq!(rules.headers("/docs/intro.html").len(), 1);
+        assert!(rules.headers("/intro.html").is_empty());
+    }
+
+    #[test]
+    fn longer_globs_win() {
+        let rules = rules(&[
+            ("*.html", &[("Cache-Control", "no-store")]),
+            ("/static/*.html", &[("cache-control", "max-age=60")]),
+            ("*", &[("Content-Length", "0")]),
+        ]);
+        let headers = rules.headers("/static/page.html");
+        assert_eq!(
+            headers,
+            vec![("cache-control".to_string(), "max-age=60".to_string())]
+        );
+        assert_eq!(rules.headers("/page.html")[0].1, "no-store");
+    }
+}
@@ -0,0 +1,62 @@
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::Stdio;
+
+use tokio::process::Command;
+
+/// Tried in order if no browser is configured
+const BROWSERS: [&str; 6] = [
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+    "chrome",
+    "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
+];
+
+/// Renders `url` at the given viewport with a headless chromium and saves it as png at `out`
+pub async fn capture(
+    browser: Option<&str>,
+    url: &str,
+    width: u32,
+    height: u32,
+    out: &Path,
+) -> Result<(), String> {
+    if let Some(parent) = out.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("{}: {}", parent.display(), e))?;
+    }
+    // a stale screenshot must not pass for a new one
+    let _ = tokio::fs::remove_file(out).await;
+    let candidates = match browser {
+        Some(browser) => vec![browser],
+        None => BROWSERS.to_vec(),
+    };
+    for browser in candidates {
+        let output = Command::new(browser)
+            .arg("--headless=new")
+            .arg("--disable-gpu")
+            .arg("--hide-scrollbars")
+            .arg(format!("--window-size={},{}", width, height))
+            .arg(format!("--screenshot={}", out.display()))
+            .arg(url)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await;
+        let output = match output {
+            Ok(output) => output,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("failed to run {}: {}", browser, e)),
+        };
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        return match tokio::fs::try_exists(out).await {
+            Ok(true) => Ok(()),
+            _ => Err(format!("{} did not write a screenshot", browser)),
+        };
+    }
+    Err("no chromium based browser found, set `headless_browser`".to_string())
+}
@@ -0,0 +1,48 @@
+use std::path::Path;
+use std::process::Stdio;
+
+use serde_json::Value;
+use tokio::process::{Child, Command};
+
+/// Lock files and the package manager they belong to, the first one found runs the scripts
+const LOCK_FILES: [(&str, &str); 4] = [
+    ("pnpm-lock.yaml", "pnpm"),
+    ("yarn.lock", "yarn"),
+    ("bun.lock", "bun"),
+    ("bun.lockb", "bun"),
+];
+
+/// Names of the scripts in the `package.json` of `workspace`, sorted
+pub async fn list(workspace: &Path) -> Vec<String> {
+    let Ok(text) = tokio::fs::read(workspace.join("package.json")).await else {
+        return vec![];
+    };
+    serde_json::from_slice::<Value>(&text)
+        .ok()
+        .as_ref()
+        .and_then(|package| package.get("scripts"))
+        .and_then(Value::as_object)
+        .map(|scripts| scripts.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Package manager of `workspace`, npm unless the lock file of another one exists
+pub fn package_manager(workspace: &Path) -> &'static str {
+    LOCK_FILES
+        .iter()
+        .find(|(lock, _)| workspace.join(lock).is_file())
+        .map_or("npm", |(_, manager)| manager)
+}
+
+/// Starts `script` in `workspace` with its output piped
+pub fn spawn(workspace: &Path, script: &str) -> std::io::Result<Child> {
+    Command::new(package_manager(workspace))
+        .arg("run")
+        .arg(script)
+        .current_dir(workspace)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+}
@@ -0,0 +1,185 @@
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+
+use crate::limits::{Connections, Limits};
+
+/// How long a share stays open unless `share_ttl` is set
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Request heads larger than this are refused
+const MAX_HEAD: usize = 16 * 1024;
+/// Visitors that didn't send a whole request head by then are dropped, so stalled ones don't hold on to a slot
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const COOKIE: &str = "__live_server_share";
+
+/// Public entrance of a share, every interface at a free port
+pub async fn bind() -> std::io::Result<TcpListener> {
+    TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).await
+}
+
+/// Admits visitors of `listener` to the server at `server` until dropped, which closes their connections.
+///
+/// Visitors open `/<token>/`, a random path segment, which sets a cookie and redirects to the page without
+/// the token, so the absolute urls of the site keep working. Connections without the token or cookie are refused,
+/// those over `limits` get a `503`. Each connection carries one request, so every request is checked.
+pub async fn serve(listener: TcpListener, server: SocketAddr, token: String, limits: Limits) {
+    let open = Connections::new(limits);
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((mut inbound, address)) => {
+                    let Some(connection) = open.admit(Some(address.ip())) else {
+                        let _ = inbound
+                            .write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                            .await;
+                        continue;
+                    };
+                    let token = token.clone();
+                    connections.spawn(async move {
+                        let _ = admit(inbound, server, token).await;
+                        drop(connection);
+                    });
+                }
+                Err(_) => return,
+            },
+            Some(_) = connections.join_next() => {}
+        }
+    }
+}
+
+async fn admit(mut inbound: TcpStream, server: SocketAddr, token: String) -> std::io::Result<()> {
+    let Some(head) = read_head(&mut inbound).await? else {
+        return Ok(());
+    };
+    let text = String::from_utf8_lossy(&head);
+    if let Some(location) = entrance(&text, &token) {
+        return enter(&mut inbound, &location, COOKIE, &token).await;
+    }
+    if cookie(&text, COOKIE) != Some(token.as_str()) {
+        return refuse(&mut inbound).await;
+    }
+    let mut outbound = TcpStream::connect(server).await?;
+    outbound.write_all(&closing(&head)).await?;
+    copy_bidirectional(&mut inbound, &mut outbound).await?;
+    Ok(())
+}
+
+/// Request head of a visitor with whatever of the body came along, `None` if it closed or sent too much.
+/// Fails with `TimedOut` if the head doesn't arrive within `READ_TIMEOUT`
+pub async fn read_head(stream: &mut TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+    let read = async {
+        let mut head = vec![];
+        let mut buffer = [0; 4096];
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 || head.len() + read > MAX_HEAD {
+                return Ok(None);
+            }
+            head.extend_from_slice(&buffer[..read]);
+        }
+        Ok(Some(head))
+    };
+    match tokio::time::timeout(READ_TIMEOUT, read).await {
+        Ok(head) => head,
+        Err(_) => Err(ErrorKind::TimedOut.into()),
+    }
+}
+
+/// `head` asking the server to close after its response, so a visitor can't send further requests
+/// past the token check on the same connection. Upgrades are left alone, they don't carry requests
+fn closing(head: &[u8]) -> Vec<u8> {
+    let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return head.to_vec();
+    };
+    let text = String::from_utf8_lossy(&head[..end]);
+    let header = |line: &str| {
+        line.split_once(':')
+            .map(|(name, _)| name.trim().to_string())
+    };
+    if text
+        .lines()
+        .filter_map(header)
+        .any(|name| name.eq_ignore_ascii_case("upgrade"))
+    {
+        return head.to_vec();
+    }
+    let mut closing = String::new();
+    for (index, line) in text.split("\r\n").enumerate() {
+        let keeps = index == 0
+            || header(line).is_none_or(|name| {
+                !name.eq_ignore_ascii_case("connection") && !name.eq_ignore_ascii_case("keep-alive")
+            });
+        if keeps {
+            closing.push_str(line);
+            closing.push_str("\r\n");
+        }
+    }
+    closing.push_str("Connection: close\r\n\r\n");
+    let mut closing = closing.into_bytes();
+    closing.extend_from_slice(&head[end + 4..]);
+    closing
+}
+
+/// Where a request of `/<segment>/rest` is redirected to, `None` if `head` requests another path
+pub fn entrance(head: &str, segment: &str) -> Option<String> {
+    let target = head.lines().next()?.split_whitespace().nth(1)?;
+    let rest = target
+        .strip_prefix('/')?
+        .strip_prefix(segment)
+        .filter(|rest| rest.is_empty() || rest.starts_with(['/', '?']))?;
+    Some(match rest.starts_with('/') {
+        true => rest.to_string(),
+        false => format!("/{}", rest),
+    })
+}
+
+/// Value of the cookie `name` sent in `head`
+pub fn cookie<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(header, _)| header.eq_ignore_ascii_case("cookie"))
+        .flat_map(|(_, value)| value.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(name)?.strip_prefix('='))
+}
+
+/// Redirects to `location`, remembering the visitor with the cookie `name`
+pub async fn enter(
+    stream: &mut TcpStream,
+    location: &str,
+    name: &str,
+    value: &str,
+) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 302 Found\r\nLocation: {}\r\nSet-Cookie: {}={}; Path=/; HttpOnly; SameSite=Lax\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        location, name, value
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+pub async fn refuse(stream: &mut TcpStream) -> std::io::Result<()> {
+    stream
+        .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_close() {
+        let head = b"GET / HTTP/1.1\r\nHost: a\r\nConnection: keep-alive\r\nKeep-Alive: timeout=5\r\n\r\nbody";
+        assert_eq!(
+            closing(head),
+            b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\nbody"
+        );
+        let upgrade = b"GET /ws HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n";
+        assert_eq!(closing(upgrade), upgrade);
+    }
+}
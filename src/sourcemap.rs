@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+
+use dashmap::DashMap;
+
+use crate::overlay::Overlay;
+
+/// Source maps of transformed responses, served from memory at `<file>.map`.
+///
+/// The serving layer does not expose response headers, so maps are linked with a
+/// `sourceMappingURL` comment instead of a `SourceMap` header.
+#[derive(Default)]
+pub struct SourceMaps {
+    maps: DashMap<PathBuf, String>,
+}
+
+impl SourceMaps {
+    /// Map requested at `path`, if a transformation generated one
+    pub fn get(&self, path: &Path) -> Option<Vec<u8>> {
+        self.maps.get(path).map(|map| map.as_bytes().to_vec())
+    }
+
+    /// Links the map of a served script or stylesheet, generated or lying next to it on disk
+    pub async fn link(&self, overlay: &Overlay, path: &Path, content: Vec<u8>) -> Vec<u8> {
+        let map = map_path(path);
+        let name = map
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let Some(comment) = link_comment(path, &name) else {
+            return content;
+        };
+        if has_link(&content) || !self.maps.contains_key(&map) && !overlay.exists(&map).await {
+            return content;
+        }
+        let mut content = content;
+        content.extend_from_slice(comment.as_bytes());
+        content
+    }
+}
+
+fn map_path(file: &Path) -> PathBuf {
+    let mut path = file.to_path_buf().into_os_string();
+    path.push(".map");
+    path.into()
+}
+
+fn link_comment(path: &Path, map: &str) -> Option<String> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("js" | "mjs") => Some(format!("\n//# sourceMappingURL={}\n", map)),
+        Some("css") => Some(format!("\n/*# sourceMappingURL={} */\n", map)),
+        _ => None,
+    }
+}
+
+fn has_link(content: &[u8]) -> bool {
+    const NEEDLE: &[u8] = b"sourceMappingURL=";
+    let tail = &content[content.len().saturating_sub(512)..];
+    tail.windows(NEEDLE.len()).any(|w| w == NEEDLE)
+}
@@ -0,0 +1,89 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::task::{JoinError, JoinHandle};
+
+const FIRST_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+/// A task running this long counts as healthy, its next crash restarts it immediately
+const HEALTHY: Duration = Duration::from_secs(30);
+
+/// Restart delays of a failing task, none after a healthy run, then doubling from one second up to a minute
+pub struct Backoff {
+    delay: Duration,
+    failures: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            delay: FIRST_DELAY,
+            failures: 0,
+        }
+    }
+}
+
+impl Backoff {
+    /// Records a failure of a task that ran for `ran`, returns how long to wait before restarting it
+    pub fn failed(&mut self, ran: Duration) -> Duration {
+        if ran >= HEALTHY {
+            *self = Self::default();
+            self.failures = 1;
+            return Duration::ZERO;
+        }
+        self.failures += 1;
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(MAX_DELAY);
+        delay
+    }
+
+    /// Failures in a row, without a healthy run in between
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+/// Runs `task` on its own, so a panic is reported as an error instead of taking the supervisor down.
+///
+/// The task is aborted if the supervisor is.
+pub async fn run<T: Send + 'static>(
+    task: impl Future<Output = T> + Send + 'static,
+) -> Result<T, JoinError> {
+    let mut guard = AbortOnDrop(tokio::spawn(task));
+    (&mut guard.0).await
+}
+
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_the_delay_up_to_a_minute() {
+        let mut backoff = Backoff::default();
+        let delays: Vec<_> = (0..8).map(|_| backoff.failed(Duration::ZERO)).collect();
+        assert_eq!(delays[0], FIRST_DELAY);
+        assert_eq!(delays[1], Duration::from_secs(2));
+        assert_eq!(delays[7], MAX_DELAY);
+        assert_eq!(backoff.failures(), 8);
+    }
+
+    #[test]
+    fn restarts_immediately_after_a_healthy_run() {
+        let mut backoff = Backoff::default();
+        for _ in 0..4 {
+            backoff.failed(Duration::ZERO);
+        }
+        assert_eq!(backoff.failed(HEALTHY), Duration::ZERO);
+        assert_eq!(backoff.failures(), 1);
+        assert_eq!(backoff.failed(Duration::ZERO), FIRST_DELAY);
+        assert_eq!(backoff.failed(Duration::ZERO), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use tokio::process::{Child, Command};
+
+/// Configuration files whose presence enables tailwind for a workspace
+const CONFIG_FILES: [&str; 4] = [
+    "tailwind.config.js",
+    "tailwind.config.cjs",
+    "tailwind.config.mjs",
+    "tailwind.config.ts",
+];
+
+/// Tailwind CLI in watch mode, writing the stylesheet the pages link, e.g. `npx tailwindcss`.
+///
+/// The server watches the output like any other stylesheet, so rebuilds are swapped in without a reload.
+pub struct Tailwind {
+    command: Vec<String>,
+    input: Option<PathBuf>,
+    output: PathBuf,
+}
+
+impl Tailwind {
+    /// `None` if the command is empty
+    pub fn new(command: Vec<String>, input: Option<PathBuf>, output: PathBuf) -> Option<Self> {
+        if command.is_empty() {
+            return None;
+        }
+        Some(Self {
+            command,
+            input,
+            output,
+        })
+    }
+
+    /// Whether `workspace` has a tailwind configuration
+    pub fn detect(workspace: &Path) -> bool {
+        CONFIG_FILES
+            .iter()
+            .any(|file| workspace.join(file).is_file())
+    }
+
+    pub fn name(&self) -> String {
+        self.command.join(" ")
+    }
+
+    /// Starts the CLI in `workspace` with its stderr piped, the watcher keeps running as long as its stdin is open
+    pub fn spawn(&self, workspace: &Path) -> std::io::Result<Child> {
+        let mut command = Command::new(&self.command[0]);
+        command.args(&self.command[1..]);
+        if let Some(input) = &self.input {
+            command.arg("--input").arg(input);
+        }
+        command
+            .arg("--output")
+            .arg(&self.output)
+            .arg("--watch")
+            .current_dir(workspace)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+    }
+}
@@ -0,0 +1,323 @@
+use std::path::{Path, PathBuf};
+
+use handlebars::Handlebars;
+use serde_json::Value;
+use tera::Tera;
+
+use crate::env;
+use crate::overlay::Overlay;
+
+/// Renders `.hbs` pages with handlebars and `.tera` pages with tera before they get served.
+///
+/// Every file of the partials directory in the language of the page can be included by its path
+/// below it, with or without the extension.
+pub struct Templates {
+    partials: PathBuf,
+    data: PathBuf,
+    /// Expose the environment as `env` in the context
+    env: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    Handlebars,
+    Tera,
+}
+
+pub struct Rendered {
+    pub html: String,
+    /// Template, partials and data file the page was rendered from
+    pub includes: Vec<PathBuf>,
+}
+
+impl Syntax {
+    fn extension(self) -> &'static str {
+        match self {
+            Syntax::Handlebars => "hbs",
+            Syntax::Tera => "tera",
+        }
+    }
+}
+
+impl Templates {
+    pub fn new(root: &Path, partials: Option<&Path>, data: Option<&Path>, env: bool) -> Self {
+        Self {
+            partials: root.join(partials.unwrap_or(Path::new("partials"))),
+            data: root.join(data.unwrap_or(Path::new("data.json"))),
+            env,
+        }
+    }
+
+    /// Template rendering to `path`, e.g. `page.html.hbs` or `page.tera` for `page.html`
+    pub async fn find(&self, overlay: &Overlay, path: &Path) -> Option<(PathBuf, Syntax)> {
+        if !matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("html" | "htm")
+        ) {
+            return None;
+        }
+        for syntax in [Syntax::Handlebars, Syntax::Tera] {
+            let mut appended = path.to_path_buf().into_os_string();
+            appended.push(format!(".{}", syntax.extension()));
+            for candidate in [
+                PathBuf::from(appended),
+                path.with_extension(syntax.extension()),
+            ] {
+                if overlay.exists(&candidate).await {
+                    return Some((candidate, syntax));
+                }
+            }
+        }
+        None
+    }
+
+    /// Page a template file renders to, `None` for files that aren't templates
+    pub fn page_for(&self, file: &Path) -> Option<PathBuf> {
+        let ext = file.extension().and_then(|ext| ext.to_str())?;
+        if !matches!(ext, "hbs" | "tera") {
+            return None;
+        }
+        let page = file.with_extension("");
+        Some(match page.extension().and_then(|ext| ext.to_str()) {
+            Some("html" | "htm") => page,
+            _ => page.with_extension("html"),
+        })
+    }
+
+    pub fn is_partial(&self, file: &Path) -> bool {
+        file.starts_with(&self.partials)
+    }
+
+    /// Renders a template, errors are rendered into the page so they show up in the preview
+    pub async fn render(&self, overlay: &Overlay, template: &Path, syntax: Syntax) -> Rendered {
+        let mut includes = vec![template.to_path_buf(), self.data.clone()];
+        let html = match self
+            .try_render(overlay, template, syntax, &mut includes)
+            .await
+        {
+            Ok(html) => html,
+            Err(e) => format!(
+                "<!DOCTYPE html><html><body><h1>Template error</h1><pre>{}</pre></body></html>",
+                escape(&e)
+            ),
+        };
+        Rendered { html, includes }
+    }
+
+    async fn try_render(
+        &self,
+        overlay: &Overlay,
+        template: &Path,
+        syntax: Syntax,
+        includes: &mut Vec<PathBuf>,
+    ) -> Result<String, String> {
+        let mut context = match overlay.read(&self.data).await {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| format!("{}: {}", self.data.display(), e))?,
+            Err(_) => Value::Null,
+        };
+        if self.env {
+            if context.is_null() {
+                context = Value::Object(Default::default());
+            }
+            if let Value::Object(map) = &mut context {
+                map.insert("env".to_string(), env::context());
+            }
+        }
+        let source = self.read(overlay, template).await?;
+        let mut partials = vec![];
+        for path in self.partial_files(syntax).await {
+            let name = path.strip_prefix(&self.partials).unwrap_or(&path);
+            let name = name.to_string_lossy().replace('\\', "/");
+            partials.push((name, self.read(overlay, &path).await?));
+            includes.push(path);
+        }
+        match syntax {
+            Syntax::Handlebars => render_handlebars(&source, &partials, &context),
+            Syntax::Tera => render_tera(&source, &partials, &context),
+        }
+    }
+
+    async fn read(&self, overlay: &Overlay, path: &Path) -> Result<String, String> {
+        let source = overlay
+            .read(path)
+            .await
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(String::from_utf8_lossy(&source).into_owned())
+    }
+
+    /// Files of the partials directory in the language of `syntax`, nested directories included
+    async fn partial_files(&self, syntax: Syntax) -> Vec<PathBuf> {
+        let mut files = vec![];
+        let mut dirs = vec![self.partials.clone()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+                continue;
+            };
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let path = entry.path();
+                if entry.file_type().await.is_ok_and(|kind| kind.is_dir()) {
+                    dirs.push(path);
+                } else if path
+                    .extension()
+                    .is_some_and(|ext| ext == syntax.extension())
+                {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+}
+
+/// Renders `source` with handlebars, `partials` are named by their path below the partials directory
+fn render_handlebars(
+    source: &str,
+    partials: &[(String, String)],
+    context: &Value,
+) -> Result<String, String> {
+    let mut handlebars = Handlebars::new();
+    for (name, partial) in partials {
+        for name in names(name, Syntax::Handlebars) {
+            handlebars
+                .register_partial(name, partial)
+                .map_err(|e| format!("{}: {}", name, e))?;
+        }
+    }
+    handlebars
+        .render_template(source, context)
+        .map_err(|e| e.to_string())
+}
+
+/// Renders `source` with tera, escaping every variable that isn't marked `safe`
+fn render_tera(
+    source: &str,
+    partials: &[(String, String)],
+    context: &Value,
+) -> Result<String, String> {
+    const PAGE: &str = "__page";
+    let mut tera = Tera::default();
+    tera.autoescape_on(vec![""]);
+    let mut templates = vec![(PAGE, source)];
+    for (name, partial) in partials {
+        templates.extend(names(name, Syntax::Tera).map(|name| (name, partial.as_str())));
+    }
+    tera.add_raw_templates(templates).map_err(|e| chain(&e))?;
+    let context = match context {
+        Value::Null => tera::Context::new(),
+        context => tera::Context::from_value(context.clone()).map_err(|e| chain(&e))?,
+    };
+    tera.render(PAGE, &context).map_err(|e| chain(&e))
+}
+
+/// Names a partial is included by, with and without the extension
+fn names(name: &str, syntax: Syntax) -> impl Iterator<Item = &str> {
+    let bare = name
+        .strip_suffix(syntax.extension())
+        .and_then(|bare| bare.strip_suffix('.'));
+    std::iter::once(name).chain(bare)
+}
+
+/// An error with its causes, tera keeps the line of a syntax error in the cause
+fn chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn partials(partials: &[(&str, &str)]) -> Vec<(String, String)> {
+        partials
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn renders_handlebars() {
+        let source = "<h1>{{title}}</h1>{{{raw}}}{{! note }}\
+                      {{#each items}}<li>{{@index}}:{{name}}</li>{{/each}}\
+                      {{#if missing}}yes{{else}}no{{/if}}{{> footer}}";
+        let context = json!({
+            "title": "a < b",
+            "raw": "<b>bold</b>",
+            "items": [{ "name": "one" }, { "name": "two" }],
+            "year": 2024,
+        });
+        assert_eq!(
+            render_handlebars(
+                source,
+                &partials(&[("footer.hbs", "<p>{{year}}</p>")]),
+                &context
+            )
+            .unwrap(),
+            "<h1>a &lt; b</h1><b>bold</b><li>0:one</li><li>1:two</li>no<p>2024</p>"
+        );
+    }
+
+    #[test]
+    fn renders_tera() {
+        let source = "{# note #}{% for user in users %}{{ loop.index }}.{{ user.name }}\
+                      {% if loop.last %}!{% endif %} {% endfor %}{{ html | safe }}{{ html }}\
+                      {% include \"footer\" %}";
+        let context = json!({
+            "users": [{ "name": "ada" }, { "name": "alan" }],
+            "html": "<br>",
+        });
+        assert_eq!(
+            render_tera(
+                source,
+                &partials(&[("footer.tera", "{{ users.1.name }}")]),
+                &context
+            )
+            .unwrap(),
+            "1.ada 2.alan! <br>&lt;br&gt;alan"
+        );
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        assert!(render_handlebars("{{#if a}}open", &[], &json!({})).is_err());
+        assert!(render_tera("{% endfor %}", &[], &Value::Null).is_err());
+    }
+
+    #[test]
+    fn maps_templates_to_pages() {
+        let templates = Templates::new(Path::new("/site"), None, None, false);
+        assert_eq!(
+            templates.page_for(Path::new("/site/index.html.hbs")),
+            Some(PathBuf::from("/site/index.html"))
+        );
+        assert_eq!(
+            templates.page_for(Path::new("/site/about.tera")),
+            Some(PathBuf::from("/site/about.html"))
+        );
+        assert_eq!(templates.page_for(Path::new("/site/index.html")), None);
+        assert!(templates.is_partial(Path::new("/site/partials/nav.hbs")));
+    }
+}
@@ -0,0 +1,320 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use tokio::io::{copy_bidirectional, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+
+use crate::random;
+use crate::share;
+
+/// Cookie naming the tunnel a visitor of the relay entered
+const COOKIE: &str = "__live_server_tunnel";
+
+/// Path segment the entrances of the tunnels are below, which keeps them apart from the paths of the sites
+const ENTRANCES: &str = "__tunnel";
+
+/// How long the relay waits for a workspace to open the connection of a visitor
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Relay forwarding visitors to workspace servers that connected out to it, for previews across networks.
+///
+/// Workspaces keep a control connection open with `HELLO <name> <secret>`, answered with `OK <url>`.
+/// For every visitor of `/__tunnel/<name>/` the relay sends `CONNECT <id>` over it and the workspace opens
+/// a further connection starting with `DATA <id> <secret>`, which is piped to the visitor. Visitors get a cookie
+/// and are redirected to the page without the name, like visitors of a share.
+pub struct TunnelRelay {
+    secret: Option<String>,
+    /// Public base url announced to the workspaces, like `http://relay.example.com:8000`
+    url: Option<String>,
+    tunnels: DashMap<String, mpsc::Sender<u64>>,
+    pending: DashMap<u64, oneshot::Sender<BufReader<TcpStream>>>,
+}
+
+impl TunnelRelay {
+    pub fn new(secret: Option<String>, url: Option<String>) -> Arc<Self> {
+        Arc::new(Self {
+            secret,
+            url,
+            tunnels: Default::default(),
+            pending: Default::default(),
+        })
+    }
+
+    /// Accepts workspaces on `control` and visitors on `public` until either listener fails
+    pub async fn run(self: Arc<Self>, control: TcpListener, public: TcpListener) {
+        let workspaces = {
+            let relay = self.clone();
+            async move {
+                while let Ok((stream, _)) = control.accept().await {
+                    tokio::spawn(relay.clone().workspace(stream));
+                }
+            }
+        };
+        let visitors = async move {
+            while let Ok((stream, _)) = public.accept().await {
+                let relay = self.clone();
+                tokio::spawn(async move {
+                    let _ = relay.visitor(stream).await;
+                });
+            }
+        };
+        tokio::join!(workspaces, visitors);
+    }
+
+    async fn workspace(self: Arc<Self>, stream: TcpStream) {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.is_err() {
+            return;
+        }
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("HELLO"), Some(name), secret) => {
+                if !self.admits(secret) {
+                    let _ = reader.get_mut().write_all(b"ERR wrong secret\n").await;
+                    return;
+                }
+                self.control(name.to_string(), reader).await;
+            }
+            // without the secret anyone reaching the control port could take over visitors by their id
+            (Some("DATA"), Some(id), secret) if self.admits(secret) => {
+                let pending = id.parse().ok().and_then(|id| self.pending.remove(&id));
+                if let Some((_, waiting)) = pending {
+                    let _ = waiting.send(reader);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether `secret` is the one the relay was started with, any is if it has none
+    fn admits(&self, secret: Option<&str>) -> bool {
+        self.secret
+            .as_deref()
+            .is_none_or(|expected| secret == Some(expected))
+    }
+
+    /// Holds the control connection of the tunnel `name` open, asking for connections as visitors arrive
+    async fn control(&self, name: String, mut reader: BufReader<TcpStream>) {
+        if self
+            .tunnels
+            .get(&name)
+            .is_some_and(|tunnel| !tunnel.is_closed())
+        {
+            let _ = reader.get_mut().write_all(b"ERR name in use\n").await;
+            return;
+        }
+        let (requests, mut received) = mpsc::channel(64);
+        self.tunnels.insert(name.clone(), requests);
+        let url = format!(
+            "{}/{}/{}/",
+            self.url.as_deref().unwrap_or_default(),
+            ENTRANCES,
+            name
+        );
+        if reader
+            .get_mut()
+            .write_all(format!("OK {}\n", url).as_bytes())
+            .await
+            .is_ok()
+        {
+            let mut line = String::new();
+            loop {
+                tokio::select! {
+                    Some(id) = received.recv() => {
+                        let message = format!("CONNECT {}\n", id);
+                        if reader.get_mut().write_all(message.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                    // workspaces never send on this connection, so a read only returns once it closed
+                    read = reader.read_line(&mut line) => match read {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => line.clear(),
+                    },
+                }
+            }
+        }
+        self.tunnels.remove(&name);
+    }
+
+    /// Where the request `head` of a visitor goes. Entrances are below their own segment,
+    /// so a tunnel named like a path of another site can't take its visitors
+    fn visit(&self, head: &str) -> Visit {
+        let entered = self.tunnels.iter().find_map(|tunnel| {
+            let segment = format!("{}/{}", ENTRANCES, tunnel.key());
+            share::entrance(head, &segment).map(|location| (tunnel.key().clone(), location))
+        });
+        if let Some((name, location)) = entered {
+            return Visit::Enter(name, location);
+        }
+        share::cookie(head, COOKIE)
+            .and_then(|name| self.tunnels.get(name))
+            .map_or(Visit::Refused, |tunnel| {
+                Visit::Tunnel(tunnel.value().clone())
+            })
+    }
+
+    async fn visitor(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let Some(head) = share::read_head(&mut stream).await? else {
+            return Ok(());
+        };
+        let tunnel = match self.visit(&String::from_utf8_lossy(&head)) {
+            Visit::Enter(name, location) => {
+                return share::enter(&mut stream, &location, COOKIE, &name).await
+            }
+            Visit::Tunnel(tunnel) => tunnel,
+            Visit::Refused => return share::refuse(&mut stream).await,
+        };
+        let id = u64::from_le_bytes(random::bytes()?);
+        let (waiting, connected) = oneshot::channel();
+        self.pending.insert(id, waiting);
+        let connected = match tunnel.send(id).await {
+            Ok(()) => tokio::time::timeout(CONNECT_TIMEOUT, connected).await.ok(),
+            Err(_) => None,
+        };
+        self.pending.remove(&id);
+        let Some(Ok(mut workspace)) = connected else {
+            return stream
+                .write_all(
+                    b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .await;
+        };
+        workspace.get_mut().write_all(&head).await?;
+        copy_bidirectional(&mut stream, &mut workspace).await?;
+        Ok(())
+    }
+}
+
+/// Where a visitor of the relay goes
+enum Visit {
+    /// Redirected to the location, with the cookie of the tunnel name
+    Enter(String, String),
+    Tunnel(mpsc::Sender<u64>),
+    Refused,
+}
+
+/// Tunnel of a workspace server through a relay at `relay`, a `host:port` of its control listener
+pub struct Tunnel {
+    pub relay: String,
+    pub name: String,
+    pub secret: Option<String>,
+}
+
+/// Tunnel the relay accepted, reachable at `url`
+pub struct Registered {
+    pub url: String,
+    relay: String,
+    secret: Option<String>,
+    control: BufReader<TcpStream>,
+}
+
+impl Tunnel {
+    /// Opens the control connection and registers the tunnel
+    pub async fn open(&self) -> std::io::Result<Registered> {
+        let mut control = BufReader::new(TcpStream::connect(&self.relay).await?);
+        let hello = match &self.secret {
+            Some(secret) => format!("HELLO {} {}\n", self.name, secret),
+            None => format!("HELLO {}\n", self.name),
+        };
+        control.get_mut().write_all(hello.as_bytes()).await?;
+        let mut line = String::new();
+        control.read_line(&mut line).await?;
+        match line.trim_end().split_once(' ') {
+            Some(("OK", url)) => Ok(Registered {
+                url: url.to_string(),
+                relay: self.relay.clone(),
+                secret: self.secret.clone(),
+                control,
+            }),
+            _ => {
+                let message = line.trim_end().strip_prefix("ERR ").unwrap_or("closed");
+                Err(std::io::Error::other(format!("relay refused: {}", message)))
+            }
+        }
+    }
+}
+
+impl Registered {
+    /// Connects visitors to the server at `server` until the relay closes the tunnel
+    pub async fn forward(self, server: SocketAddr) -> std::io::Result<()> {
+        // dropped with the tunnel, which closes the connections of its visitors
+        let mut connections = JoinSet::new();
+        let mut lines = self.control.lines();
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => line,
+                    None => return Ok(()),
+                },
+                Some(_) = connections.join_next() => continue,
+            };
+            let Some(id) = line.trim_end().strip_prefix("CONNECT ") else {
+                continue;
+            };
+            let relay = self.relay.clone();
+            let data = match &self.secret {
+                Some(secret) => format!("DATA {} {}\n", id, secret),
+                None => format!("DATA {}\n", id),
+            };
+            connections.spawn(async move {
+                let (Ok(mut visitor), Ok(mut server)) = (
+                    TcpStream::connect(&relay).await,
+                    TcpStream::connect(server).await,
+                ) else {
+                    return;
+                };
+                if visitor.write_all(data.as_bytes()).await.is_ok() {
+                    let _ = copy_bidirectional(&mut visitor, &mut server).await;
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relay(names: &[&str]) -> (Arc<TunnelRelay>, Vec<mpsc::Receiver<u64>>) {
+        let relay = TunnelRelay::new(None, None);
+        let receivers = names
+            .iter()
+            .map(|name| {
+                let (requests, received) = mpsc::channel(1);
+                relay.tunnels.insert(name.to_string(), requests);
+                received
+            })
+            .collect();
+        (relay, receivers)
+    }
+
+    #[test]
+    fn keeps_visitors_in_their_tunnel() {
+        let (relay, _receivers) = relay(&["site", "assets"]);
+        let site = relay.tunnels.get("site").unwrap().clone();
+        // a path of the site named like the other tunnel
+        let head = "GET /assets/app.js HTTP/1.1\r\nCookie: __live_server_tunnel=site\r\n\r\n";
+        match relay.visit(head) {
+            Visit::Tunnel(tunnel) => assert!(tunnel.same_channel(&site)),
+            _ => panic!("the visitor left its tunnel"),
+        }
+        let head =
+            "GET /__tunnel/assets/app.js HTTP/1.1\r\nCookie: __live_server_tunnel=site\r\n\r\n";
+        match relay.visit(head) {
+            Visit::Enter(name, location) => {
+                assert_eq!((name.as_str(), location.as_str()), ("assets", "/app.js"))
+            }
+            _ => panic!("the entrance wasn't taken"),
+        }
+        assert!(matches!(
+            relay.visit("GET /assets/ HTTP/1.1\r\n\r\n"),
+            Visit::Refused
+        ));
+    }
+}
@@ -0,0 +1,182 @@
+use std::path::{Component, Path, PathBuf};
+
+use tower_lsp::lsp_types::Url;
+
+/// Converts a `file:` uri into a path on the local file system.
+///
+/// Handles percent encoding, drive letters (`file:///C:/…`) and UNC hosts (`file://server/share/…`).
+/// Returns `None` for every other scheme.
+pub fn uri_to_path(uri: &Url) -> Option<PathBuf> {
+    if uri.scheme() != "file" {
+        return None;
+    }
+    let path = uri.to_file_path().ok().or_else(|| fallback(uri))?;
+    Some(normalize(path))
+}
+
+/// `Url::to_file_path` only understands the conventions of the host platform,
+/// so uris created on another platform are decoded by hand.
+fn fallback(uri: &Url) -> Option<PathBuf> {
+    let decoded = percent_decode(uri.path())?;
+    let path = match uri.host_str() {
+        Some(host) if !host.is_empty() && host != "localhost" => format!("//{host}{decoded}"),
+        _ => match decoded.as_bytes() {
+            [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => decoded[1..].to_string(),
+            _ => decoded,
+        },
+    };
+    Some(PathBuf::from(path))
+}
+
+/// Decodes `%XX` escapes, `None` if they aren't valid utf-8
+pub fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Uppercases drive letters, clients are inconsistent about `c:` vs `C:`.
+fn normalize(path: PathBuf) -> PathBuf {
+    let s = path.to_string_lossy();
+    match s.as_bytes() {
+        [drive, b':', ..] if drive.is_ascii_lowercase() => {
+            PathBuf::from(format!("{}{}", drive.to_ascii_uppercase() as char, &s[1..]))
+        }
+        _ => path,
+    }
+}
+
+/// Joins the components of a relative path with `/` for use in an url.
+pub fn url_path(rel: &Path) -> String {
+    rel.components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Folder virtual documents (`untitled:` and other non file schemes) get mounted at in every workspace
+pub const VIRTUAL_MOUNT: &str = "__untitled";
+
+/// Route of a virtual document relative to the workspace root, e.g. `__untitled/Untitled-1.html`
+pub fn virtual_route(uri: &Url, language_id: &str) -> PathBuf {
+    let name = uri.path().rsplit(['/', '\\']).next().unwrap_or_default();
+    let mut name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    if uri.scheme() != "untitled" {
+        name = format!("{}-{}", uri.scheme(), name);
+    }
+    if Path::new(&name).extension().is_none() {
+        name = format!("{}.{}", name, extension_for(language_id));
+    }
+    Path::new(VIRTUAL_MOUNT).join(name)
+}
+
+fn extension_for(language_id: &str) -> &str {
+    match language_id {
+        "html" => "html",
+        "css" => "css",
+        "javascript" => "js",
+        "typescript" => "ts",
+        "json" => "json",
+        "markdown" => "md",
+        "xml" => "xml",
+        "svg" => "svg",
+        _ => "txt",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(uri: &str) -> Url {
+        Url::parse(uri).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn round_trips_unix_paths() {
+        for path in ["/home/me/My Site/index.html", "/home/me/café/über.html"] {
+            let uri = Url::from_file_path(path).unwrap();
+            assert_eq!(uri_to_path(&uri), Some(PathBuf::from(path)));
+        }
+        assert_eq!(
+            uri_to_path(&parse("file:///home/me/My%20Site/%C3%BCber.html")),
+            Some(PathBuf::from("/home/me/My Site/über.html"))
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn round_trips_windows_paths() {
+        for path in [
+            r"C:\Users\me\My Site\über.html",
+            r"\\server\share\site\index.html",
+        ] {
+            let uri = Url::from_file_path(path).unwrap();
+            assert_eq!(uri_to_path(&uri), Some(PathBuf::from(path)));
+        }
+        assert_eq!(
+            uri_to_path(&parse("file:///c:/site/My%20Page.html")),
+            Some(PathBuf::from(r"C:\site\My Page.html"))
+        );
+    }
+
+    #[test]
+    fn decodes_uris_of_other_platforms() {
+        let decode = |uri: &str| normalize(fallback(&parse(uri)).unwrap());
+        assert_eq!(
+            decode("file:///c%3A/Users/me/My%20Site/%C3%BCber.html"),
+            PathBuf::from("C:/Users/me/My Site/über.html")
+        );
+        assert_eq!(
+            decode("file://server/share/My%20Site/index.html"),
+            PathBuf::from("//server/share/My Site/index.html")
+        );
+        assert_eq!(
+            decode("file://localhost/srv/site%20one/index.html"),
+            PathBuf::from("/srv/site one/index.html")
+        );
+    }
+
+    #[test]
+    fn ignores_other_schemes() {
+        assert_eq!(uri_to_path(&parse("untitled:Untitled-1")), None);
+        assert_eq!(
+            virtual_route(&parse("untitled:Untitled-1"), "html"),
+            Path::new(VIRTUAL_MOUNT).join("Untitled-1.html")
+        );
+        assert_eq!(
+            virtual_route(&parse("vscode-notebook-cell:/a/b%20c.ipynb"), "markdown"),
+            Path::new(VIRTUAL_MOUNT).join("vscode-notebook-cell-b_20c.ipynb")
+        );
+    }
+
+    #[test]
+    fn joins_url_paths_with_slashes() {
+        assert_eq!(
+            url_path(Path::new("docs/guide/index.html")),
+            "docs/guide/index.html"
+        );
+        assert_eq!(url_path(Path::new("./docs/index.html")), "docs/index.html");
+    }
+}
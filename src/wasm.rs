@@ -0,0 +1,26 @@
+use std::path::Path;
+
+/// Build run after saving a Rust source of a wasm project, unless `wasm_command` is set
+pub const BUILD_COMMAND: [&str; 4] = ["wasm-pack", "build", "--target", "web"];
+
+/// Whether `workspace` is a Rust project built for the browser: a `cdylib` crate,
+/// or one whose cargo configuration targets wasm
+pub fn detect(workspace: &Path) -> bool {
+    let Ok(manifest) = std::fs::read_to_string(workspace.join("Cargo.toml")) else {
+        return false;
+    };
+    let cdylib = manifest
+        .lines()
+        .any(|line| line.trim_start().starts_with("crate-type") && line.contains("cdylib"));
+    cdylib
+        || ["config.toml", "config"].iter().any(|file| {
+            std::fs::read_to_string(workspace.join(".cargo").join(file))
+                .is_ok_and(|config| config.contains("wasm32"))
+        })
+}
+
+/// Whether saving `file` has to rebuild the wasm module
+pub fn is_source(file: &Path) -> bool {
+    file.extension().and_then(|ext| ext.to_str()) == Some("rs")
+        || file.file_name().and_then(|name| name.to_str()) == Some("Cargo.toml")
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often directories outside the workspace are scanned
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Detects changes of files and directories the editor doesn't report file events for by comparing modification times
+pub struct Poller {
+    paths: Vec<PathBuf>,
+    seen: HashMap<PathBuf, SystemTime>,
+}
+
+impl Poller {
+    pub async fn new(paths: Vec<PathBuf>) -> Self {
+        let mut poller = Self {
+            paths,
+            seen: HashMap::new(),
+        };
+        poller.seen = poller.scan().await;
+        poller
+    }
+
+    /// Files created, modified or deleted since the last call
+    pub async fn changes(&mut self) -> Vec<PathBuf> {
+        let current = self.scan().await;
+        let mut changed: Vec<PathBuf> = current
+            .iter()
+            .filter(|(path, modified)| self.seen.get(*path) != Some(modified))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(
+            self.seen
+                .keys()
+                .filter(|path| !current.contains_key(*path))
+                .cloned(),
+        );
+        self.seen = current;
+        changed
+    }
+
+    async fn scan(&self) -> HashMap<PathBuf, SystemTime> {
+        let paths = self.paths.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut files = HashMap::new();
+            for path in &paths {
+                scan(path, &mut files);
+            }
+            files
+        })
+        .await
+        .unwrap_or_default()
+    }
+}
+
+fn scan(path: &Path, files: &mut HashMap<PathBuf, SystemTime>) {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return;
+    };
+    if !metadata.is_dir() {
+        if let Ok(modified) = metadata.modified() {
+            files.insert(path.to_path_buf(), modified);
+        }
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        scan(&entry.path(), files);
+    }
+}
@@ -0,0 +1,413 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use live_server_lsp::{serve_on, Config, ConfigBuilder, Middleware};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::net::TcpStream;
+use tower_lsp::lsp_types::Url;
+
+async fn send(stream: &mut DuplexStream, message: Value) {
+    let body = message.to_string();
+    let frame = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    stream.write_all(frame.as_bytes()).await.unwrap();
+}
+
+/// Next message with an `id`, skipping the notifications logged in between
+async fn response(reader: &mut BufReader<DuplexStream>) -> Value {
+    loop {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await.unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).await.unwrap();
+        let message: Value = serde_json::from_slice(&body).unwrap();
+        if message.get("id").is_some() && message.get("method").is_none() {
+            return message;
+        }
+    }
+}
+
+#[tokio::test]
+async fn layers_initialization_options_over_the_config() {
+    let (mut requests, server_input) = tokio::io::duplex(64 * 1024);
+    let (server_output, client_output) = tokio::io::duplex(64 * 1024);
+    let mut reader = BufReader::new(client_output);
+    let config = Config::builder()
+        .lazy(true)
+        .start_port(8123)
+        .build()
+        .unwrap();
+    let client = async {
+        send(
+            &mut requests,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "capabilities": {}, "initializationOptions": { "start_port": 8124 } },
+            }),
+        )
+        .await;
+        let initialized = response(&mut reader).await;
+        assert!(initialized["result"]["capabilities"].is_object());
+
+        send(
+            &mut requests,
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "liveServer/status" }),
+        )
+        .await;
+        let status = response(&mut reader).await;
+        // `lazy` of the config survives options that don't set it
+        assert_eq!(status["result"]["eager"], json!(false));
+        assert_eq!(status["result"]["workspaces"], json!([]));
+    };
+    tokio::select! {
+        _ = serve_on(config, server_input, server_output) => panic!("the server stopped"),
+        _ = client => {}
+    }
+}
+
+#[test]
+fn rejects_options_of_the_wrong_type() {
+    assert!(Config::builder().option("lazy", "yes").build().is_err());
+}
+
+struct Banner;
+
+impl Middleware for Banner {
+    fn applies_to(&self, workspace: &Path) -> bool {
+        workspace.ends_with("site")
+    }
+
+    fn transform(&self, _path: &Path, mut body: Vec<u8>) -> Vec<u8> {
+        body.extend_from_slice(b"<!-- banner -->");
+        body
+    }
+}
+
+fn free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Workspace folder `site` with `files` in a temporary directory, which the test removes again
+fn workspace(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("live-server-{}-{}", test, std::process::id()));
+    let root = dir.join("site");
+    std::fs::create_dir_all(&root).unwrap();
+    for (name, text) in files {
+        std::fs::write(root.join(name), text).unwrap();
+    }
+    root
+}
+
+/// Serves `root` with `config`, which starts at `port`, and runs `browser` once the server accepts connections
+async fn visit<T>(root: &Path, config: Config, port: u16, browser: impl Future<Output = T>) -> T {
+    let (mut requests, server_input) = tokio::io::duplex(64 * 1024);
+    let (server_output, mut client_output) = tokio::io::duplex(64 * 1024);
+    let client = async {
+        let folder = json!({ "uri": Url::from_file_path(root).unwrap(), "name": "site" });
+        send(
+            &mut requests,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "capabilities": {}, "workspaceFolders": [folder] },
+            }),
+        )
+        .await;
+        send(
+            &mut requests,
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        )
+        .await;
+        let mut started = false;
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+                started = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(started, "the server didn't start");
+        browser.await
+    };
+    // the log messages of the server are read and dropped, so it never waits on a full pipe
+    let drain = async {
+        let mut sink = [0; 4096];
+        while client_output
+            .read(&mut sink)
+            .await
+            .is_ok_and(|read| read > 0)
+        {}
+    };
+    let visited = tokio::select! {
+        _ = serve_on(config, server_input, server_output) => panic!("the server stopped"),
+        _ = drain => panic!("the server closed its output"),
+        visited = client => visited,
+    };
+    std::fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    visited
+}
+
+/// Head up to the empty line of the next message of a connection
+async fn read_head(stream: &mut BufReader<TcpStream>) -> String {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        assert!(stream.read_line(&mut line).await.unwrap() > 0, "{}", head);
+        if line.trim_end().is_empty() {
+            return head;
+        }
+        head.push_str(&line);
+    }
+}
+
+/// Value of the header `name` in `head`
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Next response of a connection that stays open, with its body of `Content-Length` bytes
+async fn read_response(stream: &mut BufReader<TcpStream>) -> (String, String) {
+    let head = read_head(stream).await;
+    let length = header(&head, "content-length").map_or(0, |length| length.parse().unwrap());
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await.unwrap();
+    (head, String::from_utf8(body).unwrap())
+}
+
+async fn connect(port: u16) -> BufReader<TcpStream> {
+    BufReader::new(TcpStream::connect(("127.0.0.1", port)).await.unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_pages_through_registered_middleware() {
+    let root = workspace("middleware", &[("page.html", "<p>hello</p>")]);
+    let port = free_port();
+    let config = Config::builder()
+        .start_port(port)
+        .middleware(Banner)
+        .build()
+        .unwrap();
+    let response = visit(&root, config, port, async {
+        let mut page = connect(port).await;
+        page.write_all(b"GET /page.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = vec![];
+        page.read_to_end(&mut response).await.unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    })
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(body.contains("<p>hello</p>"));
+    assert!(body.contains("<!-- banner -->"), "{}", body);
+}
+
+const ALPHABET: &str = "abcdefghijklmnopqrstuvwxyz";
+
+/// Config of a server on `port` whose gateway sends files from 16 bytes on itself
+fn streaming(port: u16) -> ConfigBuilder {
+    Config::builder()
+        .start_port(port)
+        .option("stream_threshold", 16)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_connections_alive_between_requests() {
+    let root = workspace("keep-alive", &[("letters.txt", ALPHABET)]);
+    let port = free_port();
+    let config = streaming(port).build().unwrap();
+    visit(&root, config, port, async {
+        let mut connection = connect(port).await;
+        for _ in 0..2 {
+            connection
+                .write_all(b"GET /letters.txt HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            let (head, body) = read_response(&mut connection).await;
+            assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+            assert_eq!(header(&head, "connection"), None);
+            assert_eq!(body, ALPHABET);
+        }
+        connection
+            .write_all(b"GET /letters.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let (head, body) = read_response(&mut connection).await;
+        assert_eq!(header(&head, "connection"), Some("close"));
+        assert_eq!(body, ALPHABET);
+        let mut rest = vec![];
+        connection.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_byte_ranges_of_streamed_files() {
+    let root = workspace("ranges", &[("letters.txt", ALPHABET)]);
+    let port = free_port();
+    let config = streaming(port).build().unwrap();
+    visit(&root, config, port, async {
+        let mut connection = connect(port).await;
+        let ranges = [
+            ("bytes=2-5", "cdef", "bytes 2-5/26"),
+            ("bytes=-3", "xyz", "bytes 23-25/26"),
+            ("bytes=20-", "uvwxyz", "bytes 20-25/26"),
+        ];
+        for (range, expected, content_range) in ranges {
+            let request = format!(
+                "GET /letters.txt HTTP/1.1\r\nHost: localhost\r\nRange: {}\r\n\r\n",
+                range
+            );
+            connection.write_all(request.as_bytes()).await.unwrap();
+            let (head, body) = read_response(&mut connection).await;
+            assert!(head.starts_with("HTTP/1.1 206"), "{}", head);
+            assert_eq!(header(&head, "content-range"), Some(content_range));
+            assert_eq!(body, expected);
+        }
+        connection
+            .write_all(b"GET /letters.txt HTTP/1.1\r\nHost: localhost\r\nRange: bytes=40-\r\n\r\n")
+            .await
+            .unwrap();
+        let (head, _) = read_response(&mut connection).await;
+        assert!(head.starts_with("HTTP/1.1 416"), "{}", head);
+        assert_eq!(header(&head, "content-range"), Some("bytes */26"));
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn decodes_chunked_bodies_of_proxied_requests() {
+    let root = workspace("chunked", &[("letters.txt", ALPHABET)]);
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    // echoes the body of one request back in chunks
+    let echo = tokio::spawn(async move {
+        let (stream, _) = upstream.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let head = read_head(&mut stream).await;
+        let length: usize = header(&head, "content-length").unwrap().parse().unwrap();
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.unwrap();
+        let body = String::from_utf8(body).unwrap();
+        let (first, second) = body.split_at(body.len() / 2);
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            first.len(),
+            first,
+            second.len(),
+            second
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        head
+    });
+    let port = free_port();
+    let upstream_url = format!("http://127.0.0.1:{}", upstream_port);
+    let config = streaming(port)
+        .option("proxy", json!({ "/upstream": upstream_url }))
+        .build()
+        .unwrap();
+    visit(&root, config, port, async {
+        let mut connection = connect(port).await;
+        connection
+            .write_all(
+                b"POST /upstream/echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let (head, body) = read_response(&mut connection).await;
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert_eq!(header(&head, "transfer-encoding"), None);
+        assert_eq!(body, "hello world");
+        let upstream_head = echo.await.unwrap();
+        assert!(upstream_head.starts_with("POST /echo "), "{}", upstream_head);
+        assert_eq!(header(&upstream_head, "transfer-encoding"), None);
+
+        // the whole body was read, the next request of the connection is answered as well
+        connection
+            .write_all(b"GET /letters.txt HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let (head, body) = read_response(&mut connection).await;
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert_eq!(body, ALPHABET);
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn passes_websockets_on_to_the_upstream() {
+    let root = workspace("websocket", &[]);
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_port = upstream.local_addr().unwrap().port();
+    // accepts one upgrade and echoes what is sent over it
+    tokio::spawn(async move {
+        let (stream, _) = upstream.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let head = read_head(&mut stream).await;
+        assert!(head.starts_with("GET /socket "), "{}", head);
+        assert_eq!(header(&head, "upgrade"), Some("websocket"));
+        stream
+            .write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n")
+            .await
+            .unwrap();
+        let mut message = [0; 4];
+        stream.read_exact(&mut message).await.unwrap();
+        stream.write_all(&message).await.unwrap();
+    });
+    let port = free_port();
+    let upstream_url = format!("http://127.0.0.1:{}", upstream_port);
+    let config = Config::builder()
+        .start_port(port)
+        .option("proxy", json!({ "/ws": upstream_url }))
+        .build()
+        .unwrap();
+    visit(&root, config, port, async {
+        let upgrade = |origin: &str| {
+            format!(
+                "GET /ws/socket HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nOrigin: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+                port, origin
+            )
+        };
+        // pages of other sites can't open it
+        let mut foreign = connect(port).await;
+        foreign
+            .write_all(upgrade("http://example.com").as_bytes())
+            .await
+            .unwrap();
+        let head = read_head(&mut foreign).await;
+        assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+
+        let mut socket = connect(port).await;
+        let origin = format!("http://127.0.0.1:{}", port);
+        socket.write_all(upgrade(&origin).as_bytes()).await.unwrap();
+        let head = read_head(&mut socket).await;
+        assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+        socket.write_all(b"ping").await.unwrap();
+        let mut echoed = [0; 4];
+        socket.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"ping");
+    })
+    .await;
+}
@@ -0,0 +1,145 @@
+//! Peak memory of sending a large asset, streamed from disk or read into memory by the server.
+//!
+//! Run with `cargo test --release --test stream -- --ignored --nocapture`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use live_server_lsp::{serve_on, Config};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpStream;
+use tower_lsp::lsp_types::Url;
+
+/// Size of the asset, like a texture atlas or the audio of a game
+const SIZE: usize = 64 * 1024 * 1024;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts the bytes allocated by the whole test process
+struct Counting;
+
+// SAFETY: every call is passed on to the system allocator unchanged
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+async fn send(stream: &mut DuplexStream, message: Value) {
+    let body = message.to_string();
+    let frame = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    stream.write_all(frame.as_bytes()).await.unwrap();
+}
+
+fn free_port() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Bytes allocated on top of what was in use while a server with `stream_threshold` sends `big.bin` of `root`
+async fn peak_while_sending(root: &Path, threshold: u64) -> usize {
+    let port = free_port();
+    let (mut requests, server_input) = tokio::io::duplex(64 * 1024);
+    let (server_output, mut client_output) = tokio::io::duplex(64 * 1024);
+    let config = Config::builder()
+        .start_port(port)
+        .option("stream_threshold", threshold)
+        .build()
+        .unwrap();
+    let client = async {
+        let folder = json!({ "uri": Url::from_file_path(root).unwrap(), "name": "assets" });
+        send(
+            &mut requests,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "capabilities": {}, "workspaceFolders": [folder] },
+            }),
+        )
+        .await;
+        send(
+            &mut requests,
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        )
+        .await;
+        let mut page = None;
+        for _ in 0..100 {
+            if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+                page = Some(stream);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let mut page = page.expect("the server didn't start");
+        let mut buffer = vec![0; 64 * 1024];
+        let baseline = CURRENT.load(Ordering::Relaxed);
+        PEAK.store(baseline, Ordering::Relaxed);
+        page.write_all(b"GET /big.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = 0;
+        loop {
+            match page.read(&mut buffer).await.unwrap() {
+                0 => break,
+                read => received += read,
+            }
+        }
+        assert!(received > SIZE, "only {} bytes were received", received);
+        PEAK.load(Ordering::Relaxed) - baseline
+    };
+    // the log messages of the server are read and dropped, so it never waits on a full pipe
+    let drain = async {
+        let mut sink = [0; 4096];
+        while client_output
+            .read(&mut sink)
+            .await
+            .is_ok_and(|read| read > 0)
+        {}
+    };
+    tokio::select! {
+        _ = serve_on(config, server_input, server_output) => panic!("the server stopped"),
+        _ = drain => panic!("the server closed its output"),
+        peak = client => peak,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "benchmark, run it with --release --ignored --nocapture"]
+async fn streams_large_assets_without_buffering_them() {
+    let root = std::env::temp_dir().join(format!("live-server-stream-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("big.bin"), vec![7; SIZE]).unwrap();
+
+    let buffered = peak_while_sending(&root, u64::MAX).await;
+    let streamed = peak_while_sending(&root, 1024 * 1024).await;
+    std::fs::remove_dir_all(&root).unwrap();
+    println!(
+        "peak while sending {} MiB: {} KiB streamed, {} KiB read into memory",
+        SIZE >> 20,
+        streamed >> 10,
+        buffered >> 10
+    );
+    assert!(
+        buffered >= SIZE,
+        "reading the asset into memory didn't show in the peak"
+    );
+    assert!(streamed < SIZE / 4);
+}